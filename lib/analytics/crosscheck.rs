@@ -0,0 +1,164 @@
+use super::{Greeks, OptionModel};
+use crate::exchanges::OptionSummary;
+use crate::trading::{Instrument, OrderBook};
+use chrono::{DateTime, NaiveDateTime};
+
+/// how far our Black-Scholes numbers may be from an exchange's before we flag them
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct ModelTolerance {
+    pub iv: f64,       // absolute, in vol (0.02 is 2 vol points)
+    pub delta: f64,    // absolute
+    pub relative: f64, // gamma, vega and theta, as a fraction of the exchange's value
+}
+
+impl Default for ModelTolerance {
+    fn default() -> Self {
+        Self {
+            iv: 0.02,
+            delta: 0.05,
+            relative: 0.25,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum ModelField {
+    ImpliedVol,
+    Delta,
+    Gamma,
+    Vega,
+    Theta,
+}
+
+/// one number where our model and the exchange disagree beyond tolerance
+#[derive(PartialEq, Debug, Clone)]
+pub struct ModelDisagreement {
+    pub exchange: String,
+    pub instrument: Instrument,
+    pub field: ModelField,
+    pub ours: f64,
+    pub theirs: f64,
+}
+
+impl ModelDisagreement {
+    pub fn difference(&self) -> f64 {
+        self.ours - self.theirs
+    }
+}
+
+/// Recomputes an exchange's summary with our model: the iv implied by its mark price and the
+/// greeks at its mark iv, both at the exchange's forward and timestamp so only the models differ
+pub fn compare_with_exchange(
+    summary: &OptionSummary,
+    rate: f64,
+    tolerance: &ModelTolerance,
+) -> Vec<ModelDisagreement> {
+    let Some(at) = DateTime::from_timestamp_millis(summary.timestamp as i64) else {
+        return Vec::new();
+    };
+    let now: NaiveDateTime = at.naive_utc();
+    let model = OptionModel::new(summary.underlying_price, rate);
+    let instrument = &summary.instrument;
+    let mut disagreements = Vec::new();
+    let mut flag = |field: ModelField, ours: f64, theirs: f64, limit: f64| {
+        if (ours - theirs).abs() > limit {
+            disagreements.push(ModelDisagreement {
+                exchange: summary.exchange.clone(),
+                instrument: instrument.clone(),
+                field,
+                ours,
+                theirs,
+            });
+        }
+    };
+
+    if let Some(iv) = summary
+        .mark_price
+        .and_then(|premium| model.implied_volatility(instrument, premium, now))
+    {
+        flag(ModelField::ImpliedVol, iv, summary.mark_iv, tolerance.iv);
+    }
+
+    let ours: Greeks = model.greeks(instrument, summary.mark_iv, now);
+    let theirs = summary.greeks;
+    let relative = |value: f64| tolerance.relative * value.abs();
+    flag(ModelField::Delta, ours.delta, theirs.delta, tolerance.delta);
+    flag(
+        ModelField::Gamma,
+        ours.gamma,
+        theirs.gamma,
+        relative(theirs.gamma),
+    );
+    flag(
+        ModelField::Vega,
+        ours.vega,
+        theirs.vega,
+        relative(theirs.vega),
+    );
+    flag(
+        ModelField::Theta,
+        ours.theta,
+        theirs.theta,
+        relative(theirs.theta),
+    );
+    disagreements
+}
+
+/// compares every exchange summary held by a book, taking the mark price from the
+/// mark price feed when the summary doesn't carry one
+pub fn check_exchange_models(
+    book: &OrderBook<'_>,
+    rate: f64,
+    tolerance: &ModelTolerance,
+) -> Vec<ModelDisagreement> {
+    book.option_summaries
+        .values()
+        .flat_map(|summary| {
+            let mut summary = summary.clone();
+            if summary.mark_price.is_none() {
+                summary.mark_price = book.mark_price(&summary.instrument).map(f64::from);
+            }
+            compare_with_exchange(&summary, rate, tolerance)
+        })
+        .collect()
+}
+
+#[test]
+fn flags_only_the_numbers_that_disagree() {
+    use crate::trading::InstrumentType;
+    use chrono::NaiveDate;
+    let instrument = Instrument {
+        asset: "BTC".to_owned(),
+        strike_price: 66000,
+        expiration_date: NaiveDate::from_ymd_opt(2024, 6, 28).unwrap(),
+        instrument_type: InstrumentType::Call,
+    };
+    let now = NaiveDate::from_ymd_opt(2024, 5, 10)
+        .unwrap()
+        .and_hms_opt(8, 0, 0)
+        .unwrap();
+    let model = OptionModel::new(63000.0, 0.0);
+    let mut summary = OptionSummary {
+        exchange: "deribit".to_owned(),
+        instrument: instrument.clone(),
+        mark_iv: 0.55,
+        bid_iv: None,
+        ask_iv: None,
+        mark_price: Some(model.price(&instrument, 0.55, now)),
+        underlying_price: 63000.0,
+        greeks: model.greeks(&instrument, 0.55, now),
+        timestamp: now.and_utc().timestamp_millis() as u128,
+    };
+    let tolerance = ModelTolerance::default();
+    assert!(compare_with_exchange(&summary, 0.0, &tolerance).is_empty());
+
+    summary.greeks.vega *= 2.0;
+    summary.mark_iv = 0.6;
+    let fields: Vec<_> = compare_with_exchange(&summary, 0.0, &tolerance)
+        .into_iter()
+        .map(|disagreement| disagreement.field)
+        .collect();
+    assert!(fields.contains(&ModelField::ImpliedVol));
+    assert!(fields.contains(&ModelField::Vega));
+    assert!(!fields.contains(&ModelField::Delta));
+}
@@ -0,0 +1,8 @@
+mod pricing;
+pub use pricing::*;
+mod surface;
+pub use surface::*;
+mod crosscheck;
+pub use crosscheck::*;
+mod portfolio;
+pub use portfolio::*;
@@ -0,0 +1,351 @@
+use super::{Greeks, OptionModel};
+use crate::trading::{Instrument, OrderBook, PaperAccount, VenueKey};
+use chrono::{Duration, NaiveDateTime};
+
+/// an option position priced at its own vol, quantity in underlying units (negative when short)
+#[derive(PartialEq, Debug, Clone)]
+pub struct PortfolioPosition {
+    pub exchange: String,
+    pub instrument: Instrument,
+    pub quantity: f64,
+    pub vol: f64,
+}
+
+/// one point of a scenario grid and the pnl of the portfolio there
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct ScenarioResult {
+    pub spot_shift: f64, // fraction of the underlying price, 0.05 is +5%
+    pub vol_shift: f64,  // vol points, 2.0 is +0.02 on every vol
+    pub days: f64,       // time passed
+    pub pnl: f64,        // in the underlying, like the premiums
+    pub pnl_usd: f64,    // converted at the shifted underlying price
+}
+
+/// the shifts a scenario grid runs through, every combination is priced
+#[derive(PartialEq, Debug, Clone)]
+pub struct ScenarioGrid {
+    pub spot_shifts: Vec<f64>,
+    pub vol_shifts: Vec<f64>,
+    pub days: Vec<f64>,
+}
+
+impl Default for ScenarioGrid {
+    fn default() -> Self {
+        Self::symmetric(0.1, 2, 5.0, 1, vec![0.0, 1.0, 7.0])
+    }
+}
+
+impl ScenarioGrid {
+    /// spot from -`max_spot` to +`max_spot` and vol from -`max_vol` to +`max_vol` points,
+    /// in `steps` equal steps on each side of zero
+    pub fn symmetric(
+        max_spot: f64,
+        spot_steps: usize,
+        max_vol: f64,
+        vol_steps: usize,
+        days: Vec<f64>,
+    ) -> Self {
+        let shifts = |max: f64, steps: usize| -> Vec<f64> {
+            let steps = steps as i64;
+            (-steps..=steps)
+                .map(|step| max * step as f64 / steps.max(1) as f64)
+                .collect()
+        };
+        Self {
+            spot_shifts: shifts(max_spot, spot_steps),
+            vol_shifts: shifts(max_vol, vol_steps),
+            days,
+        }
+    }
+}
+
+/// Option positions across venues priced with one model and one underlying price, so their
+/// greeks add up in common units whatever each venue reports: delta in the underlying, gamma
+/// per unit move of the underlying price, vega per vol point and theta per day in the premium
+/// currency (the underlying, both venues are coin-margined)
+#[derive(PartialEq, Debug, Clone)]
+pub struct Portfolio {
+    pub positions: Vec<PortfolioPosition>,
+    pub underlying_price: f64,
+    pub rate: f64,
+}
+
+impl Portfolio {
+    pub fn new(underlying_price: f64, rate: f64) -> Self {
+        Self {
+            positions: Vec::new(),
+            underlying_price,
+            rate,
+        }
+    }
+
+    pub fn add_position(
+        &mut self,
+        exchange: &str,
+        instrument: &Instrument,
+        quantity: f64,
+        vol: f64,
+    ) {
+        self.positions.push(PortfolioPosition {
+            exchange: exchange.to_owned(),
+            instrument: instrument.clone(),
+            quantity,
+            vol,
+        });
+    }
+
+    /// The positions of a paper account, one per venue they are held on. Each is priced at the
+    /// mark iv of its own venue or, without a summary there, of the first venue with one. The
+    /// underlying price is the first index price, else that of the most recent option summary
+    /// (the highest of equally recent ones). Returns the positions left out for lack of a vol,
+    /// None without any underlying price
+    pub fn from_account(
+        account: &PaperAccount,
+        venues: &[&OrderBook],
+        rate: f64,
+    ) -> Option<(Self, Vec<VenueKey>)> {
+        let underlying_price = venues
+            .iter()
+            .find_map(|venue| venue.index_price().map(|price| price as f64))
+            .or_else(|| {
+                venues
+                    .iter()
+                    .flat_map(|venue| venue.option_summaries.values())
+                    .max_by(|a, b| {
+                        a.timestamp
+                            .cmp(&b.timestamp)
+                            .then(a.underlying_price.total_cmp(&b.underlying_price))
+                    })
+                    .map(|summary| summary.underlying_price)
+            })?;
+        let mut portfolio = Self::new(underlying_price, rate);
+        let mut unpriced = Vec::new();
+        for ((exchange, instrument), position) in account.positions.iter() {
+            if position.is_flat() {
+                continue;
+            }
+            let own = venues.iter().filter(|venue| venue.exchange == exchange);
+            let summary = own
+                .chain(venues.iter())
+                .find_map(|venue| venue.option_summary(instrument));
+            match summary {
+                Some(summary) => portfolio.add_position(
+                    exchange,
+                    instrument,
+                    position.quantity as f64,
+                    summary.mark_iv,
+                ),
+                None => unpriced.push((exchange.clone(), instrument.clone())),
+            }
+        }
+        Some((portfolio, unpriced))
+    }
+
+    fn model(&self) -> OptionModel {
+        OptionModel::new(self.underlying_price, self.rate)
+    }
+
+    /// premium value of all positions, in the underlying
+    pub fn value(&self, now: NaiveDateTime) -> f64 {
+        let model = self.model();
+        self.positions
+            .iter()
+            .map(|position| {
+                position.quantity * model.price(&position.instrument, position.vol, now)
+            })
+            .sum()
+    }
+
+    pub fn greeks(&self, now: NaiveDateTime) -> Greeks {
+        let model = self.model();
+        let mut total = Greeks::default();
+        for position in self.positions.iter() {
+            total += model
+                .greeks(&position.instrument, position.vol, now)
+                .scaled(position.quantity);
+        }
+        total
+    }
+
+    /// greeks per venue, they add up to `greeks`
+    pub fn greeks_by_exchange(&self, now: NaiveDateTime) -> Vec<(String, Greeks)> {
+        let model = self.model();
+        let mut venues: Vec<(String, Greeks)> = Vec::new();
+        for position in self.positions.iter() {
+            let greeks = model
+                .greeks(&position.instrument, position.vol, now)
+                .scaled(position.quantity);
+            match venues
+                .iter_mut()
+                .find(|(exchange, _)| *exchange == position.exchange)
+            {
+                Some((_, total)) => *total += greeks,
+                None => venues.push((position.exchange.clone(), greeks)),
+            }
+        }
+        venues
+    }
+
+    /// revalues every position with the underlying, the vols and the clock shifted
+    pub fn scenario(
+        &self,
+        spot_shift: f64,
+        vol_shift: f64,
+        days: f64,
+        now: NaiveDateTime,
+    ) -> ScenarioResult {
+        let shifted = Self {
+            underlying_price: self.underlying_price * (1.0 + spot_shift),
+            positions: self
+                .positions
+                .iter()
+                .map(|position| PortfolioPosition {
+                    vol: (position.vol + vol_shift / 100.0).max(0.0),
+                    ..position.clone()
+                })
+                .collect(),
+            ..self.clone()
+        };
+        let later = now + Duration::seconds((days * 24.0 * 60.0 * 60.0) as i64);
+        let pnl = shifted.value(later) - self.value(now);
+        ScenarioResult {
+            spot_shift,
+            vol_shift,
+            days,
+            pnl,
+            pnl_usd: pnl * shifted.underlying_price,
+        }
+    }
+
+    /// every combination of the grid's shifts, spot first then vol then time
+    pub fn scenario_grid(&self, grid: &ScenarioGrid, now: NaiveDateTime) -> Vec<ScenarioResult> {
+        let mut results = Vec::new();
+        for spot_shift in grid.spot_shifts.iter() {
+            for vol_shift in grid.vol_shifts.iter() {
+                for days in grid.days.iter() {
+                    results.push(self.scenario(*spot_shift, *vol_shift, *days, now));
+                }
+            }
+        }
+        results
+    }
+}
+
+#[test]
+fn portfolio_greeks_and_scenarios() -> anyhow::Result<()> {
+    use crate::exchanges::ExchangeType;
+    use chrono::NaiveDate;
+
+    let now = NaiveDate::from_ymd_opt(2024, 4, 10)
+        .unwrap()
+        .and_hms_opt(8, 0, 0)
+        .unwrap();
+    let call = Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+    let okex_call = Instrument::from_exchange_string("BTC-USD-240510-66000-C", ExchangeType::Okex)?;
+    let put = Instrument::from_exchange_string("BTC-10MAY24-60000-P", ExchangeType::Delibris)?;
+    let model = OptionModel::new(63_000.0, 0.0);
+
+    let mut portfolio = Portfolio::new(63_000.0, 0.0);
+    portfolio.add_position("deribit", &call, 1.0, 0.55);
+    portfolio.add_position("okex", &okex_call, -1.0, 0.55);
+    // the same option long on one venue and short on the other has no risk
+    let flat = portfolio.greeks(now);
+    assert!(flat.delta.abs() < 1e-12 && flat.vega.abs() < 1e-12);
+    assert!(portfolio.scenario(0.1, 5.0, 7.0, now).pnl.abs() < 1e-12);
+
+    portfolio.add_position("deribit", &put, 0.5, 0.6);
+    let greeks = portfolio.greeks(now);
+    let put_greeks = model.greeks(&put, 0.6, now).scaled(0.5);
+    assert!((greeks.delta - put_greeks.delta).abs() < 1e-12);
+    assert!((greeks.gamma - put_greeks.gamma).abs() < 1e-12);
+    let by_exchange = portfolio.greeks_by_exchange(now);
+    assert_eq!(by_exchange.len(), 2);
+    let okex = &by_exchange[1].1;
+    assert!((by_exchange[0].1.delta + okex.delta - greeks.delta).abs() < 1e-12);
+
+    // small shifts are explained by the greeks, in the same units
+    let up = portfolio.scenario(0.0001, 0.0, 0.0, now);
+    assert!((up.pnl - greeks.delta * 0.0001).abs() < 1e-6);
+    let vol_up = portfolio.scenario(0.0, 1.0, 0.0, now);
+    assert!((vol_up.pnl - greeks.vega).abs() / greeks.vega.abs() < 0.01);
+    let day = portfolio.scenario(0.0, 0.0, 1.0, now);
+    assert!((day.pnl - greeks.theta).abs() / greeks.theta.abs() < 0.05);
+    assert!((day.pnl_usd - day.pnl * 63_000.0).abs() < 1e-9);
+
+    let grid = ScenarioGrid::default();
+    assert_eq!(grid.spot_shifts, vec![-0.1, -0.05, 0.0, 0.05, 0.1]);
+    assert_eq!(grid.vol_shifts, vec![-5.0, 0.0, 5.0]);
+    let results = portfolio.scenario_grid(&grid, now);
+    assert_eq!(results.len(), 5 * 3 * 3);
+    let unchanged = results
+        .iter()
+        .find(|result| result.spot_shift == 0.0 && result.vol_shift == 0.0 && result.days == 0.0)
+        .unwrap();
+    assert_eq!(unchanged.pnl, 0.0);
+    // a long put loses as spot rallies
+    let rally = portfolio.scenario(0.1, 0.0, 0.0, now);
+    assert!(rally.pnl < 0.0);
+    Ok(())
+}
+
+#[test]
+fn portfolio_from_a_paper_account() -> anyhow::Result<()> {
+    use crate::exchanges::{ExchangeType, OptionSummary};
+    use crate::trading::Position;
+    use crate::utils::system_clock;
+
+    let call = Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+    let put = Instrument::from_exchange_string("BTC-10MAY24-60000-P", ExchangeType::Delibris)?;
+    let mut account = PaperAccount::new("paper", system_clock());
+    for (exchange, instrument, quantity) in [
+        ("deribit", &call, 2.0),
+        ("okex", &call, -1.0),
+        ("deribit", &put, -1.0),
+    ] {
+        account.positions.insert(
+            (exchange.to_owned(), instrument.clone()),
+            Position {
+                quantity,
+                ..Default::default()
+            },
+        );
+    }
+    let summary = |instrument: &Instrument, underlying_price: f64, timestamp: u128| OptionSummary {
+        exchange: "okex".to_owned(),
+        instrument: instrument.clone(),
+        mark_iv: 0.55,
+        bid_iv: None,
+        ask_iv: None,
+        mark_price: None,
+        underlying_price,
+        greeks: Greeks::default(),
+        timestamp,
+    };
+    let mut okex = OrderBook::new("okex");
+    let okex_call = Instrument::from_exchange_string("BTC-USD-240510-66000-C", ExchangeType::Okex)?;
+    let okex_june = Instrument::from_exchange_string("BTC-USD-240628-66000-C", ExchangeType::Okex)?;
+    okex.apply_option_summary(summary(&okex_call, 63_100.0, 2_000));
+    okex.apply_option_summary(summary(&okex_june, 63_900.0, 1_000));
+    let deribit = OrderBook::new("deribit");
+
+    let (mut portfolio, unpriced) =
+        Portfolio::from_account(&account, &[&deribit, &okex], 0.0).unwrap();
+    assert_eq!(unpriced, vec![("deribit".to_owned(), put)]);
+    // the most recent summary's forward, not whichever comes first
+    assert_eq!(portfolio.underlying_price, 63_100.0);
+    // each venue keeps its own position, priced at the only vol there is
+    portfolio
+        .positions
+        .sort_by(|a, b| a.exchange.cmp(&b.exchange));
+    assert_eq!(portfolio.positions.len(), 2);
+    assert_eq!(portfolio.positions[0].exchange, "deribit");
+    assert_eq!(portfolio.positions[0].quantity, 2.0);
+    assert_eq!(portfolio.positions[1].exchange, "okex");
+    assert_eq!(portfolio.positions[1].quantity, -1.0);
+    assert!(portfolio
+        .positions
+        .iter()
+        .all(|position| position.vol == 0.55));
+    assert!(Portfolio::from_account(&account, &[&deribit], 0.0).is_none());
+    Ok(())
+}
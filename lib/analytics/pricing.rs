@@ -0,0 +1,313 @@
+use crate::trading::{Instrument, InstrumentType, PriceColumns};
+use chrono::{NaiveDateTime, NaiveTime};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+const MIN_VOL: f64 = 1e-4;
+const MAX_VOL: f64 = 5.0;
+
+/// standard normal cumulative distribution
+pub fn norm_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2))
+}
+
+pub fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+/// Abramowitz and Stegun 7.1.26, max error 1.5e-7
+fn erf(x: f64) -> f64 {
+    let sign = x.signum();
+    let x = x.abs();
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    sign * (1.0 - poly * (-x * x).exp())
+}
+
+/// Sensitivities of an option's price. For inverse options delta is premium adjusted (the
+/// exposure in the coin) while gamma stays the Black-Scholes one, as both venues publish them
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub struct Greeks {
+    pub delta: f64, // underlying units per unit held, premium adjusted when inverse
+    pub gamma: f64, // change of the unadjusted delta per 1 USD move of the underlying
+    pub vega: f64,  // per 1 vol point (0.01), in the premium currency
+    pub theta: f64, // per calendar day, in the premium currency
+}
+
+impl Greeks {
+    /// the greeks of `quantity` units, e.g. of a position
+    pub fn scaled(&self, quantity: f64) -> Self {
+        Self {
+            delta: self.delta * quantity,
+            gamma: self.gamma * quantity,
+            vega: self.vega * quantity,
+            theta: self.theta * quantity,
+        }
+    }
+}
+
+impl std::ops::AddAssign for Greeks {
+    fn add_assign(&mut self, other: Self) {
+        self.delta += other.delta;
+        self.gamma += other.gamma;
+        self.vega += other.vega;
+        self.theta += other.theta;
+    }
+}
+
+/// implied volatilities solved from the best bid, best ask and midprice of a book
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub struct QuoteVols {
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+    pub mid: Option<f64>,
+}
+
+/// Black-Scholes on `underlying_price` (spot) with a flat rate and no carry. Given a forward
+/// and a zero rate, as when checking the venues' summaries, it reduces to Black-76.
+/// Both Deribit and OKX quote BTC options in BTC (inverse, coin-margined): with `inverse` set,
+/// premiums are read and returned in the underlying and converted through `underlying_price`
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct OptionModel {
+    pub underlying_price: f64,
+    pub rate: f64,
+    pub inverse: bool,
+}
+
+impl OptionModel {
+    pub fn new(underlying_price: f64, rate: f64) -> Self {
+        Self {
+            underlying_price,
+            rate,
+            inverse: true,
+        }
+    }
+
+    pub fn linear(underlying_price: f64, rate: f64) -> Self {
+        Self {
+            inverse: false,
+            ..Self::new(underlying_price, rate)
+        }
+    }
+
+    /// years until expiry, options on both venues expire at 08:00 UTC
+    pub fn time_to_expiry(instrument: &Instrument, now: NaiveDateTime) -> f64 {
+        let expiry = instrument
+            .expiration_date
+            .and_time(NaiveTime::from_hms_opt(8, 0, 0).unwrap());
+        ((expiry - now).num_seconds() as f64 / SECONDS_PER_YEAR).max(0.0)
+    }
+
+    fn d1_d2(&self, strike: f64, t: f64, vol: f64) -> (f64, f64) {
+        let sqrt_t = t.sqrt();
+        let d1 = ((self.underlying_price / strike).ln() + (self.rate + 0.5 * vol * vol) * t)
+            / (vol * sqrt_t);
+        (d1, d1 - vol * sqrt_t)
+    }
+
+    /// price in USD (the strike currency)
+    fn usd_price(&self, kind: &InstrumentType, strike: f64, t: f64, vol: f64) -> f64 {
+        let spot = self.underlying_price;
+        let discount = (-self.rate * t).exp();
+        if t <= 0.0 || vol <= 0.0 {
+            return match kind {
+                InstrumentType::Call => (spot - strike).max(0.0),
+                InstrumentType::Pull => (strike - spot).max(0.0),
+            };
+        }
+        let (d1, d2) = self.d1_d2(strike, t, vol);
+        match kind {
+            InstrumentType::Call => spot * norm_cdf(d1) - strike * discount * norm_cdf(d2),
+            InstrumentType::Pull => strike * discount * norm_cdf(-d2) - spot * norm_cdf(-d1),
+        }
+    }
+
+    fn premium_from_usd(&self, usd: f64) -> f64 {
+        if self.inverse {
+            usd / self.underlying_price
+        } else {
+            usd
+        }
+    }
+
+    fn usd_from_premium(&self, premium: f64) -> f64 {
+        if self.inverse {
+            premium * self.underlying_price
+        } else {
+            premium
+        }
+    }
+
+    /// theoretical premium, in BTC when inverse
+    pub fn price(&self, instrument: &Instrument, vol: f64, now: NaiveDateTime) -> f64 {
+        let t = Self::time_to_expiry(instrument, now);
+        let strike = instrument.strike_price as f64;
+        self.premium_from_usd(self.usd_price(&instrument.instrument_type, strike, t, vol))
+    }
+
+    /// Greeks in the premium currency. For inverse options delta is the exposure in the
+    /// underlying coin, which is the Black-Scholes delta less the premium held in that coin.
+    /// Gamma is left unadjusted, see `Greeks`
+    pub fn greeks(&self, instrument: &Instrument, vol: f64, now: NaiveDateTime) -> Greeks {
+        let t = Self::time_to_expiry(instrument, now);
+        let strike = instrument.strike_price as f64;
+        let spot = self.underlying_price;
+        let kind = &instrument.instrument_type;
+        if t <= 0.0 || vol <= 0.0 {
+            return Greeks::default();
+        }
+        let (d1, d2) = self.d1_d2(strike, t, vol);
+        let sqrt_t = t.sqrt();
+        let discount = (-self.rate * t).exp();
+        let (delta, rate_theta) = match kind {
+            InstrumentType::Call => (norm_cdf(d1), -self.rate * strike * discount * norm_cdf(d2)),
+            InstrumentType::Pull => (
+                norm_cdf(d1) - 1.0,
+                self.rate * strike * discount * norm_cdf(-d2),
+            ),
+        };
+        let gamma = norm_pdf(d1) / (spot * vol * sqrt_t);
+        let vega = spot * norm_pdf(d1) * sqrt_t / 100.0;
+        let theta = (-spot * norm_pdf(d1) * vol / (2.0 * sqrt_t) + rate_theta) / 365.0;
+
+        let mut greeks = Greeks {
+            delta,
+            gamma,
+            vega: self.premium_from_usd(vega),
+            theta: self.premium_from_usd(theta),
+        };
+        if self.inverse {
+            greeks.delta -= self.premium_from_usd(self.usd_price(kind, strike, t, vol));
+        }
+        greeks
+    }
+
+    /// Solves the volatility that reproduces a premium (in BTC when inverse).
+    /// None when the premium is outside the no-arbitrage bounds or the option has expired
+    pub fn implied_volatility(
+        &self,
+        instrument: &Instrument,
+        premium: f64,
+        now: NaiveDateTime,
+    ) -> Option<f64> {
+        let t = Self::time_to_expiry(instrument, now);
+        let strike = instrument.strike_price as f64;
+        let kind = &instrument.instrument_type;
+        let target = self.usd_from_premium(premium);
+        if t <= 0.0 || !target.is_finite() {
+            return None;
+        }
+        let (low_price, high_price) = (
+            self.usd_price(kind, strike, t, MIN_VOL),
+            self.usd_price(kind, strike, t, MAX_VOL),
+        );
+        if target < low_price || target > high_price {
+            return None;
+        }
+
+        // Newton from a reasonable guess, falling back to bisection when it misbehaves
+        let (mut low, mut high) = (MIN_VOL, MAX_VOL);
+        let mut vol = 0.5;
+        for _ in 0..100 {
+            let diff = self.usd_price(kind, strike, t, vol) - target;
+            if diff.abs() < 1e-8 * self.underlying_price.max(1.0) {
+                return Some(vol);
+            }
+            if diff > 0.0 {
+                high = vol;
+            } else {
+                low = vol;
+            }
+            let (d1, _) = self.d1_d2(strike, t, vol);
+            let vega = self.underlying_price * norm_pdf(d1) * t.sqrt();
+            let newton = vol - diff / vega;
+            vol = if vega > 1e-12 && newton > low && newton < high {
+                newton
+            } else {
+                0.5 * (low + high)
+            };
+        }
+        Some(vol)
+    }
+
+    /// implied volatilities of the best bid, best ask and midprice of a book
+    pub fn implied_vols(
+        &self,
+        instrument: &Instrument,
+        columns: &PriceColumns,
+        now: NaiveDateTime,
+    ) -> QuoteVols {
+        let best_bid = columns
+            .bids
+            .last_key_value()
+            .map(|(price, _)| price.into_inner());
+        let best_ask = columns
+            .asks
+            .first_key_value()
+            .map(|(price, _)| price.into_inner());
+        let mid = match (best_bid, best_ask) {
+            (Some(_), Some(_)) => Some(columns.midprice),
+            _ => None,
+        };
+        let solve = |premium: Option<f32>| {
+            premium.and_then(|p| self.implied_volatility(instrument, p as f64, now))
+        };
+        QuoteVols {
+            bid: solve(best_bid),
+            ask: solve(best_ask),
+            mid: solve(mid),
+        }
+    }
+}
+
+#[test]
+fn black_scholes_prices_greeks_and_implied_vol() {
+    use chrono::NaiveDate;
+    // one year to expiry
+    let now = NaiveDate::from_ymd_opt(2024, 4, 27)
+        .unwrap()
+        .and_hms_opt(8, 0, 0)
+        .unwrap();
+    let call = Instrument {
+        asset: "BTC".to_owned(),
+        strike_price: 100,
+        expiration_date: NaiveDate::from_ymd_opt(2025, 4, 27).unwrap(),
+        instrument_type: InstrumentType::Call,
+    };
+    let put = Instrument {
+        instrument_type: InstrumentType::Pull,
+        ..call.clone()
+    };
+
+    let linear = OptionModel::linear(100.0, 0.05);
+    assert!((linear.price(&call, 0.2, now) - 10.4506).abs() < 1e-3);
+    assert!((linear.price(&put, 0.2, now) - 5.5735).abs() < 1e-3);
+    let greeks = linear.greeks(&call, 0.2, now);
+    assert!((greeks.delta - 0.6368).abs() < 1e-3);
+    assert!((greeks.gamma - 0.01876).abs() < 1e-4);
+    assert!((greeks.vega - 0.3752).abs() < 1e-3);
+
+    // inverse premiums are quoted in the underlying
+    let inverse = OptionModel::new(100.0, 0.05);
+    let premium = inverse.price(&call, 0.2, now);
+    assert!((premium - 0.104506).abs() < 1e-5);
+    let iv = inverse.implied_volatility(&call, premium, now).unwrap();
+    assert!((iv - 0.2).abs() < 1e-5);
+    assert!((inverse.greeks(&call, 0.2, now).delta - (0.6368 - premium)).abs() < 1e-3);
+    let put_premium = inverse.price(&put, 0.2, now);
+    assert!((put_premium - 0.055735).abs() < 1e-5);
+    assert!((inverse.greeks(&put, 0.2, now).delta - (-0.3632 - put_premium)).abs() < 1e-3);
+    // the premium adjusted delta is the coin value change of the coin premium
+    for option in [&call, &put] {
+        let bumped = |spot: f64| OptionModel::new(spot, 0.05).price(option, 0.2, now);
+        let numeric = 100.0 * (bumped(100.01) - bumped(99.99)) / 0.02;
+        assert!((inverse.greeks(option, 0.2, now).delta - numeric).abs() < 1e-4);
+        // gamma matches the linear (unadjusted) one
+        let linear_gamma = linear.greeks(option, 0.2, now).gamma;
+        assert!((inverse.greeks(option, 0.2, now).gamma - linear_gamma).abs() < 1e-12);
+    }
+
+    // below intrinsic there is no implied volatility
+    assert!(inverse.implied_volatility(&call, 0.01, now).is_none());
+}
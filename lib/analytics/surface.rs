@@ -0,0 +1,459 @@
+use super::OptionModel;
+use crate::trading::{ForwardCurve, InstrumentType, OrderBook};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// implied volatility of one option on the surface
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct VolPoint {
+    pub strike: i64,
+    pub expiry: NaiveDate,
+    pub log_moneyness: f64, // ln(K / F)
+    pub time: f64,          // years to expiry
+    pub iv: f64,
+}
+
+impl VolPoint {
+    pub fn total_variance(&self) -> f64 {
+        self.iv * self.iv * self.time
+    }
+}
+
+/// raw SVI parameterisation of total implied variance,
+/// `w(k) = a + b * (rho * (k - m) + sqrt((k - m)^2 + sigma^2))`
+#[derive(PartialEq, Debug, Clone, Copy, Serialize)]
+pub struct SviParams {
+    pub a: f64,
+    pub b: f64,
+    pub rho: f64,
+    pub m: f64,
+    pub sigma: f64,
+}
+
+impl SviParams {
+    pub fn total_variance(&self, k: f64) -> f64 {
+        let x = k - self.m;
+        self.a + self.b * (self.rho * x + (x * x + self.sigma * self.sigma).sqrt())
+    }
+
+    fn first_derivative(&self, k: f64) -> f64 {
+        let x = k - self.m;
+        self.b * (self.rho + x / (x * x + self.sigma * self.sigma).sqrt())
+    }
+
+    fn second_derivative(&self, k: f64) -> f64 {
+        let x = k - self.m;
+        let s2 = self.sigma * self.sigma;
+        self.b * s2 / (x * x + s2).powf(1.5)
+    }
+
+    /// Gatheral's density condition, negative values mean butterfly arbitrage at k
+    pub fn butterfly_density(&self, k: f64) -> f64 {
+        let w = self.total_variance(k);
+        let w1 = self.first_derivative(k);
+        let w2 = self.second_derivative(k);
+        (1.0 - k * w1 / (2.0 * w)).powi(2) - w1 * w1 / 4.0 * (1.0 / w + 0.25) + w2 / 2.0
+    }
+
+    /// Quasi-explicit fit: for fixed (m, sigma) the rest is linear least squares,
+    /// (m, sigma) are searched on a grid that is refined around the best candidate
+    pub fn fit(points: &[(f64, f64)]) -> Option<SviParams> {
+        if points.len() < 5 {
+            return None;
+        }
+        let (min_k, max_k) = points
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (k, _)| {
+                (lo.min(*k), hi.max(*k))
+            });
+        let mut best: Option<(f64, SviParams)> = None;
+        let (mut m_range, mut sigma_range) = ((min_k, max_k), (0.005, 1.0));
+        for _ in 0..4 {
+            let steps = 20;
+            for i in 0..=steps {
+                let m = m_range.0 + (m_range.1 - m_range.0) * i as f64 / steps as f64;
+                for j in 0..=steps {
+                    let sigma =
+                        sigma_range.0 + (sigma_range.1 - sigma_range.0) * j as f64 / steps as f64;
+                    if let Some(candidate) = Self::fit_linear(points, m, sigma) {
+                        if best.is_none_or(|(error, _)| candidate.0 < error) {
+                            best = Some(candidate);
+                        }
+                    }
+                }
+            }
+            let (_, params) = best?;
+            let (m_step, sigma_step) = (
+                (m_range.1 - m_range.0) / 10.0,
+                (sigma_range.1 - sigma_range.0) / 10.0,
+            );
+            m_range = (params.m - m_step, params.m + m_step);
+            sigma_range = (
+                (params.sigma - sigma_step).max(1e-4),
+                params.sigma + sigma_step,
+            );
+        }
+        best.map(|(_, params)| params)
+    }
+
+    /// least squares of w = a + d * y + c * sqrt(y^2 + 1) with y = (k - m) / sigma
+    fn fit_linear(points: &[(f64, f64)], m: f64, sigma: f64) -> Option<(f64, SviParams)> {
+        let mut ata = [[0.0; 3]; 3];
+        let mut atb = [0.0; 3];
+        for (k, w) in points {
+            let y = (k - m) / sigma;
+            let row = [1.0, y, (y * y + 1.0).sqrt()];
+            for r in 0..3 {
+                for c in 0..3 {
+                    ata[r][c] += row[r] * row[c];
+                }
+                atb[r] += row[r] * w;
+            }
+        }
+        let [a, d, c] = solve3(ata, atb)?;
+        // keep the smile convex with |rho| < 1 and non-negative variance at the minimum
+        if c <= 0.0 || d.abs() >= c || a + (c * c - d * d).sqrt() < 0.0 {
+            return None;
+        }
+        let params = SviParams {
+            a,
+            b: c / sigma,
+            rho: d / c,
+            m,
+            sigma,
+        };
+        let error = points
+            .iter()
+            .map(|(k, w)| (params.total_variance(*k) - w).powi(2))
+            .sum();
+        Some((error, params))
+    }
+}
+
+/// gaussian elimination with partial pivoting
+fn solve3(mut a: [[f64; 3]; 3], mut b: [f64; 3]) -> Option<[f64; 3]> {
+    for col in 0..3 {
+        let pivot = (col..3).max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..3 {
+            let factor = a[row][col] / a[col][col];
+            let pivot_row = a[col];
+            for (value, pivot_value) in a[row].iter_mut().zip(pivot_row).skip(col) {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; 3];
+    for row in (0..3).rev() {
+        let sum: f64 = (row + 1..3).map(|c| a[row][c] * x[c]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// the smile of one expiry
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct SmileSlice {
+    pub expiry: NaiveDate,
+    pub time: f64,
+    pub forward: f64,
+    pub points: Vec<VolPoint>,
+    pub svi: Option<SviParams>,
+}
+
+impl SmileSlice {
+    pub fn total_variance(&self, k: f64) -> Option<f64> {
+        if let Some(svi) = self.svi {
+            return Some(svi.total_variance(k));
+        }
+        // linear interpolation between quoted strikes, flat outside them
+        let first = self.points.first()?;
+        let last = self.points.last()?;
+        if k <= first.log_moneyness {
+            return Some(first.total_variance());
+        }
+        if k >= last.log_moneyness {
+            return Some(last.total_variance());
+        }
+        self.points.windows(2).find_map(|pair| {
+            let (lo, hi) = (&pair[0], &pair[1]);
+            if k < lo.log_moneyness || k > hi.log_moneyness {
+                return None;
+            }
+            let weight = (k - lo.log_moneyness) / (hi.log_moneyness - lo.log_moneyness);
+            Some(lo.total_variance() + weight * (hi.total_variance() - lo.total_variance()))
+        })
+    }
+
+    pub fn implied_vol(&self, strike: f64) -> Option<f64> {
+        let w = self.total_variance((strike / self.forward).ln())?;
+        (self.time > 0.0 && w >= 0.0).then(|| (w / self.time).sqrt())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub enum SurfaceArbitrage {
+    /// the fitted smile implies a negative density
+    Butterfly {
+        expiry: NaiveDate,
+        log_moneyness: f64,
+    },
+    /// total variance decreases from the near to the far expiry
+    Calendar {
+        near: NaiveDate,
+        far: NaiveDate,
+        log_moneyness: f64,
+    },
+}
+
+/// implied volatility of the same strike and expiry on two surfaces
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct SmileDifference {
+    pub expiry: NaiveDate,
+    pub strike: i64,
+    pub iv: f64,
+    pub other_iv: f64,
+}
+
+/// Implied volatility surface of one venue, one slice per expiry
+#[derive(PartialEq, Debug, Default, Clone, Serialize)]
+pub struct VolSurface {
+    pub exchange: String,
+    pub asset: String,
+    pub slices: BTreeMap<NaiveDate, SmileSlice>,
+}
+
+impl VolSurface {
+    /// builds the surface from the out of the money midprices of a book and fits each expiry
+    pub fn from_book(
+        book: &OrderBook<'_>,
+        asset: &str,
+        forwards: &ForwardCurve,
+        now: NaiveDateTime,
+    ) -> Self {
+        let mut surface = Self {
+            exchange: book.exchange.to_owned(),
+            asset: asset.to_owned(),
+            ..Default::default()
+        };
+        for (instrument, columns) in &book.asset_order_table {
+            if instrument.to_singular_asset().asset != asset {
+                continue;
+            }
+            let forward = forwards.forward(&instrument.expiration_date) as f64;
+            let strike = instrument.strike_price as f64;
+            let out_of_the_money = match instrument.instrument_type {
+                InstrumentType::Call => strike >= forward,
+                InstrumentType::Pull => strike < forward,
+            };
+            if !out_of_the_money {
+                continue;
+            }
+            // discounting is already in the forward
+            let model = OptionModel::new(forward, 0.0);
+            let time = OptionModel::time_to_expiry(instrument, now);
+            if let Some(iv) = model.implied_vols(instrument, columns, now).mid {
+                surface.add_point(
+                    VolPoint {
+                        strike: instrument.strike_price,
+                        expiry: instrument.expiration_date,
+                        log_moneyness: (strike / forward).ln(),
+                        time,
+                        iv,
+                    },
+                    forward,
+                );
+            }
+        }
+        surface.fit();
+        surface
+    }
+
+    pub fn add_point(&mut self, point: VolPoint, forward: f64) {
+        let slice = self
+            .slices
+            .entry(point.expiry)
+            .or_insert_with(|| SmileSlice {
+                expiry: point.expiry,
+                time: point.time,
+                forward,
+                points: Vec::new(),
+                svi: None,
+            });
+        slice.points.push(point);
+        slice
+            .points
+            .sort_by(|a, b| a.log_moneyness.total_cmp(&b.log_moneyness));
+    }
+
+    /// fits SVI to every expiry with enough strikes
+    pub fn fit(&mut self) {
+        for slice in self.slices.values_mut() {
+            let points: Vec<(f64, f64)> = slice
+                .points
+                .iter()
+                .map(|point| (point.log_moneyness, point.total_variance()))
+                .collect();
+            slice.svi = SviParams::fit(&points);
+        }
+    }
+
+    /// Implied volatility at any strike and expiry. Between expiries total variance is
+    /// interpolated linearly in time at the same log-moneyness
+    pub fn implied_vol(&self, strike: f64, expiry: NaiveDate, time: f64) -> Option<f64> {
+        if let Some(slice) = self.slices.get(&expiry) {
+            return slice.implied_vol(strike);
+        }
+        let near = self.slices.range(..expiry).next_back().map(|(_, s)| s);
+        let far = self.slices.range(expiry..).next().map(|(_, s)| s);
+        let (near, far) = match (near, far) {
+            (Some(near), Some(far)) => (near, far),
+            (Some(slice), None) | (None, Some(slice)) => return slice.implied_vol(strike),
+            (None, None) => return None,
+        };
+        let forward = near.forward
+            + (far.forward - near.forward) * (time - near.time) / (far.time - near.time);
+        let k = (strike / forward).ln();
+        let (w_near, w_far) = (near.total_variance(k)?, far.total_variance(k)?);
+        let w = w_near + (w_far - w_near) * (time - near.time) / (far.time - near.time);
+        (time > 0.0 && w >= 0.0).then(|| (w / time).sqrt())
+    }
+
+    /// butterfly checks on each fitted slice and calendar checks between consecutive expiries,
+    /// reporting the worst log-moneyness of each violation
+    pub fn check_arbitrage(&self) -> Vec<SurfaceArbitrage> {
+        let grid: Vec<f64> = (-30..=30).map(|i| i as f64 * 0.05).collect();
+        let mut violations = Vec::new();
+        for slice in self.slices.values() {
+            let Some(svi) = slice.svi else { continue };
+            let worst = grid
+                .iter()
+                .map(|k| (*k, svi.butterfly_density(*k)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((k, density)) = worst {
+                if density < 0.0 {
+                    violations.push(SurfaceArbitrage::Butterfly {
+                        expiry: slice.expiry,
+                        log_moneyness: k,
+                    });
+                }
+            }
+        }
+        let slices: Vec<&SmileSlice> = self.slices.values().collect();
+        for pair in slices.windows(2) {
+            let (near, far) = (pair[0], pair[1]);
+            let worst = grid
+                .iter()
+                .filter_map(|k| Some((*k, far.total_variance(*k)? - near.total_variance(*k)?)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((k, diff)) = worst {
+                if diff < 0.0 {
+                    violations.push(SurfaceArbitrage::Calendar {
+                        near: near.expiry,
+                        far: far.expiry,
+                        log_moneyness: k,
+                    });
+                }
+            }
+        }
+        violations
+    }
+
+    /// the implied volatility of every strike quoted on both surfaces
+    pub fn compare(&self, other: &VolSurface) -> Vec<SmileDifference> {
+        let mut differences = Vec::new();
+        for (expiry, slice) in &self.slices {
+            let Some(other_slice) = other.slices.get(expiry) else {
+                continue;
+            };
+            for point in &slice.points {
+                if let Some(other_point) =
+                    other_slice.points.iter().find(|p| p.strike == point.strike)
+                {
+                    differences.push(SmileDifference {
+                        expiry: *expiry,
+                        strike: point.strike,
+                        iv: point.iv,
+                        other_iv: other_point.iv,
+                    });
+                }
+            }
+        }
+        differences
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        let json = serde_json::to_string_pretty(&self)?;
+        Ok(json)
+    }
+}
+
+/// surfaces of several venues in one JSON document, for side by side comparison
+pub fn surfaces_to_json(surfaces: &[VolSurface]) -> anyhow::Result<String> {
+    let json = serde_json::to_string_pretty(surfaces)?;
+    Ok(json)
+}
+
+#[test]
+fn svi_fit_recovers_smile_and_flags_calendar_arbitrage() -> anyhow::Result<()> {
+    let truth = SviParams {
+        a: 0.02,
+        b: 0.1,
+        rho: -0.3,
+        m: 0.05,
+        sigma: 0.2,
+    };
+    let expiry = NaiveDate::from_ymd_opt(2024, 6, 28).unwrap();
+    let later = NaiveDate::from_ymd_opt(2024, 9, 27).unwrap();
+    let mut surface = VolSurface {
+        exchange: "deribit".to_owned(),
+        asset: "BTC".to_owned(),
+        ..Default::default()
+    };
+    let forward = 60000.0;
+    for strike in (40000..=90000).step_by(5000) {
+        let k = (strike as f64 / forward).ln();
+        let point = |expiry, time: f64, variance: f64| VolPoint {
+            strike,
+            expiry,
+            log_moneyness: k,
+            time,
+            iv: (variance / time).sqrt(),
+        };
+        surface.add_point(point(expiry, 0.25, truth.total_variance(k)), forward);
+        // the later expiry has less total variance, a calendar arbitrage
+        surface.add_point(point(later, 0.5, truth.total_variance(k) * 0.8), forward);
+    }
+    surface.fit();
+
+    let slice = &surface.slices[&expiry];
+    let fitted = slice.svi.unwrap();
+    for k in [-0.3, 0.0, 0.3] {
+        assert!((fitted.total_variance(k) - truth.total_variance(k)).abs() < 1e-4);
+    }
+    let atm_iv = surface.implied_vol(forward, expiry, 0.25).unwrap();
+    assert!((atm_iv - (truth.total_variance(0.0) / 0.25).sqrt()).abs() < 1e-3);
+
+    let violations = surface.check_arbitrage();
+    assert!(violations
+        .iter()
+        .any(|v| matches!(v, SurfaceArbitrage::Calendar { near, far, .. } if *near == expiry && *far == later)));
+    assert!(!violations
+        .iter()
+        .any(|v| matches!(v, SurfaceArbitrage::Butterfly { .. })));
+
+    let mut okex = surface.clone();
+    okex.exchange = "okex".to_owned();
+    let differences = surface.compare(&okex);
+    assert_eq!(differences.len(), 22);
+    assert!(differences.iter().all(|d| d.iv == d.other_iv));
+    let json = surfaces_to_json(&[surface, okex])?;
+    assert!(json.contains("\"2024-06-28\"") && json.contains("\"okex\""));
+    Ok(())
+}
@@ -0,0 +1,778 @@
+use super::{MarketEvent, MarketEventKind};
+use crate::exchanges::{TradeSide, VenueSpec};
+use crate::trading::{
+    Instrument, Order, OrderBook, Position, PriceColumns, RiskLimits, RiskRejection, TradeRequest,
+    VenueKey,
+};
+use crate::utils::{Clock, SimulatedClock};
+use ordered_float::OrderedFloat;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// rounds of fills and follow up orders handled after one event, so strategies cannot spin forever
+const MAX_SETTLE_ROUNDS: usize = 16;
+
+/// the books holding the strategy's orders, they trade one for one in the venue's contracts
+const STRATEGY_BOOK: &str = "backtest";
+
+#[derive(Debug, Clone)]
+pub struct BacktestConfig {
+    pub latency_ms: u128, // from submitting (or cancelling) an order until the venue sees it
+    pub venues: HashMap<String, VenueSpec>,
+    pub queue_position: bool, // resting orders wait behind the quantity already at their price
+    pub risk: RiskLimits,     // checked as orders reach the venue, no limits by default
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        Self {
+            latency_ms: 50,
+            venues: [VenueSpec::deribit(), VenueSpec::okex()]
+                .into_iter()
+                .map(|venue| (venue.name.clone(), venue))
+                .collect(),
+            queue_position: true,
+            risk: RiskLimits::default(),
+        }
+    }
+}
+
+/// the latest levels of a venue's book, best first
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct BookSnapshot {
+    pub bids: Vec<(f32, f32)>,
+    pub asks: Vec<(f32, f32)>,
+    pub timestamp: u128,
+}
+
+impl BookSnapshot {
+    pub fn new(mut asks: Vec<(f32, f32)>, mut bids: Vec<(f32, f32)>, timestamp: u128) -> Self {
+        asks.retain(|(_, qty)| *qty > 0.0);
+        bids.retain(|(_, qty)| *qty > 0.0);
+        asks.sort_by(|a, b| a.0.total_cmp(&b.0));
+        bids.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Self {
+            bids,
+            asks,
+            timestamp,
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<f32> {
+        self.bids.first().map(|(price, _)| *price)
+    }
+
+    pub fn best_ask(&self) -> Option<f32> {
+        self.asks.first().map(|(price, _)| *price)
+    }
+
+    pub fn mid(&self) -> Option<f32> {
+        Some((self.best_bid()? + self.best_ask()?) / 2.0)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct BacktestFill {
+    pub timestamp: u128,
+    pub order_id: u128,
+    pub exchange: String,
+    pub instrument: Instrument,
+    pub request: TradeRequest,
+    pub price: f32,
+    pub quantity: i32, // contracts
+    pub fee: f32,      // in the premium currency
+    pub liquidity: Liquidity,
+}
+
+/// pnl of all positions at a point in time, in the premium currency
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub struct PnlPoint {
+    pub timestamp: u128,
+    pub realized: f32,
+    pub unrealized: f32, // marked to the mid of each venue
+    pub fees: f32,
+    pub net: f32,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BacktestReport {
+    pub fills: Vec<BacktestFill>,
+    pub pnl: Vec<PnlPoint>,
+    pub positions: HashMap<VenueKey, Position>,
+    pub orders: Vec<Order>, // every order the strategy placed, in their final state
+    pub rejections: Vec<(Order, RiskRejection)>,
+    pub match_errors: Vec<(u128, String)>, // (timestamp, why) of fills that could not be booked
+}
+
+impl BacktestReport {
+    pub fn final_pnl(&self) -> PnlPoint {
+        self.pnl.last().copied().unwrap_or_default()
+    }
+}
+
+/// a strategy under test, it trades through the context
+pub trait BacktestStrategy {
+    fn on_event(&mut self, event: &MarketEvent, ctx: &mut BacktestContext);
+    fn on_fill(&mut self, _fill: &BacktestFill, _ctx: &mut BacktestContext) {}
+}
+
+/// an order on its way to the venue
+struct PendingOrder {
+    order: Order,
+    key: VenueKey,
+    active_at: u128,
+}
+
+/// A `'static` name for the books of a venue, they outlive the events naming them. Each
+/// distinct name is leaked once, there are only a handful of venues
+fn venue_name(exchange: &str) -> &'static str {
+    static NAMES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.iter().find(|name| **name == exchange) {
+        return name;
+    }
+    let name: &'static str = Box::leak(exchange.to_owned().into_boxed_str());
+    names.push(name);
+    name
+}
+
+/// The simulated venues as a strategy sees them. Each venue's depth is an `OrderBook` rebuilt
+/// from every book event, the strategy's orders rest on a book of their own per venue and
+/// match against it with `match_orders_in` like live orders do. Latency and queue position
+/// are simulated on top
+pub struct BacktestContext {
+    pub config: BacktestConfig,
+    clock: Arc<SimulatedClock>,
+    venues: HashMap<String, OrderBook<'static>>,
+    books: HashMap<String, OrderBook<'static>>, // the strategy's orders, per venue
+    positions: HashMap<VenueKey, Position>,
+    pending: Vec<PendingOrder>,
+    cancels: Vec<(u128, u128)>, // (due, order id)
+    keys: HashMap<u128, VenueKey>,
+    queue_ahead: HashMap<u128, f32>, // contracts ahead of each resting order
+    applied_fills: HashMap<u128, usize>,
+    closed: Vec<Order>, // cancelled before they reached the venue
+    rejections: Vec<(Order, RiskRejection)>,
+    fills: Vec<BacktestFill>, // not yet delivered to the strategy
+}
+
+impl BacktestContext {
+    fn new(config: BacktestConfig) -> Self {
+        Self {
+            config,
+            clock: Arc::new(SimulatedClock::default()),
+            venues: HashMap::new(),
+            books: HashMap::new(),
+            positions: HashMap::new(),
+            pending: Vec::new(),
+            cancels: Vec::new(),
+            keys: HashMap::new(),
+            queue_ahead: HashMap::new(),
+            applied_fills: HashMap::new(),
+            closed: Vec::new(),
+            rejections: Vec::new(),
+            fills: Vec::new(),
+        }
+    }
+
+    pub fn now(&self) -> u128 {
+        self.clock.now_ms()
+    }
+
+    /// the venue's depth as of the latest book event
+    pub fn book(&self, exchange: &str, instrument: &Instrument) -> Option<&PriceColumns> {
+        self.venues.get(exchange)?.table_for(instrument)
+    }
+
+    pub fn position(&self, exchange: &str, instrument: &Instrument) -> Position {
+        self.positions
+            .get(&(exchange.to_owned(), instrument.to_singular_asset()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// orders on their way to a venue and those resting there
+    pub fn open_orders(&self) -> Vec<Order> {
+        let resting = self.books.values().flat_map(|book| {
+            book.asset_order_table
+                .values()
+                .flat_map(|table| table.orders.lock().unwrap().clone())
+        });
+        self.pending
+            .iter()
+            .map(|pending| pending.order.clone())
+            .chain(resting)
+            .filter(|order| order.is_open())
+            .collect()
+    }
+
+    /// sends a limit order of `quantity` contracts, the venue sees it after the configured latency
+    pub fn submit(
+        &mut self,
+        exchange: &str,
+        instrument: &Instrument,
+        request: TradeRequest,
+        price: f32,
+        quantity: i32,
+    ) -> u128 {
+        let order = Order::new_at(price, quantity, request, self.clock.as_ref()).with_tag(exchange);
+        let id = order.id;
+        let key = (exchange.to_owned(), instrument.to_singular_asset());
+        self.keys.insert(id, key.clone());
+        self.pending.push(PendingOrder {
+            order,
+            key,
+            active_at: self.now() + self.config.latency_ms,
+        });
+        id
+    }
+
+    /// cancels an order once the request reaches the venue, it may still fill until then
+    pub fn cancel(&mut self, order_id: u128) {
+        self.cancels
+            .push((self.now() + self.config.latency_ms, order_id));
+    }
+
+    /// Orders and cancels the venues have received by now, in the order they arrive. Every
+    /// one is handled, the first fill that could not be booked is returned
+    fn process_due(&mut self) -> anyhow::Result<()> {
+        let now = self.now();
+        let mut due: Vec<(u128, bool, u128)> = self
+            .pending
+            .iter()
+            .filter(|pending| pending.active_at <= now)
+            .map(|pending| (pending.active_at, false, pending.order.id))
+            .collect();
+        due.extend(
+            self.cancels
+                .iter()
+                .filter(|(at, _)| *at <= now)
+                .map(|(at, id)| (*at, true, *id)),
+        );
+        self.cancels.retain(|(at, _)| *at > now);
+        due.sort();
+
+        let mut failure = None;
+        for (_, is_cancel, id) in due {
+            if is_cancel {
+                self.cancel_now(id);
+                continue;
+            }
+            let Some(index) = self.pending.iter().position(|p| p.order.id == id) else {
+                continue;
+            };
+            let pending = self.pending.remove(index);
+            if let Err(err) = self.activate(pending) {
+                failure.get_or_insert(err);
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+
+    fn cancel_now(&mut self, order_id: u128) {
+        if let Some(index) = self.pending.iter().position(|p| p.order.id == order_id) {
+            let mut pending = self.pending.remove(index);
+            if pending.order.cancel().is_ok() {
+                self.closed.push(pending.order);
+            }
+            return;
+        }
+        let Some((exchange, instrument)) = self.keys.get(&order_id) else {
+            return;
+        };
+        if let Some(book) = self.books.get_mut(exchange) {
+            book.cancel_order(instrument, order_id);
+        }
+        self.queue_ahead.remove(&order_id);
+    }
+
+    /// the venue receives an order: it passes the risk checks, takes what it crosses on the
+    /// venue's book and rests the remainder behind the quantity already at its price
+    fn activate(&mut self, pending: PendingOrder) -> anyhow::Result<()> {
+        let PendingOrder { order, key, .. } = pending;
+        let (exchange, instrument) = &key;
+        let underlying = self
+            .config
+            .venues
+            .get(exchange)
+            .map_or(order.quantity as f32, |venue| {
+                venue.to_underlying(order.quantity)
+            });
+        let position = self.position(exchange, instrument).quantity;
+        let venue = self
+            .venues
+            .entry(exchange.clone())
+            .or_insert_with(|| OrderBook::with_clock(venue_name(exchange), self.clock.clone()));
+        venue.add_asset(instrument.clone());
+        let book = self.books.entry(exchange.clone()).or_insert_with(|| {
+            let mut book = OrderBook::with_clock(STRATEGY_BOOK, self.clock.clone());
+            book.risk = self.config.risk.clone();
+            book
+        });
+        book.add_asset(instrument.clone());
+
+        let mut state = book.risk_state(instrument);
+        state.midprice = venue.risk_state(instrument).midprice;
+        state.position = position;
+        let id = order.id;
+        let request = order.request.clone();
+        let price = order.price;
+        if let Err(rejection) =
+            book.add_order_checked(order.clone(), instrument, underlying, &state)
+        {
+            self.rejections.push((order, rejection));
+            return Ok(());
+        }
+        let matched = book.match_order_in(instrument, id, &mut [venue]);
+        let ahead = match venue.table_for(instrument) {
+            Some(table) if self.config.queue_position => quantity_at(table, &request, price),
+            _ => 0.0,
+        };
+        self.queue_ahead.insert(id, ahead);
+        self.collect_fills(&key, Liquidity::Taker);
+        matched
+    }
+
+    fn apply_event(&mut self, event: &MarketEvent) -> anyhow::Result<()> {
+        let key = (event.exchange.clone(), event.instrument.to_singular_asset());
+        let (exchange, instrument) = &key;
+        match &event.kind {
+            MarketEventKind::Book { asks, bids } => {
+                let venue = self.venues.entry(exchange.clone()).or_insert_with(|| {
+                    OrderBook::with_clock(venue_name(exchange), self.clock.clone())
+                });
+                // the event carries the whole book, it replaces what the venue had
+                venue
+                    .asset_order_table
+                    .insert(instrument.clone(), PriceColumns::default());
+                let levels = asks
+                    .iter()
+                    .map(|level| (level, TradeRequest::Ask))
+                    .chain(bids.iter().map(|level| (level, TradeRequest::Bid)));
+                for ((price, quantity), request) in levels {
+                    let contracts = quantity.floor() as i32;
+                    if contracts > 0 {
+                        let order = venue.new_order(*price, contracts, request);
+                        venue.insert_order(order, instrument);
+                    }
+                }
+                let Some(book) = self.books.get_mut(exchange) else {
+                    return Ok(());
+                };
+                // resting orders the new book has moved through fill at its levels
+                let matched = book.match_orders_in(instrument, &mut [venue]);
+                if let (Some(ours), Some(theirs)) =
+                    (book.table_for(instrument), venue.table_for(instrument))
+                {
+                    for order in ours.orders.lock().unwrap().iter() {
+                        if let Some(ahead) = self.queue_ahead.get_mut(&order.id) {
+                            // cancellations ahead of us move us up the queue
+                            let queue = quantity_at(theirs, &order.request, order.price);
+                            *ahead = ahead.min(queue);
+                        }
+                    }
+                }
+                self.collect_fills(&key, Liquidity::Maker);
+                matched
+            }
+            MarketEventKind::Trade(trade) => {
+                let Some(book) = self.books.get_mut(exchange) else {
+                    return Ok(());
+                };
+                // the queue is in the venue's contracts, prints are in the underlying
+                let mut volume = self
+                    .config
+                    .venues
+                    .get(exchange)
+                    .map_or(trade.size, |venue| trade.size / venue.contract_size);
+                let resting: Vec<Order> = book
+                    .table_for(instrument)
+                    .map(|table| table.orders.lock().unwrap().iter().cloned().collect())
+                    .unwrap_or_default();
+                let mut failure = None;
+                for order in resting {
+                    if volume <= 0.0 {
+                        break;
+                    }
+                    let Some(ahead) = self.queue_ahead.get_mut(&order.id) else {
+                        continue;
+                    };
+                    // sells fill resting bids at or above the print, buys fill resting asks
+                    let reaches = match (&order.request, &trade.side) {
+                        (TradeRequest::Bid, TradeSide::Sell) => trade.price <= order.price,
+                        (TradeRequest::Ask, TradeSide::Buy) => trade.price >= order.price,
+                        _ => false,
+                    };
+                    if !reaches {
+                        continue;
+                    }
+                    if trade.price == order.price {
+                        let consumed = ahead.min(volume);
+                        *ahead -= consumed;
+                        volume -= consumed;
+                    } else {
+                        *ahead = 0.0; // traded through our price
+                    }
+                    let qty = (volume.floor() as i32).min(order.remaining_qty);
+                    if qty <= 0 {
+                        continue;
+                    }
+                    volume -= qty as f32;
+                    // what reached us trades against the order at its own price, like the
+                    // venue's matching would have
+                    let mut print = OrderBook::with_clock(venue_name(exchange), self.clock.clone());
+                    print.add_asset(instrument.clone());
+                    let opposite = if order.request.is_ask() {
+                        TradeRequest::Bid
+                    } else {
+                        TradeRequest::Ask
+                    };
+                    print.insert_order(print.new_order(order.price, qty, opposite), instrument);
+                    if let Err(err) = book.match_order_in(instrument, order.id, &mut [&mut print]) {
+                        failure.get_or_insert(err);
+                    }
+                }
+                self.collect_fills(&key, Liquidity::Maker);
+                failure.map_or(Ok(()), Err)
+            }
+        }
+    }
+
+    /// books the fills our orders on a venue took since the last call into positions, fees
+    /// and the fills the strategy is told about
+    fn collect_fills(&mut self, key: &VenueKey, liquidity: Liquidity) {
+        let (exchange, instrument) = key;
+        let Some(table) = self
+            .books
+            .get(exchange)
+            .and_then(|book| book.table_for(instrument))
+        else {
+            return;
+        };
+        let open: Vec<Order> = table.orders.lock().unwrap().iter().cloned().collect();
+        let mut fills = Vec::new();
+        for order in table.history.iter().chain(open.iter()) {
+            let applied = self.applied_fills.entry(order.id).or_default();
+            for fill in order.filled_with.iter().skip(*applied) {
+                fills.push((order.id, order.request.clone(), fill.clone()));
+            }
+            *applied = order.filled_with.len();
+            if !order.is_open() {
+                self.queue_ahead.remove(&order.id);
+            }
+        }
+
+        let venue = self.config.venues.get(exchange);
+        for (order_id, request, fill) in fills {
+            let underlying = venue.map_or(fill.quantity as f32, |venue| {
+                venue.to_underlying(fill.quantity)
+            });
+            let fee_per_unit = match (venue, liquidity) {
+                (Some(venue), Liquidity::Taker) => venue.taker_fee_per_unit(fill.price),
+                (Some(venue), Liquidity::Maker) => venue.maker_fee_per_unit(fill.price),
+                (None, _) => 0.0,
+            };
+            let fee = fee_per_unit * underlying;
+            let signed = if request.is_ask() {
+                -underlying
+            } else {
+                underlying
+            };
+            self.positions
+                .entry(key.clone())
+                .or_default()
+                .apply_fill(signed, fill.price, fee);
+            self.fills.push(BacktestFill {
+                timestamp: fill.matched_at,
+                order_id,
+                exchange: exchange.clone(),
+                instrument: instrument.clone(),
+                request,
+                price: fill.price,
+                quantity: fill.quantity,
+                fee,
+                liquidity,
+            });
+        }
+    }
+
+    fn pnl(&self) -> PnlPoint {
+        let mut point = PnlPoint {
+            timestamp: self.now(),
+            ..Default::default()
+        };
+        for ((exchange, instrument), position) in &self.positions {
+            point.realized += position.realized_pnl;
+            point.fees += position.fees;
+            let mid = self
+                .venues
+                .get(exchange)
+                .and_then(|venue| venue.risk_state(instrument).midprice);
+            if let Some(mid) = mid {
+                point.unrealized += position.unrealized_pnl(mid);
+            }
+        }
+        point.net = point.realized + point.unrealized - point.fees;
+        point
+    }
+
+    /// every order the strategy placed, in their final state
+    fn into_orders(self) -> Vec<Order> {
+        let mut orders = self.closed;
+        for book in self.books.into_values() {
+            for table in book.asset_order_table.into_values() {
+                orders.extend(table.history);
+                orders.extend(table.orders.lock().unwrap().drain(..));
+            }
+        }
+        orders.extend(self.pending.into_iter().map(|pending| pending.order));
+        orders
+    }
+}
+
+/// contracts resting at exactly `price` on one side of a venue's book
+fn quantity_at(table: &PriceColumns, request: &TradeRequest, price: f32) -> f32 {
+    let row = if request.is_ask() {
+        &table.asks
+    } else {
+        &table.bids
+    };
+    row.get(&OrderedFloat(price))
+        .map_or(0.0, |holding| holding.total_quantity as f32)
+}
+
+/// Feeds historical events through a strategy on simulated venues with latency,
+/// queue position and fees, recording fills, positions and pnl over time
+pub struct Backtester<S: BacktestStrategy> {
+    pub strategy: S,
+    ctx: BacktestContext,
+    report: BacktestReport,
+}
+
+impl<S: BacktestStrategy> Backtester<S> {
+    pub fn new(strategy: S, config: BacktestConfig) -> Self {
+        Self {
+            strategy,
+            ctx: BacktestContext::new(config),
+            report: BacktestReport::default(),
+        }
+    }
+
+    /// a clock following the event times, for components that should share the backtest's time
+    pub fn clock(&self) -> Arc<SimulatedClock> {
+        self.ctx.clock.clone()
+    }
+
+    pub fn run<I: IntoIterator<Item = MarketEvent>>(mut self, events: I) -> BacktestReport {
+        for event in events {
+            let now = (event.timestamp as u64).max(self.ctx.now() as u64);
+            self.ctx.clock.set(now);
+            let processed = self.ctx.process_due();
+            self.record(processed);
+            let applied = self.ctx.apply_event(&event);
+            self.record(applied);
+            self.deliver_fills();
+            self.strategy.on_event(&event, &mut self.ctx);
+            self.settle();
+            self.report.pnl.push(self.ctx.pnl());
+        }
+
+        self.report.positions = std::mem::take(&mut self.ctx.positions);
+        self.report.rejections = std::mem::take(&mut self.ctx.rejections);
+        self.report.orders = self.ctx.into_orders();
+        self.report
+    }
+
+    /// keeps why a fill could not be booked, the backtest carries on with the rest
+    fn record(&mut self, result: anyhow::Result<()>) {
+        if let Err(err) = result {
+            self.report
+                .match_errors
+                .push((self.ctx.now(), format!("{err:#}")));
+        }
+    }
+
+    fn deliver_fills(&mut self) {
+        while !self.ctx.fills.is_empty() {
+            let fills: Vec<_> = self.ctx.fills.drain(..).collect();
+            for fill in fills {
+                self.strategy.on_fill(&fill, &mut self.ctx);
+                self.report.fills.push(fill);
+            }
+        }
+    }
+
+    /// without latency, orders sent in a callback reach the venue before the next event
+    fn settle(&mut self) {
+        for _ in 0..MAX_SETTLE_ROUNDS {
+            let processed = self.ctx.process_due();
+            self.record(processed);
+            if self.ctx.fills.is_empty() {
+                break;
+            }
+            self.deliver_fills();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchanges::{ExchangeType, Trade};
+
+    /// submits the given orders when it sees an event at their time
+    struct Scripted(Vec<(u128, TradeRequest, f32, i32)>);
+
+    impl BacktestStrategy for Scripted {
+        fn on_event(&mut self, event: &MarketEvent, ctx: &mut BacktestContext) {
+            for (at, request, price, quantity) in &self.0 {
+                if *at == event.timestamp {
+                    ctx.submit(
+                        "deribit",
+                        &event.instrument,
+                        request.clone(),
+                        *price,
+                        *quantity,
+                    );
+                }
+            }
+        }
+    }
+
+    fn instrument() -> Instrument {
+        Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris).unwrap()
+    }
+
+    fn book(timestamp: u128, asks: Vec<(f32, f32)>, bids: Vec<(f32, f32)>) -> MarketEvent {
+        MarketEvent {
+            timestamp,
+            exchange: "deribit".to_owned(),
+            instrument: instrument(),
+            kind: MarketEventKind::Book { asks, bids },
+        }
+    }
+
+    fn trade(timestamp: u128, price: f32, size: f32) -> MarketEvent {
+        MarketEvent {
+            timestamp,
+            exchange: "deribit".to_owned(),
+            instrument: instrument(),
+            kind: MarketEventKind::Trade(Trade {
+                exchange: "deribit".to_owned(),
+                instrument: instrument(),
+                trade_id: timestamp.to_string(),
+                price,
+                size,
+                side: TradeSide::Sell,
+                timestamp,
+            }),
+        }
+    }
+
+    #[test]
+    fn latency_fees_and_pnl() {
+        let strategy = Scripted(vec![
+            (1_000, TradeRequest::Bid, 0.013, 3),
+            (2_000, TradeRequest::Ask, 0.014, 3),
+        ]);
+        let events = vec![
+            book(1_000, vec![(0.012, 5.0)], vec![(0.010, 5.0)]),
+            // the ask moved before our order arrived
+            book(1_050, vec![(0.013, 5.0)], vec![(0.011, 5.0)]),
+            book(1_100, vec![(0.013, 2.0)], vec![(0.011, 5.0)]),
+            book(2_000, vec![(0.016, 5.0)], vec![(0.015, 5.0)]),
+            book(2_100, vec![(0.016, 5.0)], vec![(0.0145, 5.0)]),
+        ];
+        let config = BacktestConfig {
+            latency_ms: 100,
+            ..Default::default()
+        };
+        let report = Backtester::new(strategy, config).run(events);
+
+        let fills: Vec<_> = report
+            .fills
+            .iter()
+            .map(|fill| (fill.timestamp, fill.price, fill.quantity, fill.liquidity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (1_100, 0.013, 3, Liquidity::Taker),
+                (2_100, 0.015, 3, Liquidity::Taker)
+            ]
+        );
+        let position = &report.positions[&("deribit".to_owned(), instrument())];
+        assert!(position.is_flat());
+        let fees = 3.0 * 0.0003 * 2.0;
+        let pnl = report.final_pnl();
+        assert!((pnl.realized - 0.006).abs() < 1e-6);
+        assert!((pnl.fees - fees).abs() < 1e-6);
+        assert!((pnl.net - (0.006 - fees)).abs() < 1e-6);
+        assert!(report.orders.iter().all(|order| order.is_completed()));
+    }
+
+    #[test]
+    fn resting_orders_wait_their_turn_in_the_queue() {
+        let strategy = Scripted(vec![(1_000, TradeRequest::Bid, 0.010, 3)]);
+        let events = vec![
+            book(1_000, vec![(0.012, 5.0)], vec![(0.010, 5.0)]),
+            trade(1_100, 0.010, 3.0), // 2 still ahead of us
+            trade(1_200, 0.010, 4.0),
+            trade(1_300, 0.009, 1.0), // through our price
+        ];
+        let config = BacktestConfig {
+            latency_ms: 0,
+            ..Default::default()
+        };
+        let report = Backtester::new(strategy, config).run(events);
+        let fills: Vec<_> = report
+            .fills
+            .iter()
+            .map(|fill| (fill.timestamp, fill.quantity, fill.liquidity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![(1_200, 2, Liquidity::Maker), (1_300, 1, Liquidity::Maker)]
+        );
+        let position = &report.positions[&("deribit".to_owned(), instrument())];
+        assert_eq!((position.quantity, position.avg_price), (3.0, 0.010));
+        // marked to the 0.011 mid
+        assert!((report.final_pnl().unrealized - 0.003).abs() < 1e-6);
+    }
+
+    #[test]
+    fn orders_past_the_limits_never_reach_the_venue() {
+        let strategy = Scripted(vec![
+            (1_000, TradeRequest::Bid, 0.012, 3),
+            (1_000, TradeRequest::Bid, 0.012, 2),
+        ]);
+        let events = vec![
+            book(1_000, vec![(0.012, 5.0)], vec![(0.010, 5.0)]),
+            book(1_100, vec![(0.012, 5.0)], vec![(0.010, 5.0)]),
+        ];
+        let config = BacktestConfig {
+            risk: RiskLimits {
+                max_order_size: Some(2.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = Backtester::new(strategy, config).run(events);
+        assert_eq!(report.rejections.len(), 1);
+        assert_eq!(report.rejections[0].0.quantity, 3);
+        let fills: Vec<_> = report
+            .fills
+            .iter()
+            .map(|fill| (fill.timestamp, fill.quantity, fill.liquidity))
+            .collect();
+        assert_eq!(fills, vec![(1_100, 2, Liquidity::Taker)]);
+        assert!(report.match_errors.is_empty());
+        assert_eq!(report.orders.len(), 1);
+    }
+}
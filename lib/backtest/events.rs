@@ -0,0 +1,69 @@
+use crate::exchanges::{DeribitResponse, OkexResponse, Returnable, Trade};
+use crate::trading::Instrument;
+use crate::utils::RecordedFrame;
+use serde::de::DeserializeOwned;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum MarketEventKind {
+    /// the levels of a book message as (price, quantity), like `Returnable::asks_bids_pair`
+    Book {
+        asks: Vec<(f32, f32)>,
+        bids: Vec<(f32, f32)>,
+    },
+    Trade(Trade),
+}
+
+/// a historical market event of one venue, instruments are canonical
+#[derive(PartialEq, Debug, Clone)]
+pub struct MarketEvent {
+    pub timestamp: u128, // ms
+    pub exchange: String,
+    pub instrument: Instrument,
+    pub kind: MarketEventKind,
+}
+
+impl MarketEvent {
+    /// decodes a recorded frame with the decoder of its venue, stamped with its receive time
+    pub fn from_frame(frame: &RecordedFrame) -> Vec<Self> {
+        match frame.exchange.as_str() {
+            "deribit" => Self::decode::<DeribitResponse>(frame),
+            "okex" => Self::decode::<OkexResponse>(frame),
+            _ => Vec::new(),
+        }
+    }
+
+    fn decode<T: Returnable + DeserializeOwned>(frame: &RecordedFrame) -> Vec<Self> {
+        let Ok(json) = serde_json::from_str::<T>(&frame.frame) else {
+            return Vec::new();
+        };
+        let event = |instrument: &Instrument, kind| MarketEvent {
+            timestamp: frame.received_at,
+            exchange: frame.exchange.clone(),
+            instrument: instrument.to_singular_asset(),
+            kind,
+        };
+        let mut events = Vec::new();
+        if let (Some((asks, bids)), Some(instrument)) =
+            (json.asks_bids_pair(), json.instrument_name())
+        {
+            events.push(event(&instrument, MarketEventKind::Book { asks, bids }));
+        }
+        for trade in json.trades() {
+            events.push(event(
+                &trade.instrument.clone(),
+                MarketEventKind::Trade(trade),
+            ));
+        }
+        events
+    }
+}
+
+/// the market events of a recording, in time order
+pub fn events_from_frames<I: IntoIterator<Item = RecordedFrame>>(frames: I) -> Vec<MarketEvent> {
+    let mut events: Vec<MarketEvent> = frames
+        .into_iter()
+        .flat_map(|frame| MarketEvent::from_frame(&frame))
+        .collect();
+    events.sort_by_key(|event| event.timestamp);
+    events
+}
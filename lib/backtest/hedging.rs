@@ -0,0 +1,343 @@
+use super::BookSnapshot;
+use crate::analytics::Portfolio;
+use crate::trading::{ImpactEstimate, TradeRequest, VenueQuote};
+use chrono::{DateTime, NaiveDateTime};
+
+/// An inverse (coin-margined) perpetual swap: sized in USD contracts, with pnl and fees in the
+/// underlying. Holding `usd` of it is a delta of `usd / price` in the underlying
+#[derive(PartialEq, Debug, Clone)]
+pub struct PerpetualSpec {
+    pub name: String,
+    pub contract_size: f64, // USD per contract
+    pub taker_fee: f64,     // fraction of the notional
+}
+
+impl PerpetualSpec {
+    pub fn deribit() -> Self {
+        Self {
+            name: "BTC-PERPETUAL".to_owned(),
+            contract_size: 10.0,
+            taker_fee: 0.0005,
+        }
+    }
+
+    pub fn okex() -> Self {
+        Self {
+            name: "BTC-USD-SWAP".to_owned(),
+            contract_size: 100.0,
+            taker_fee: 0.0005,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum HedgePolicy {
+    Interval { every_ms: u128 },  // rehedge on a schedule, whatever the delta
+    Threshold { max_delta: f64 }, // rehedge once the net delta drifts past this (in the underlying)
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct HedgeConfig {
+    pub policy: HedgePolicy,
+    pub perpetual: PerpetualSpec,
+}
+
+/// the market at one point of a hedging simulation
+#[derive(PartialEq, Debug, Clone)]
+pub struct HedgeTick {
+    pub timestamp: u128,         // ms
+    pub underlying_price: f64,   // the options are repriced and the perpetual marked at this
+    pub perpetual: BookSnapshot, // quantities in contracts
+}
+
+/// a rebalance of the perpetual, which takes liquidity
+#[derive(PartialEq, Debug, Clone)]
+pub struct HedgeTrade {
+    pub timestamp: u128,
+    pub request: TradeRequest,
+    pub requested: i32, // contracts
+    pub filled: i32,
+    pub price: f64,    // vwap of the levels taken
+    pub fee: f64,      // in the underlying
+    pub slippage: f64, // what trading at the vwap instead of the mid cost, in the underlying
+    pub delta_before: f64,
+    pub delta_after: f64,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct HedgePoint {
+    pub timestamp: u128,
+    pub option_pnl: f64,
+    pub hedge_pnl: f64,
+    pub net_delta: f64,
+}
+
+/// Pnl is in the underlying and kept apart: `option_pnl` is the repricing of the options,
+/// `hedge_pnl` the perpetual's including slippage, fees are paid on top of both
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct HedgeReport {
+    pub trades: Vec<HedgeTrade>,
+    pub path: Vec<HedgePoint>,
+    pub option_pnl: f64,
+    pub hedge_pnl: f64,
+    pub fees: f64,
+    pub slippage: f64,
+    pub final_delta: f64,
+}
+
+impl HedgeReport {
+    pub fn total_pnl(&self) -> f64 {
+        self.option_pnl + self.hedge_pnl - self.fees
+    }
+}
+
+/// Delta hedges option positions (e.g. `Portfolio::from_account`) with a perpetual, at the
+/// vols the portfolio was built with. The perpetual is marked at the underlying price,
+/// ignoring its basis and funding
+#[derive(Debug, Clone)]
+pub struct DeltaHedger {
+    pub config: HedgeConfig,
+    portfolio: Portfolio,
+    contracts: i32, // signed perpetual position
+    cost: f64,      // sum of the USD bought over the price paid, in the underlying
+    last_hedge: Option<u128>,
+}
+
+impl DeltaHedger {
+    pub fn new(portfolio: Portfolio, config: HedgeConfig) -> Self {
+        Self {
+            config,
+            portfolio,
+            contracts: 0,
+            cost: 0.0,
+            last_hedge: None,
+        }
+    }
+
+    fn usd(&self) -> f64 {
+        self.contracts as f64 * self.config.perpetual.contract_size
+    }
+
+    fn hedge_delta(&self, underlying_price: f64) -> f64 {
+        self.usd() / underlying_price
+    }
+
+    fn hedge_pnl(&self, underlying_price: f64) -> f64 {
+        self.cost - self.usd() / underlying_price
+    }
+
+    fn is_due(&self, timestamp: u128, net_delta: f64) -> bool {
+        match self.config.policy {
+            HedgePolicy::Interval { every_ms } => self
+                .last_hedge
+                .is_none_or(|last| timestamp >= last + every_ms),
+            HedgePolicy::Threshold { max_delta } => net_delta.abs() > max_delta,
+        }
+    }
+
+    /// trades the perpetual back to delta neutral, as far as its book allows
+    fn rebalance(&mut self, tick: &HedgeTick, option_delta: f64) -> Option<HedgeTrade> {
+        let spot = tick.underlying_price;
+        let delta_before = option_delta + self.hedge_delta(spot);
+        let size = self.config.perpetual.contract_size;
+        let target = (-option_delta * spot / size).round() as i32;
+        let quantity = target - self.contracts;
+        if quantity == 0 {
+            return None;
+        }
+        let (request, levels) = if quantity > 0 {
+            (TradeRequest::Bid, &tick.perpetual.asks)
+        } else {
+            (TradeRequest::Ask, &tick.perpetual.bids)
+        };
+        let levels: Vec<VenueQuote> = levels
+            .iter()
+            .map(|(price, qty)| VenueQuote::new(&self.config.perpetual.name, *price, *qty as i32))
+            .collect();
+        // walked in contracts, the perpetual is quoted in USD contracts
+        let estimate = ImpactEstimate::from_levels(
+            request.clone(),
+            quantity.abs() as f32,
+            &levels,
+            tick.perpetual.mid(),
+            |_| 1.0,
+        );
+        let price = estimate.vwap? as f64;
+        let filled = estimate.filled.round() as i32;
+        let signed = if request.is_ask() { -filled } else { filled };
+        let usd = signed as f64 * size;
+        let mid = estimate.mid.map_or(price, |mid| mid as f64);
+        self.contracts += signed;
+        self.cost += usd / price;
+        Some(HedgeTrade {
+            timestamp: tick.timestamp,
+            request,
+            requested: quantity.abs(),
+            filled,
+            price,
+            fee: self.config.perpetual.taker_fee * usd.abs() / price,
+            slippage: usd / mid - usd / price,
+            delta_before,
+            delta_after: option_delta + self.hedge_delta(spot),
+        })
+    }
+
+    /// runs the policy over the ticks in order, the pnl is measured from the first
+    pub fn run(&mut self, ticks: &[HedgeTick]) -> HedgeReport {
+        let mut report = HedgeReport::default();
+        let mut initial_value = None;
+        for tick in ticks {
+            let Some(now) = DateTime::from_timestamp_millis(tick.timestamp as i64) else {
+                continue;
+            };
+            let now: NaiveDateTime = now.naive_utc();
+            let priced = Portfolio {
+                underlying_price: tick.underlying_price,
+                ..self.portfolio.clone()
+            };
+            let value = priced.value(now);
+            let initial_value = *initial_value.get_or_insert(value);
+            let option_delta = priced.greeks(now).delta;
+
+            let net_delta = option_delta + self.hedge_delta(tick.underlying_price);
+            if self.is_due(tick.timestamp, net_delta) {
+                self.last_hedge = Some(tick.timestamp);
+                if let Some(trade) = self.rebalance(tick, option_delta) {
+                    report.fees += trade.fee;
+                    report.slippage += trade.slippage;
+                    report.trades.push(trade);
+                }
+            }
+
+            let point = HedgePoint {
+                timestamp: tick.timestamp,
+                option_pnl: value - initial_value,
+                hedge_pnl: self.hedge_pnl(tick.underlying_price),
+                net_delta: option_delta + self.hedge_delta(tick.underlying_price),
+            };
+            report.option_pnl = point.option_pnl;
+            report.hedge_pnl = point.hedge_pnl;
+            report.final_delta = point.net_delta;
+            report.path.push(point);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchanges::ExchangeType;
+    use crate::trading::Instrument;
+
+    const START: u128 = 1_712_736_000_000; // 2024-04-10 08:00 UTC
+    const HOUR: u128 = 60 * 60 * 1_000;
+
+    fn long_calls() -> Portfolio {
+        let call = Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)
+            .unwrap();
+        let mut portfolio = Portfolio::new(63_000.0, 0.0);
+        portfolio.add_position("deribit", &call, 10.0, 0.55);
+        portfolio
+    }
+
+    /// a book one dollar wide, `depth` contracts on the best level and as many a dollar behind
+    fn tick(hours: u128, spot: f64, depth: f32) -> HedgeTick {
+        let spot_f32 = spot as f32;
+        HedgeTick {
+            timestamp: START + hours * HOUR,
+            underlying_price: spot,
+            perpetual: BookSnapshot::new(
+                vec![(spot_f32 + 0.5, depth), (spot_f32 + 1.5, depth)],
+                vec![(spot_f32 - 0.5, depth), (spot_f32 - 1.5, depth)],
+                START + hours * HOUR,
+            ),
+        }
+    }
+
+    fn config(policy: HedgePolicy) -> HedgeConfig {
+        HedgeConfig {
+            policy,
+            perpetual: PerpetualSpec::deribit(),
+        }
+    }
+
+    /// up 2%, down 2% and back over three hours
+    fn round_trip(depth: f32) -> Vec<HedgeTick> {
+        vec![
+            tick(0, 63_000.0, depth),
+            tick(1, 64_260.0, depth),
+            tick(2, 61_740.0, depth),
+            tick(3, 63_000.0, depth),
+        ]
+    }
+
+    #[test]
+    fn interval_hedging_keeps_delta_flat_and_books_costs() {
+        let ticks = round_trip(100_000.0);
+        let mut hedger = DeltaHedger::new(
+            long_calls(),
+            config(HedgePolicy::Interval { every_ms: HOUR }),
+        );
+        let report = hedger.run(&ticks);
+        let mut unhedged = DeltaHedger::new(
+            long_calls(),
+            config(HedgePolicy::Threshold { max_delta: 100.0 }),
+        );
+        let unhedged = unhedged.run(&ticks);
+        assert!(unhedged.trades.is_empty());
+        assert!(unhedged.final_delta > 3.0);
+
+        assert_eq!(report.trades.len(), 4);
+        for trade in report.trades.iter() {
+            // flat to within a contract
+            assert!(trade.delta_after.abs() < 10.0 / 61_740.0);
+            assert!(trade.slippage > 0.0);
+        }
+        // long calls are hedged by selling the perpetual
+        let first = &report.trades[0];
+        assert_eq!(first.request, TradeRequest::Ask);
+        assert_eq!(first.filled, first.requested);
+        assert!((first.fee - 0.0005 * first.filled as f64 * 10.0 / first.price).abs() < 1e-12);
+
+        // option pnl is the same hedged or not, rebalancing a long gamma position earns the rest
+        assert_eq!(report.option_pnl, unhedged.option_pnl);
+        assert!(report.hedge_pnl > 0.0);
+        assert!(report.total_pnl() > unhedged.total_pnl());
+        let fees: f64 = report.trades.iter().map(|trade| trade.fee).sum();
+        assert_eq!(report.fees, fees);
+        assert_eq!(
+            report.total_pnl(),
+            report.option_pnl + report.hedge_pnl - report.fees
+        );
+        assert_eq!(report.path.len(), 4);
+        assert_eq!(report.path[3].hedge_pnl, report.hedge_pnl);
+    }
+
+    #[test]
+    fn threshold_hedging_on_thin_books() {
+        let mut hedger = DeltaHedger::new(
+            long_calls(),
+            config(HedgePolicy::Threshold { max_delta: 0.6 }),
+        );
+        // the spot moves leave the delta within the threshold after the first hedge
+        let report = hedger.run(&round_trip(100_000.0));
+        assert_eq!(report.trades.len(), 1);
+        assert!(report.path[2].net_delta.abs() < 0.6);
+
+        // the book only has 20k contracts, the rest of the delta stays unhedged
+        let mut hedger = DeltaHedger::new(
+            long_calls(),
+            config(HedgePolicy::Threshold { max_delta: 0.6 }),
+        );
+        let report = hedger.run(&round_trip(10_000.0)[..1]);
+        let trade = &report.trades[0];
+        assert_eq!(trade.filled, 20_000);
+        assert!(trade.requested > trade.filled);
+        assert!((trade.price - 62_999.0).abs() < 1e-9);
+        assert!(trade.delta_after > 0.5);
+        // half the fill paid a dollar more than the mid
+        let usd = 20_000.0 * 10.0;
+        assert!((trade.slippage - (usd / 62_999.0 - usd / 63_000.0)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,6 @@
+mod events;
+pub use events::*;
+mod engine;
+pub use engine::*;
+mod hedging;
+pub use hedging::*;
@@ -1,5 +1,9 @@
 
-use super::{MessageExtendable, Returnable};
+use super::{
+    MessageExtendable, OptionSummary, PriceKind, PriceUpdate, Returnable, Trade, TradeSide,
+    VenueSpec,
+};
+use crate::analytics::Greeks;
 use crate::trading::{Instrument, InstrumentType};
 use anyhow::{ensure, Ok};
 use serde::{Deserialize, Serialize};
@@ -11,7 +15,95 @@ pub struct DeribitResponse {
 
 #[derive(Deserialize, Debug)]
 pub struct ResponseParams {
-    pub data: DeribitResponseData,
+    pub channel: Option<String>,
+    pub data: DeribitData,
+}
+
+/// data of the subscribed channels, told apart by their shape
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum DeribitData {
+    Book(DeribitResponseData),
+    Ticker(DeribitTicker),
+    Index(DeribitIndexData),
+    Trades(Vec<DeribitTrade>), // before mark prices, trades carry a mark price too
+    MarkPrices(Vec<DeribitMarkPrice>),
+}
+
+/// an entry of `trades.{instrument_name}.100ms`
+#[derive(Deserialize, Debug)]
+pub struct DeribitTrade {
+    pub trade_id: String,
+    pub instrument_name: String,
+    pub price: f32,
+    pub amount: f32,
+    pub direction: String,
+    pub timestamp: u64,
+}
+
+/// `ticker.{instrument_name}.100ms`, only the fields we use
+#[derive(Deserialize, Debug)]
+pub struct DeribitTicker {
+    pub instrument_name: String,
+    pub mark_price: f32,
+    pub mark_iv: f64, // in percent
+    pub bid_iv: Option<f64>,
+    pub ask_iv: Option<f64>,
+    pub underlying_price: f64,
+    pub greeks: DeribitGreeks,
+    pub timestamp: u64,
+}
+
+/// Black-Scholes greeks as published by deribit, vega and theta are in USD
+#[derive(Deserialize, Debug)]
+pub struct DeribitGreeks {
+    pub delta: f64,
+    pub gamma: f64,
+    pub vega: f64,
+    pub theta: f64,
+}
+
+impl DeribitTicker {
+    fn to_summary(&self) -> Option<OptionSummary> {
+        let underlying = self.underlying_price;
+        let mark_price = self.mark_price as f64;
+        // a side without quotes has an iv of 0
+        let percent = |iv: Option<f64>| iv.filter(|iv| *iv > 0.0).map(|iv| iv / 100.0);
+        Some(OptionSummary {
+            exchange: "deribit".to_owned(),
+            instrument: string_to_instrument_deribit(&self.instrument_name).ok()?,
+            mark_iv: self.mark_iv / 100.0,
+            bid_iv: percent(self.bid_iv),
+            ask_iv: percent(self.ask_iv),
+            mark_price: Some(mark_price),
+            underlying_price: underlying,
+            greeks: Greeks {
+                // the premium is paid in BTC, so the coin delta is lower by the premium
+                delta: self.greeks.delta - mark_price,
+                gamma: self.greeks.gamma,
+                vega: self.greeks.vega / underlying,
+                theta: self.greeks.theta / underlying,
+            },
+            timestamp: self.timestamp as u128,
+        })
+    }
+}
+
+/// `deribit_price_index.{index_name}`
+#[derive(Deserialize, Debug)]
+pub struct DeribitIndexData {
+    pub index_name: String,
+    pub price: f32,
+    pub timestamp: u64,
+}
+
+/// an entry of `markprice.options.{index_name}`
+#[derive(Deserialize, Debug)]
+pub struct DeribitMarkPrice {
+    pub instrument_name: String,
+    pub mark_price: f32,
+    pub iv: Option<f32>,
+    pub timestamp: u64,
 }
 
 #[derive(Deserialize, Debug)]
@@ -55,8 +147,14 @@ struct DeribitInitMessageParams {
 
 impl MessageExtendable for DeribitInitMessageParams {
     fn add_asset(&mut self, asset: &str) {
-        if let Some(value) = self.channels.get_mut(0) {
-            *value = format!("book.{}.none.20.100ms", asset);
+        for value in self.channels.iter_mut() {
+            if value.starts_with("book.") {
+                *value = format!("book.{}.none.20.100ms", asset);
+            } else if value.starts_with("trades.") {
+                *value = format!("trades.{}.100ms", asset);
+            } else if value.starts_with("ticker.") {
+                *value = format!("ticker.{}.100ms", asset);
+            }
         }
     }
 
@@ -69,7 +167,13 @@ impl MessageExtendable for DeribitInitMessageParams {
 impl Default for DeribitInitMessageParams {
     fn default() -> Self {
         Self {
-            channels: vec!["book.BTC-10MAY24-66000-C.none.20.100ms".to_owned()],
+            channels: vec![
+                "book.BTC-10MAY24-66000-C.none.20.100ms".to_owned(),
+                "trades.BTC-10MAY24-66000-C.100ms".to_owned(),
+                "ticker.BTC-10MAY24-66000-C.100ms".to_owned(),
+                "deribit_price_index.btc_usd".to_owned(),
+                "markprice.options.btc_usd".to_owned(),
+            ],
             jsonrpc: String::from("2.0"),
             id: 0,
         }
@@ -77,12 +181,10 @@ impl Default for DeribitInitMessageParams {
 }
 
 impl DeribitInitMessageParams {
-    fn new(asset: &String) -> Self {
-        let channel = format!("book.{asset}.none.20.100ms");
-        Self {
-            channels: vec![channel],
-            ..Default::default()
-        }
+    fn new(asset: &str) -> Self {
+        let mut params = Self::default();
+        params.add_asset(asset);
+        params
     }
 }
 
@@ -96,12 +198,8 @@ impl DeribitInitMessageParams {
 impl Returnable for DeribitResponse {
     fn asks_bids_pair(&self) -> Option<super::AskBidPairs> {
         if let Some(ResponseParams {
-            data:
-                DeribitResponseData {
-                    bids,
-                    asks,
-                    instrument_name,
-                },
+            data: DeribitData::Book(DeribitResponseData { bids, asks, .. }),
+            ..
         }) = &self.params
         {
             return Some((asks.to_owned(), bids.to_owned()));
@@ -112,11 +210,10 @@ impl Returnable for DeribitResponse {
     fn instrument_name(&self) -> Option<Instrument> {
         if let Some(ResponseParams {
             data:
-                DeribitResponseData {
-                    bids,
-                    asks,
-                    instrument_name,
-                },
+                DeribitData::Book(DeribitResponseData {
+                    instrument_name, ..
+                }),
+            ..
         }) = &self.params
         {
             let name = instrument_name;
@@ -126,6 +223,71 @@ impl Returnable for DeribitResponse {
         }
         None
     }
+
+    fn price_updates(&self) -> Vec<PriceUpdate> {
+        match &self.params {
+            Some(ResponseParams {
+                data: DeribitData::Index(index),
+                ..
+            }) => vec![PriceUpdate {
+                exchange: "deribit".to_owned(),
+                kind: PriceKind::Index,
+                name: index.index_name.clone(),
+                instrument: None,
+                price: index.price,
+                timestamp: index.timestamp as u128,
+            }],
+            Some(ResponseParams {
+                data: DeribitData::MarkPrices(marks),
+                ..
+            }) => marks
+                .iter()
+                .map(|mark| PriceUpdate {
+                    exchange: "deribit".to_owned(),
+                    kind: PriceKind::Mark,
+                    name: mark.instrument_name.clone(),
+                    instrument: string_to_instrument_deribit(&mark.instrument_name).ok(),
+                    price: mark.mark_price,
+                    timestamp: mark.timestamp as u128,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn trades(&self) -> Vec<Trade> {
+        let Some(ResponseParams {
+            data: DeribitData::Trades(trades),
+            ..
+        }) = &self.params
+        else {
+            return Vec::new();
+        };
+        trades
+            .iter()
+            .filter_map(|trade| {
+                Some(Trade {
+                    exchange: "deribit".to_owned(),
+                    instrument: string_to_instrument_deribit(&trade.instrument_name).ok()?,
+                    trade_id: trade.trade_id.clone(),
+                    price: trade.price,
+                    size: trade.amount * VenueSpec::deribit().contract_size,
+                    side: TradeSide::from_given_str(&trade.direction)?,
+                    timestamp: trade.timestamp as u128,
+                })
+            })
+            .collect()
+    }
+
+    fn option_summaries(&self) -> Vec<OptionSummary> {
+        match &self.params {
+            Some(ResponseParams {
+                data: DeribitData::Ticker(ticker),
+                ..
+            }) => ticker.to_summary().into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 pub fn string_to_instrument_deribit(asset: &str) -> anyhow::Result<crate::trading::Instrument> {
@@ -172,3 +334,54 @@ fn parsing_between_string_and_instrument_deribit_works() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn decoding_deribit_index_and_mark_prices() -> anyhow::Result<()> {
+    let index = r#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"deribit_price_index.btc_usd","data":{"timestamp":1715328000000,"price":62995.42,"index_name":"btc_usd"}}}"#;
+    let response: DeribitResponse = serde_json::from_str(index)?;
+    assert!(response.asks_bids_pair().is_none());
+    let updates = response.price_updates();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].kind, PriceKind::Index);
+    assert_eq!(updates[0].name, "btc_usd");
+    assert_eq!(updates[0].price, 62995.42);
+
+    let marks = r#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"markprice.options.btc_usd","data":[{"timestamp":1715328000000,"mark_price":0.0125,"iv":0.52,"instrument_name":"BTC-10MAY24-66000-C"}]}}"#;
+    let response: DeribitResponse = serde_json::from_str(marks)?;
+    let updates = response.price_updates();
+    assert_eq!(updates[0].kind, PriceKind::Mark);
+    assert_eq!(updates[0].instrument.as_ref().unwrap().strike_price, 66000);
+    assert_eq!(updates[0].price, 0.0125);
+    Ok(())
+}
+
+#[test]
+fn decoding_deribit_trades() -> anyhow::Result<()> {
+    let trades = r#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"trades.BTC-10MAY24-66000-C.100ms","data":[{"trade_seq":12,"trade_id":"301234567","timestamp":1715328000123,"tick_direction":0,"price":0.0125,"mark_price":0.0124,"iv":52.1,"instrument_name":"BTC-10MAY24-66000-C","index_price":62995.42,"direction":"sell","amount":1.5}]}}"#;
+    let response: DeribitResponse = serde_json::from_str(trades)?;
+    assert!(response.price_updates().is_empty());
+    let trades = response.trades();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].trade_id, "301234567");
+    assert_eq!(trades[0].side, TradeSide::Sell);
+    assert_eq!((trades[0].price, trades[0].size), (0.0125, 1.5));
+    assert_eq!(trades[0].timestamp, 1715328000123);
+    Ok(())
+}
+
+#[test]
+fn decoding_deribit_ticker() -> anyhow::Result<()> {
+    let ticker = r#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"ticker.BTC-10MAY24-66000-C.100ms","data":{"timestamp":1715328000000,"state":"open","stats":{"volume":12.5,"price_change":null,"low":0.01,"high":0.015},"greeks":{"theta":-63.05,"vega":25.22,"gamma":0.00012,"rho":1.5,"delta":0.3125},"underlying_price":63050.0,"underlying_index":"BTC-10MAY24","mark_price":0.0125,"mark_iv":52.1,"instrument_name":"BTC-10MAY24-66000-C","index_price":62995.42,"interest_rate":0.0,"bid_iv":0.0,"ask_iv":54.0,"best_bid_price":0.0,"best_ask_price":0.013,"best_bid_amount":0.0,"best_ask_amount":5.0,"open_interest":100.0}}}"#;
+    let response: DeribitResponse = serde_json::from_str(ticker)?;
+    assert!(response.asks_bids_pair().is_none());
+    assert!(response.price_updates().is_empty());
+    let summaries = response.option_summaries();
+    assert_eq!(summaries.len(), 1);
+    let summary = &summaries[0];
+    assert_eq!(summary.instrument.strike_price, 66000);
+    assert!((summary.mark_iv - 0.521).abs() < 1e-9);
+    assert_eq!(summary.bid_iv, None);
+    assert!((summary.greeks.delta - 0.3).abs() < 1e-6);
+    assert!((summary.greeks.vega - 0.0004).abs() < 1e-6);
+    Ok(())
+}
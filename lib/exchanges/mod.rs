@@ -1,7 +1,7 @@
 mod okex;
 pub use okex::*;
 mod deribit;
-use crate::{exchanges, trading::Instrument};
+use crate::{analytics::Greeks, exchanges, trading::Instrument};
 pub use deribit::*;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
@@ -12,6 +12,74 @@ pub enum ExchangeType {
     Delibris,
     Okex,
 }
+
+/// trading costs and contract specification of a venue's (coin-margined) options
+#[derive(PartialEq, Debug, Clone)]
+pub struct VenueSpec {
+    pub name: String,
+    pub taker_fee: f32, // fraction of the underlying per contract
+    pub maker_fee: f32,
+    pub premium_fee_cap: Option<f32>, // fees never exceed this fraction of the option price
+    pub contract_size: f32,           // underlying units per contract
+}
+
+impl VenueSpec {
+    pub fn deribit() -> Self {
+        Self {
+            name: "deribit".to_owned(),
+            taker_fee: 0.0003,
+            maker_fee: 0.0003,
+            premium_fee_cap: Some(0.125),
+            contract_size: 1.0,
+        }
+    }
+
+    pub fn okex() -> Self {
+        Self {
+            name: "okex".to_owned(),
+            taker_fee: 0.0003,
+            maker_fee: 0.0002,
+            premium_fee_cap: Some(0.125),
+            contract_size: 0.01,
+        }
+    }
+
+    pub fn for_exchange(name: &str) -> Option<Self> {
+        match name {
+            "deribit" => Some(Self::deribit()),
+            "okex" => Some(Self::okex()),
+            _ => None,
+        }
+    }
+
+    /// taker fee for one unit of the underlying at the given option price, in the premium currency
+    pub fn taker_fee_per_unit(&self, price: f32) -> f32 {
+        let fee = self.taker_fee;
+        match self.premium_fee_cap {
+            Some(cap) => fee.min(cap * price),
+            None => fee,
+        }
+    }
+
+    /// maker fee for one unit of the underlying at the given option price
+    pub fn maker_fee_per_unit(&self, price: f32) -> f32 {
+        let fee = self.maker_fee;
+        match self.premium_fee_cap {
+            Some(cap) => fee.min(cap * price),
+            None => fee,
+        }
+    }
+
+    pub fn to_underlying(&self, contracts: i32) -> f32 {
+        contracts as f32 * self.contract_size
+    }
+
+    /// whole contracts needed for (at most) the given underlying quantity
+    pub fn to_contracts(&self, underlying: f32) -> i32 {
+        // small epsilon so 0.3 / 0.01 isn't floored to 29
+        ((underlying / self.contract_size) + 1e-4).floor() as i32
+    }
+}
 #[derive(Serialize)]
 /// exchange settings to use for connnections
 pub enum Exchanges<'a> {
@@ -50,6 +118,80 @@ type AskBidPairs = (Vec<(f32, f32)>, Vec<(f32, f32)>);
 pub trait Returnable {
     fn asks_bids_pair(&self) -> Option<AskBidPairs>;
     fn instrument_name(&self) -> Option<Instrument>;
+    /// index and mark prices carried by the message, if any
+    fn price_updates(&self) -> Vec<PriceUpdate> {
+        Vec::new()
+    }
+    /// public trades carried by the message, if any
+    fn trades(&self) -> Vec<Trade> {
+        Vec::new()
+    }
+    /// exchange computed implied volatilities and greeks carried by the message, if any
+    fn option_summaries(&self) -> Vec<OptionSummary> {
+        Vec::new()
+    }
+}
+
+/// an exchange's own implied volatility and greeks for an option, common to every exchange.
+/// Vols are fractions (0.52 for 52%) and greeks are in the units of `OptionModel::greeks`
+/// for inverse options: coin delta, gamma per USD, vega and theta in BTC
+#[derive(PartialEq, Debug, Clone)]
+pub struct OptionSummary {
+    pub exchange: String,
+    pub instrument: Instrument,
+    pub mark_iv: f64,
+    pub bid_iv: Option<f64>,
+    pub ask_iv: Option<f64>,
+    pub mark_price: Option<f64>, // premium in BTC
+    pub underlying_price: f64,   // forward the exchange priced the option with
+    pub greeks: Greeks,
+    pub timestamp: u128, // exchange timestamp in ms
+}
+
+/// side of the aggressor (taker) of a trade
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+impl TradeSide {
+    pub fn from_given_str(input_str: &str) -> Option<Self> {
+        match input_str {
+            "buy" => Some(TradeSide::Buy),
+            "sell" => Some(TradeSide::Sell),
+            _ => None,
+        }
+    }
+}
+
+/// a public trade print, common to every exchange
+#[derive(PartialEq, Debug, Clone)]
+pub struct Trade {
+    pub exchange: String,
+    pub instrument: Instrument,
+    pub trade_id: String,
+    pub price: f32,
+    pub size: f32, // in underlying units, converted from the exchange's contracts at decode
+    pub side: TradeSide,
+    pub timestamp: u128, // exchange timestamp in ms
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum PriceKind {
+    Index, // price index of the underlying, e.g. btc_usd
+    Mark,  // exchange mark price of an instrument
+}
+
+/// an index or mark price update, common to every exchange
+#[derive(PartialEq, Debug, Clone)]
+pub struct PriceUpdate {
+    pub exchange: String,
+    pub kind: PriceKind,
+    pub name: String, // index name or instrument name as given by the exchange
+    pub instrument: Option<Instrument>, // only for mark prices
+    pub price: f32,
+    pub timestamp: u128, // exchange timestamp in ms
 }
 
 pub trait MessageExtendable {
@@ -78,6 +220,3 @@ lazy_static! {
         Mutex::new(map)
     };
 }
-
-
-
@@ -2,7 +2,11 @@ use std::any;
 
 use crate::trading::{Instrument, InstrumentType};
 
-use super::{MessageExtendable, Returnable};
+use super::{
+    MessageExtendable, OptionSummary, PriceKind, PriceUpdate, Returnable, Trade, TradeSide,
+    VenueSpec,
+};
+use crate::analytics::Greeks;
 use anyhow::{ensure, Ok};
 use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
@@ -13,18 +17,13 @@ pub struct OkexInitMessage {
 }
 impl Default for OkexInitMessage {
     fn default() -> Self {
-        Self {
-            op: String::from("subscribe"),
-            args: vec![OkexInitMessageArg::default()],
-        }
+        Self::new(OkexInitMessageArg::default().inst_id)
     }
 }
 
 impl MessageExtendable for OkexInitMessage {
     fn add_asset(&mut self, asset: &str) {
-        if let Some(arg) = self.args.get_mut(0) {
-            arg.add_asset(asset)
-        }
+        self.args.iter_mut().for_each(|arg| arg.add_asset(asset))
     }
 
     fn to_json(&self) -> anyhow::Result<String> {
@@ -34,11 +33,18 @@ impl MessageExtendable for OkexInitMessage {
 }
 
 impl OkexInitMessage {
+    /// subscribes to the book and mark price of an instrument and the index of its underlying
     pub fn new<T: AsRef<str>>(inst_id: T) -> Self {
         let stri = inst_id.as_ref();
         Self {
             op: String::from("subscribe"),
-            args: vec![OkexInitMessageArg::new(stri)],
+            args: vec![
+                OkexInitMessageArg::new(stri),
+                OkexInitMessageArg::with_channel("index-tickers", stri),
+                OkexInitMessageArg::with_channel("mark-price", stri),
+                OkexInitMessageArg::with_channel("trades", stri),
+                OkexInitMessageArg::with_channel("opt-summary", stri),
+            ],
         }
     }
 }
@@ -48,13 +54,19 @@ impl Default for OkexInitMessageArg {
         Self {
             channel: "books".to_owned(),
             inst_id: "BTC-USD-240427-56000-C".to_owned(),
+            inst_family: None,
         }
     }
 }
 
 impl MessageExtendable for OkexInitMessageArg {
     fn add_asset(&mut self, asset: &str) {
-        self.inst_id = asset.to_owned();
+        match self.channel.as_str() {
+            "index-tickers" => self.inst_id = index_name_okex(asset),
+            // option summaries are published for a whole family, e.g. BTC-USD
+            "opt-summary" => self.inst_family = Some(index_name_okex(asset)),
+            _ => self.inst_id = asset.to_owned(),
+        }
     }
     fn to_json(&self) -> anyhow::Result<String> {
         let json = serde_json::to_string_pretty(&self)?;
@@ -66,15 +78,34 @@ impl OkexInitMessageArg {
         Self {
             channel: "books".to_owned(),
             inst_id: inst_id.to_owned(),
+            inst_family: None,
         }
     }
+
+    pub fn with_channel(channel: &str, inst_id: &str) -> Self {
+        let mut arg = Self {
+            channel: channel.to_owned(),
+            inst_id: String::new(),
+            inst_family: None,
+        };
+        arg.add_asset(inst_id);
+        arg
+    }
+}
+
+/// the index of an instrument's underlying, BTC-USD-240510-66000-C -> BTC-USD
+pub fn index_name_okex(inst_id: &str) -> String {
+    inst_id.split('-').take(2).collect::<Vec<_>>().join("-")
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct OkexInitMessageArg {
     pub channel: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub inst_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inst_family: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -84,15 +115,69 @@ pub struct OkexResponse {
 }
 
 #[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct OkexResponseData {
+    #[serde(default)]
     pub bids: Vec<Vec<String>>,
+    #[serde(default)]
     pub asks: Vec<Vec<String>>,
+    pub inst_id: Option<String>,
+    pub idx_px: Option<String>,   // index-tickers
+    pub mark_px: Option<String>,  // mark-price
+    pub trade_id: Option<String>, // trades
+    pub px: Option<String>,
+    pub sz: Option<String>,
+    pub side: Option<String>,
+    pub ts: Option<String>,
+    pub mark_vol: Option<String>, // opt-summary
+    pub bid_vol: Option<String>,
+    pub ask_vol: Option<String>,
+    pub fwd_px: Option<String>,
+    pub delta: Option<String>, // coin delta, the other greeks are taken from the USD (BS) ones
+    #[serde(rename = "gammaBS")]
+    pub gamma_bs: Option<String>,
+    #[serde(rename = "vegaBS")]
+    pub vega_bs: Option<String>,
+    #[serde(rename = "thetaBS")]
+    pub theta_bs: Option<String>,
+}
+
+impl OkexResponseData {
+    fn to_summary(&self) -> Option<OptionSummary> {
+        let parse = |value: &Option<String>| -> Option<f64> { value.as_ref()?.parse().ok() };
+        let underlying = parse(&self.fwd_px)?;
+        Some(OptionSummary {
+            exchange: "okex".to_owned(),
+            instrument: string_to_instrument_okex(self.inst_id.as_ref()?).ok()?,
+            mark_iv: parse(&self.mark_vol)?,
+            bid_iv: parse(&self.bid_vol).filter(|iv| *iv > 0.0),
+            ask_iv: parse(&self.ask_vol).filter(|iv| *iv > 0.0),
+            mark_price: None, // comes with the mark-price channel
+            underlying_price: underlying,
+            greeks: Greeks {
+                delta: parse(&self.delta)?,
+                gamma: parse(&self.gamma_bs)?,
+                vega: parse(&self.vega_bs)? / underlying,
+                theta: parse(&self.theta_bs)? / underlying,
+            },
+            timestamp: parse(&self.ts)? as u128,
+        })
+    }
+}
+
+impl OkexResponse {
+    fn channel(&self) -> Option<&str> {
+        self.arg.as_ref().map(|arg| arg.channel.as_str())
+    }
 }
 
 impl Returnable for OkexResponse {
     fn asks_bids_pair(&self) -> Option<super::AskBidPairs> {
+        if self.channel() != Some("books") {
+            return None;
+        }
         if let Some(ref data) = &self.data {
-            if let Some(OkexResponseData { bids, asks }) = data.first() {
+            if let Some(OkexResponseData { bids, asks, .. }) = data.first() {
                 let bid_floats: Vec<_> = bids
                     .iter()
                     .map(|v| {
@@ -118,6 +203,9 @@ impl Returnable for OkexResponse {
     }
 
     fn instrument_name(&self) -> Option<Instrument> {
+        if self.channel() != Some("books") {
+            return None;
+        }
         if let Some(ref data) = &self.arg {
             let asset_name = data.inst_id.clone();
             let instrument =
@@ -126,6 +214,76 @@ impl Returnable for OkexResponse {
         }
         None
     }
+
+    fn price_updates(&self) -> Vec<PriceUpdate> {
+        let kind = match self.channel() {
+            Some("index-tickers") => PriceKind::Index,
+            Some("mark-price") => PriceKind::Mark,
+            _ => return Vec::new(),
+        };
+        let Some(data) = &self.data else {
+            return Vec::new();
+        };
+        data.iter()
+            .filter_map(|entry| {
+                let price = match kind {
+                    PriceKind::Index => entry.idx_px.as_ref()?,
+                    PriceKind::Mark => entry.mark_px.as_ref()?,
+                };
+                let name = entry.inst_id.clone()?;
+                let instrument = match kind {
+                    PriceKind::Mark => string_to_instrument_okex(&name).ok(),
+                    PriceKind::Index => None,
+                };
+                Some(PriceUpdate {
+                    exchange: "okex".to_owned(),
+                    kind: kind.clone(),
+                    name,
+                    instrument,
+                    price: price.parse().ok()?,
+                    timestamp: entry
+                        .ts
+                        .as_ref()
+                        .and_then(|ts| ts.parse().ok())
+                        .unwrap_or(0),
+                })
+            })
+            .collect()
+    }
+
+    fn trades(&self) -> Vec<Trade> {
+        if self.channel() != Some("trades") {
+            return Vec::new();
+        }
+        let Some(data) = &self.data else {
+            return Vec::new();
+        };
+        data.iter()
+            .filter_map(|entry| {
+                Some(Trade {
+                    exchange: "okex".to_owned(),
+                    instrument: string_to_instrument_okex(entry.inst_id.as_ref()?).ok()?,
+                    trade_id: entry.trade_id.clone()?,
+                    price: entry.px.as_ref()?.parse().ok()?,
+                    size: entry.sz.as_ref()?.parse::<f32>().ok()? * VenueSpec::okex().contract_size,
+                    side: TradeSide::from_given_str(entry.side.as_ref()?)?,
+                    timestamp: entry.ts.as_ref()?.parse().ok()?,
+                })
+            })
+            .collect()
+    }
+
+    fn option_summaries(&self) -> Vec<OptionSummary> {
+        if self.channel() != Some("opt-summary") {
+            return Vec::new();
+        }
+        let Some(data) = &self.data else {
+            return Vec::new();
+        };
+        data.iter()
+            .filter_map(OkexResponseData::to_summary)
+            .collect()
+    }
 }
 
 pub fn string_to_instrument_okex(asset: &str) -> Result<crate::trading::Instrument, anyhow::Error> {
@@ -179,3 +337,58 @@ fn parsing_between_string_and_instrument_okex_works() -> anyhow::Result<()> {
     assert_eq!(instr_to_str, asset);
     Ok(())
 }
+
+#[test]
+fn decoding_okex_index_and_mark_prices() -> anyhow::Result<()> {
+    let index = r#"{"arg":{"channel":"index-tickers","instId":"BTC-USD"},"data":[{"instId":"BTC-USD","idxPx":"62995.4","high24h":"63500","low24h":"62000","open24h":"62100","sodUtc0":"62500","sodUtc8":"62400","ts":"1715328000000"}]}"#;
+    let response: OkexResponse = serde_json::from_str(index)?;
+    assert!(response.asks_bids_pair().is_none());
+    assert!(response.instrument_name().is_none());
+    let updates = response.price_updates();
+    assert_eq!(updates[0].kind, PriceKind::Index);
+    assert_eq!(updates[0].name, "BTC-USD");
+    assert_eq!(updates[0].price, 62995.4);
+    assert_eq!(updates[0].timestamp, 1715328000000);
+
+    let mark = r#"{"arg":{"channel":"mark-price","instId":"BTC-USD-240510-66000-C"},"data":[{"instType":"OPTION","instId":"BTC-USD-240510-66000-C","markPx":"0.0126","ts":"1715328000000"}]}"#;
+    let response: OkexResponse = serde_json::from_str(mark)?;
+    let updates = response.price_updates();
+    assert_eq!(updates[0].kind, PriceKind::Mark);
+    assert_eq!(updates[0].instrument.as_ref().unwrap().asset, "BTC-USD");
+
+    let subscription = OkexInitMessage::new("BTC-USD-240510-66000-C").to_json()?;
+    assert!(subscription.contains("\"index-tickers\"") && subscription.contains("\"BTC-USD\""));
+    Ok(())
+}
+
+#[test]
+fn decoding_okex_trades() -> anyhow::Result<()> {
+    let trades = r#"{"arg":{"channel":"trades","instId":"BTC-USD-240510-66000-C"},"data":[{"instId":"BTC-USD-240510-66000-C","tradeId":"130639474","px":"0.0125","sz":"10","side":"buy","ts":"1715328000123"}]}"#;
+    let response: OkexResponse = serde_json::from_str(trades)?;
+    assert!(response.asks_bids_pair().is_none());
+    let trades = response.trades();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].instrument.asset, "BTC-USD");
+    assert_eq!(trades[0].side, TradeSide::Buy);
+    // 10 contracts of 0.01 BTC
+    assert_eq!(trades[0].price, 0.0125);
+    assert!((trades[0].size - 0.1).abs() < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn decoding_okex_option_summaries() -> anyhow::Result<()> {
+    let summary = r#"{"arg":{"channel":"opt-summary","instFamily":"BTC-USD"},"data":[{"instType":"OPTION","instId":"BTC-USD-240510-66000-C","uly":"BTC-USD","delta":"0.2998","gamma":"1.21","vega":"0.0004","theta":"-0.001","lever":"80","markVol":"0.521","bidVol":"0.5","askVol":"0.54","realVol":"","volLv":"0.52","deltaBS":"0.3125","gammaBS":"0.00012","thetaBS":"-63.05","vegaBS":"25.22","fwdPx":"63050","ts":"1715328000000"}]}"#;
+    let response: OkexResponse = serde_json::from_str(summary)?;
+    assert!(response.asks_bids_pair().is_none());
+    assert!(response.instrument_name().is_none());
+    let summaries = response.option_summaries();
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].instrument.asset, "BTC-USD");
+    assert_eq!(summaries[0].bid_iv, Some(0.5));
+    assert!((summaries[0].greeks.vega - 0.0004).abs() < 1e-6);
+
+    let subscription = OkexInitMessage::new("BTC-USD-240510-66000-C").to_json()?;
+    assert!(subscription.contains("\"instFamily\": \"BTC-USD\""));
+    Ok(())
+}
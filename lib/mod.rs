@@ -1,5 +1,8 @@
 #![allow(dead_code, unused)]
+pub mod analytics;
+pub mod backtest;
 pub mod config;
 pub mod exchanges;
+pub mod strategy;
 pub mod trading;
 pub mod utils;
\ No newline at end of file
@@ -0,0 +1,2 @@
+mod runtime;
+pub use runtime::*;
@@ -0,0 +1,655 @@
+use crate::backtest::{MarketEvent, MarketEventKind};
+use crate::exchanges::Trade;
+use crate::trading::{
+    ConsolidatedBook, Instrument, KillSwitch, KillSwitchConfig, MarkSource, MatchedOrders, Order,
+    OrderBook, PaperAccount, PriceColumns, RiskRejection, SharedKillSwitch, TradeRequest,
+};
+use crate::utils::{
+    apply_recorded_frame, is_recording, record_frame, FrameSource, RecordedFrame, ReplaySource,
+    ReplayStats, SharedClock,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::{mpsc, Mutex};
+
+/// rounds of fills and follow up orders handled after one event, so strategies cannot spin forever
+const MAX_SETTLE_ROUNDS: usize = 8;
+
+/// A trading strategy hosted by the `StrategyRuntime`. Orders are sent through the context
+/// and reach the venue once the callback returns. The runtime may run on a task of its own,
+/// so strategies are `Send`
+pub trait Strategy: Send {
+    fn on_book_update(
+        &mut self,
+        _exchange: &str,
+        _instrument: &Instrument,
+        _columns: &PriceColumns,
+        _ctx: &mut StrategyContext,
+    ) {
+    }
+    fn on_trade(&mut self, _trade: &Trade, _ctx: &mut StrategyContext) {}
+    fn on_fill(&mut self, _order: &Order, _fill: &MatchedOrders, _ctx: &mut StrategyContext) {}
+    /// an order that failed the account's risk checks, it never reached the venue
+    fn on_reject(&mut self, _order: &Order, _reason: &RiskRejection, _ctx: &mut StrategyContext) {}
+    fn on_timer(&mut self, _now: u128, _ctx: &mut StrategyContext) {}
+    /// how often `on_timer` is called, never when None
+    fn timer_interval_ms(&self) -> Option<u128> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StrategyCommand {
+    Submit {
+        exchange: String,
+        instrument: Instrument,
+        order: Order,
+    },
+    Cancel {
+        exchange: String,
+        instrument: Instrument,
+        order_id: u128,
+    },
+}
+
+/// what a strategy can see and do from inside a callback
+pub struct StrategyContext {
+    clock: SharedClock,
+    commands: Vec<StrategyCommand>,
+}
+
+impl StrategyContext {
+    fn new(clock: SharedClock) -> Self {
+        Self {
+            clock,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn now(&self) -> u128 {
+        self.clock.now_ms()
+    }
+
+    /// sends a limit order to a venue, returns its id
+    pub fn submit(
+        &mut self,
+        exchange: &str,
+        instrument: &Instrument,
+        request: TradeRequest,
+        price: f32,
+        quantity: i32,
+    ) -> u128 {
+        let order = Order::new_at(price, quantity, request, self.clock.as_ref()).with_tag(exchange);
+        let id = order.id;
+        self.commands.push(StrategyCommand::Submit {
+            exchange: exchange.to_owned(),
+            instrument: instrument.clone(),
+            order,
+        });
+        id
+    }
+
+    pub fn cancel(&mut self, exchange: &str, instrument: &Instrument, order_id: u128) {
+        self.commands.push(StrategyCommand::Cancel {
+            exchange: exchange.to_owned(),
+            instrument: instrument.clone(),
+            order_id,
+        });
+    }
+}
+
+struct HostedStrategy<'a> {
+    strategy: Box<dyn Strategy + 'a>,
+    account: PaperAccount<'a>, // owns the strategy's orders and positions
+    commands: Vec<StrategyCommand>,
+    next_timer: Option<u128>,
+}
+
+impl<'a> HostedStrategy<'a> {
+    async fn apply(
+        &mut self,
+        command: StrategyCommand,
+        venues: &ConsolidatedBook<'a>,
+        clock: &SharedClock,
+        kill_switch: &SharedKillSwitch,
+    ) {
+        match command {
+            StrategyCommand::Submit {
+                exchange,
+                instrument,
+                order,
+            } => {
+                let halted = {
+                    let mut switch = kill_switch.lock().unwrap();
+                    if switch.blocked(&instrument).is_none() {
+                        switch.record_order();
+                    }
+                    let halted = switch.blocked(&instrument).cloned();
+                    if halted.is_some() {
+                        switch.record_blocked(&instrument, order.id);
+                    }
+                    halted
+                };
+                let placed = match (halted, venues.book(&exchange).await) {
+                    (Some(reason), _) => Err(RiskRejection::Halted(reason)),
+                    (None, Some(venue)) => {
+                        let venue = venue.lock().await;
+                        self.account
+                            .place(&exchange, &instrument, order.clone(), &venue)
+                    }
+                    (None, None) => Err(RiskRejection::UnknownVenue(exchange.clone())),
+                };
+                if let Err(reason) = placed {
+                    let mut ctx = StrategyContext::new(clock.clone());
+                    self.strategy.on_reject(&order, &reason, &mut ctx);
+                    self.commands.extend(ctx.commands);
+                }
+            }
+            StrategyCommand::Cancel {
+                exchange,
+                instrument,
+                order_id,
+            } => {
+                self.account.cancel(&exchange, &instrument, order_id);
+            }
+        }
+    }
+}
+
+/// Hosts any number of strategies on the venue books of a `ConsolidatedBook`, fed by live
+/// connections or a replay. Strategy orders are matched against the venue they were sent to,
+/// unless the kill switch blocks them
+pub struct StrategyRuntime<'a> {
+    pub venues: ConsolidatedBook<'a>,
+    pub kill_switch: SharedKillSwitch,
+    clock: SharedClock,
+    hosted: Vec<HostedStrategy<'a>>,
+}
+
+impl<'a> StrategyRuntime<'a> {
+    pub fn new(venues: ConsolidatedBook<'a>, clock: SharedClock) -> Self {
+        Self {
+            venues,
+            kill_switch: KillSwitch::new(KillSwitchConfig::default(), clock.clone()).shared(),
+            clock,
+            hosted: Vec::new(),
+        }
+    }
+
+    /// shares a kill switch with e.g. the operator console
+    pub fn with_kill_switch(mut self, kill_switch: SharedKillSwitch) -> Self {
+        self.kill_switch = kill_switch;
+        self
+    }
+
+    /// `name` tags the strategy's orders and the fills it leaves on the venue books
+    pub fn add_strategy(&mut self, name: &'a str, strategy: Box<dyn Strategy + 'a>) {
+        let next_timer = strategy
+            .timer_interval_ms()
+            .map(|interval| self.clock.now_ms() + interval);
+        self.hosted.push(HostedStrategy {
+            strategy,
+            account: PaperAccount::new(name, self.clock.clone()),
+            commands: Vec::new(),
+            next_timer,
+        });
+    }
+
+    /// the account holding a strategy's orders, positions and balances
+    pub fn account(&self, name: &str) -> Option<&PaperAccount<'a>> {
+        self.hosted
+            .iter()
+            .find(|hosted| hosted.account.name == name)
+            .map(|hosted| &hosted.account)
+    }
+
+    /// e.g. to set the risk limits of a strategy
+    pub fn account_mut(&mut self, name: &str) -> Option<&mut PaperAccount<'a>> {
+        self.hosted
+            .iter_mut()
+            .find(|hosted| hosted.account.name == name)
+            .map(|hosted| &mut hosted.account)
+    }
+
+    /// applies a frame to its venue book through the usual decoders, then lets every
+    /// strategy react to the book updates and trades it carried
+    pub async fn process_frame(&mut self, frame: &RecordedFrame) -> anyhow::Result<()> {
+        self.dispatch_frame(frame).await?;
+        self.settle().await
+    }
+
+    /// decodes a frame into its venue book and hands its events to the strategies
+    async fn dispatch_frame(&mut self, frame: &RecordedFrame) -> anyhow::Result<()> {
+        let Some(venue) = self.venues.book(&frame.exchange).await else {
+            return Ok(());
+        };
+        apply_recorded_frame(frame, venue.clone()).await?;
+        self.kill_switch
+            .lock()
+            .unwrap()
+            .observe_data(&frame.exchange);
+
+        for event in MarketEvent::from_frame(frame) {
+            match &event.kind {
+                MarketEventKind::Book { .. } => {
+                    let venue = venue.lock().await;
+                    let Some(columns) = venue.table_for(&event.instrument) else {
+                        continue;
+                    };
+                    for hosted in self.hosted.iter_mut() {
+                        let mut ctx = StrategyContext::new(self.clock.clone());
+                        hosted.strategy.on_book_update(
+                            &frame.exchange,
+                            &event.instrument,
+                            columns,
+                            &mut ctx,
+                        );
+                        hosted.commands.extend(ctx.commands);
+                    }
+                }
+                MarketEventKind::Trade(trade) => {
+                    for hosted in self.hosted.iter_mut() {
+                        let mut ctx = StrategyContext::new(self.clock.clone());
+                        hosted.strategy.on_trade(trade, &mut ctx);
+                        hosted.commands.extend(ctx.commands);
+                    }
+                }
+            }
+        }
+        self.fire_timers();
+        Ok(())
+    }
+
+    /// fires the timers that are due, for when no frames arrive
+    pub async fn tick(&mut self) -> anyhow::Result<()> {
+        self.fire_timers();
+        self.settle().await
+    }
+
+    fn fire_timers(&mut self) {
+        let now = self.clock.now_ms();
+        for hosted in self.hosted.iter_mut() {
+            let (Some(due), Some(interval)) =
+                (hosted.next_timer, hosted.strategy.timer_interval_ms())
+            else {
+                continue;
+            };
+            if due > now {
+                continue;
+            }
+            let mut ctx = StrategyContext::new(self.clock.clone());
+            hosted.strategy.on_timer(now, &mut ctx);
+            hosted.commands.extend(ctx.commands);
+            hosted.next_timer = Some(now + interval.max(1));
+        }
+    }
+
+    /// sends the orders strategies asked for, matches them against their venue and hands out
+    /// the fills, which may lead to more orders. A venue that fails to match does not stop the
+    /// others, its error is returned once everything settled
+    async fn settle(&mut self) -> anyhow::Result<()> {
+        let mut failure = None;
+        for _ in 0..MAX_SETTLE_ROUNDS {
+            self.enforce_kill_switch();
+            let mut pending = false;
+            for hosted in self.hosted.iter_mut() {
+                for command in std::mem::take(&mut hosted.commands) {
+                    hosted
+                        .apply(command, &self.venues, &self.clock, &self.kill_switch)
+                        .await;
+                }
+                let mut fills = Vec::new();
+                for venue in &self.venues.books {
+                    let exchange = venue.lock().await.exchange;
+                    match hosted.account.match_against(exchange, venue.clone()).await {
+                        Ok(matched) => fills.extend(matched),
+                        Err(err) => {
+                            failure.get_or_insert(err);
+                        }
+                    }
+                }
+                for fill in fills {
+                    let mut ctx = StrategyContext::new(self.clock.clone());
+                    hosted.strategy.on_fill(&fill.order, &fill.fill, &mut ctx);
+                    hosted.commands.extend(ctx.commands);
+                }
+                pending |= !hosted.commands.is_empty();
+            }
+            if !pending {
+                break;
+            }
+        }
+        self.check_kill_triggers().await;
+        self.enforce_kill_switch();
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// trips the kill switch on stale or silent venues or on an account past the loss limit
+    async fn check_kill_triggers(&mut self) {
+        let mut venues = Vec::with_capacity(self.venues.books.len());
+        for venue in &self.venues.books {
+            venues.push(venue.lock().await);
+        }
+        let venues: Vec<&OrderBook> = venues.iter().map(|venue| &**venue).collect();
+        let mut switch = self.kill_switch.lock().unwrap();
+        for venue in &venues {
+            switch.watch(venue.exchange);
+        }
+        switch.check_stale();
+        for hosted in &self.hosted {
+            switch.check_loss(hosted.account.pnl(&venues, MarkSource::Midprice));
+        }
+    }
+
+    /// cancels the open orders of every strategy in a tripped scope
+    fn enforce_kill_switch(&mut self) {
+        let mut switch = self.kill_switch.lock().unwrap();
+        if switch.tripped().is_empty() {
+            return;
+        }
+        for hosted in self.hosted.iter_mut() {
+            let cancelled = hosted
+                .account
+                .cancel_where(|instrument| switch.blocked(instrument).is_some());
+            for (instrument, order) in cancelled {
+                switch.record_cancel(&instrument, order.id);
+            }
+        }
+    }
+
+    /// Runs on frames forwarded by `forward_frames` until every sender is gone, firing the
+    /// timers every `tick` in between. Failures are counted like `run_replay` does
+    pub async fn run(
+        &mut self,
+        mut frames: mpsc::Receiver<RecordedFrame>,
+        tick: Duration,
+    ) -> ReplayStats {
+        let mut stats = ReplayStats::default();
+        let mut ticker = tokio::time::interval(tick);
+        loop {
+            select! {
+                frame = frames.recv() => {
+                    let Some(frame) = frame else {
+                        break;
+                    };
+                    self.process_counted(&frame, &mut stats).await;
+                }
+                _ = ticker.tick() => {
+                    if self.tick().await.is_err() {
+                        stats.match_errors += 1;
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    /// plays a recording through the strategies, in order and as fast as the source allows
+    pub async fn run_replay(&mut self, source: &mut ReplaySource) -> ReplayStats {
+        let mut stats = ReplayStats::default();
+        while let Some(frame) = source.next_recorded().await {
+            self.process_counted(&frame, &mut stats).await;
+        }
+        stats.record_source(source);
+        stats
+    }
+
+    /// processes a frame, counting it and whatever went wrong in `stats`
+    async fn process_counted(&mut self, frame: &RecordedFrame, stats: &mut ReplayStats) {
+        stats.frames += 1;
+        if self.venues.book(&frame.exchange).await.is_none() {
+            stats.skipped += 1;
+            return;
+        }
+        if self.dispatch_frame(frame).await.is_err() {
+            stats.decode_errors += 1;
+            return;
+        }
+        if self.settle().await.is_err() {
+            stats.match_errors += 1;
+        }
+    }
+}
+
+/// reads a live source and forwards its frames to a `StrategyRuntime`, stamped with `clock`
+pub async fn forward_frames(
+    exchange: &str,
+    source: Arc<Mutex<impl FrameSource>>,
+    clock: SharedClock,
+    frames: mpsc::Sender<RecordedFrame>,
+) -> anyhow::Result<()> {
+    while let Some(frame) = source.lock().await.next_frame().await {
+        if is_recording() {
+            record_frame(exchange, &frame)?;
+        }
+        let frame = RecordedFrame {
+            received_at: clock.now_ms(),
+            exchange: exchange.to_owned(),
+            frame,
+        };
+        if frames.send(frame).await.is_err() {
+            break; // the runtime is gone
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchanges::ExchangeType;
+    use crate::trading::{AuditAction, KillScope, OrderBook, OrderStatus};
+    use crate::utils::{ReplayClock, ReplaySpeed};
+
+    /// buys through the deribit ask, rests a bid below it and pulls that one on the first timer
+    #[derive(Default)]
+    struct Probe {
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+        resting: Option<(Instrument, u128)>,
+    }
+
+    impl Strategy for Probe {
+        fn on_book_update(
+            &mut self,
+            exchange: &str,
+            instrument: &Instrument,
+            columns: &PriceColumns,
+            ctx: &mut StrategyContext,
+        ) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("book {exchange} {}", columns.midprice));
+            if self.resting.is_none() {
+                ctx.submit(exchange, instrument, TradeRequest::Bid, 0.015, 2);
+                let id = ctx.submit(exchange, instrument, TradeRequest::Bid, 0.005, 1);
+                self.resting = Some((instrument.clone(), id));
+            }
+        }
+
+        fn on_trade(&mut self, trade: &Trade, _ctx: &mut StrategyContext) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("trade {}", trade.price));
+        }
+
+        fn on_fill(&mut self, order: &Order, fill: &MatchedOrders, ctx: &mut StrategyContext) {
+            self.log.lock().unwrap().push(format!(
+                "fill {} {}@{} at {}",
+                order.id,
+                fill.quantity,
+                fill.price,
+                ctx.now()
+            ));
+        }
+
+        fn on_timer(&mut self, now: u128, ctx: &mut StrategyContext) {
+            self.log.lock().unwrap().push(format!("timer {now}"));
+            if let Some((instrument, id)) = self.resting.as_ref() {
+                ctx.cancel("deribit", instrument, *id);
+            }
+        }
+
+        fn timer_interval_ms(&self) -> Option<u128> {
+            Some(150)
+        }
+    }
+
+    fn frame(received_at: u128, frame: &str) -> RecordedFrame {
+        RecordedFrame {
+            received_at,
+            exchange: "deribit".to_owned(),
+            frame: frame.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn strategies_trade_against_replayed_books() {
+        let frames = vec![
+            frame(
+                1_000,
+                r#"{"params":{"channel":"book.BTC-10MAY24-66000-C.none.20.100ms","data":{"instrument_name":"BTC-10MAY24-66000-C","bids":[[0.0130,5.0]],"asks":[[0.0150,5.0]]}}}"#,
+            ),
+            frame(
+                1_050,
+                r#"{"params":{"channel":"trades.BTC-10MAY24-66000-C.100ms","data":[{"trade_id":"301234567","timestamp":1715328000123,"price":0.0125,"instrument_name":"BTC-10MAY24-66000-C","direction":"sell","amount":1.5}]}}"#,
+            ),
+            frame(
+                1_200,
+                r#"{"params":{"channel":"deribit_price_index.btc_usd","data":{"timestamp":1715328000000,"price":62995.42,"index_name":"btc_usd"}}}"#,
+            ),
+            frame(1_300, "{truncated"),
+        ];
+        let clock = Arc::new(ReplayClock::default());
+        clock.observe(1_000);
+        let deribit = Arc::new(Mutex::new(OrderBook::with_clock("deribit", clock.clone())));
+        let mut runtime =
+            StrategyRuntime::new(ConsolidatedBook::new(vec![deribit.clone()]), clock.clone());
+        let probe = Probe::default();
+        let log = probe.log.clone();
+        runtime.add_strategy("probe", Box::new(probe));
+
+        let mut source =
+            ReplaySource::from_frames(frames, ReplaySpeed::AsFastAsPossible).with_clock(clock);
+        let stats = runtime.run_replay(&mut source).await;
+        assert_eq!(stats.frames, 4);
+        assert_eq!(stats.decode_errors, 1);
+
+        let account = runtime.account("probe").unwrap();
+        let orders = account.orders("deribit");
+        assert_eq!(orders.len(), 2);
+        let taker = orders.iter().find(|order| order.price == 0.015).unwrap();
+        let resting = orders.iter().find(|order| order.price == 0.005).unwrap();
+        assert!(taker.is_completed());
+        assert_eq!(resting.status, OrderStatus::Cancelled);
+
+        let log = log.lock().unwrap().clone();
+        assert_eq!(
+            log,
+            vec![
+                "book deribit 0.014".to_owned(),
+                format!("fill {} 2@0.015 at 1000", taker.id),
+                "trade 0.0125".to_owned(),
+                "timer 1200".to_owned(),
+            ]
+        );
+
+        // the strategy's fill took liquidity from the venue
+        let venue = deribit.lock().await;
+        let instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)
+                .unwrap();
+        let asks = &venue.table_for(&instrument).unwrap().asks;
+        assert_eq!(asks.values().next().unwrap().total_quantity, 3);
+    }
+
+    /// rests one bid per book update and logs what the runtime does with it
+    #[derive(Default)]
+    struct Quoter {
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl Strategy for Quoter {
+        fn on_book_update(
+            &mut self,
+            exchange: &str,
+            instrument: &Instrument,
+            _columns: &PriceColumns,
+            ctx: &mut StrategyContext,
+        ) {
+            ctx.submit(exchange, instrument, TradeRequest::Bid, 0.005, 1);
+        }
+
+        fn on_reject(
+            &mut self,
+            _order: &Order,
+            reason: &RiskRejection,
+            _ctx: &mut StrategyContext,
+        ) {
+            self.log.lock().unwrap().push(reason.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn kill_switch_cancels_and_blocks_strategy_orders() -> anyhow::Result<()> {
+        let book = |received_at: u128| {
+            frame(
+                received_at,
+                r#"{"params":{"channel":"book.BTC-10MAY24-66000-C.none.20.100ms","data":{"instrument_name":"BTC-10MAY24-66000-C","bids":[[0.0130,5.0]],"asks":[[0.0150,5.0]]}}}"#,
+            )
+        };
+        let clock = Arc::new(ReplayClock::default());
+        let deribit = Arc::new(Mutex::new(OrderBook::with_clock("deribit", clock.clone())));
+        let kill_switch = KillSwitch::new(
+            KillSwitchConfig {
+                max_data_age_ms: Some(1_000),
+                ..Default::default()
+            },
+            clock.clone(),
+        )
+        .shared();
+        let mut runtime =
+            StrategyRuntime::new(ConsolidatedBook::new(vec![deribit.clone()]), clock.clone())
+                .with_kill_switch(kill_switch.clone());
+        let quoter = Quoter::default();
+        let log = quoter.log.clone();
+        runtime.add_strategy("quoter", Box::new(quoter));
+
+        clock.observe(1_000);
+        runtime.process_frame(&book(1_000)).await?;
+        clock.observe(1_100);
+        runtime.process_frame(&book(1_100)).await?;
+        let account = runtime.account("quoter").unwrap();
+        assert_eq!(account.open_orders("deribit").len(), 2);
+
+        // the feed goes quiet
+        clock.observe(2_200);
+        runtime.tick().await?;
+        let account = runtime.account("quoter").unwrap();
+        assert!(account.open_orders("deribit").is_empty());
+        assert_eq!(account.orders("deribit").len(), 2);
+        runtime.process_frame(&book(2_200)).await?;
+        assert_eq!(
+            log.lock().unwrap().clone(),
+            vec!["trading is halted, no data from deribit for 1100ms, the limit is 1000ms"]
+        );
+
+        let cancels = kill_switch
+            .lock()
+            .unwrap()
+            .audit
+            .iter()
+            .filter(|event| matches!(event.action, AuditAction::Cancelled { .. }))
+            .count();
+        assert_eq!(cancels, 2);
+
+        // once resumed the strategy trades again
+        kill_switch.lock().unwrap().reset(KillScope::Global);
+        runtime.process_frame(&book(2_300)).await?;
+        let account = runtime.account("quoter").unwrap();
+        assert_eq!(account.open_orders("deribit").len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+use super::{
+    Instrument, MatchedOrders, Order, OrderBook, Position, RiskLimits, RiskRejection, RiskState,
+    VenueKey,
+};
+use crate::analytics::Greeks;
+use crate::exchanges::VenueSpec;
+use crate::utils::SharedClock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// what unrealized pnl is marked to
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub enum MarkSource {
+    #[default]
+    Midprice,
+    MarkPrice, // the venue's mark, the midprice until one arrives
+}
+
+/// a fill of one of the account's orders, as applied to its positions and balances
+#[derive(PartialEq, Debug, Clone)]
+pub struct AccountFill {
+    pub exchange: String, // venue the order was sent to
+    pub instrument: Instrument,
+    pub order: Order, // the order after the fill
+    pub fill: MatchedOrders,
+    pub fee: f32, // in the settlement currency
+}
+
+/// A simulated account. It owns the orders it places, one book per venue so they only match
+/// against the venue they were sent to, and tracks positions per venue and (canonical)
+/// instrument and balances per settlement currency from their fills. Options are coin-margined, so premiums
+/// and fees settle in the underlying
+pub struct PaperAccount<'a> {
+    pub name: &'a str,
+    pub positions: HashMap<VenueKey, Position>,
+    pub balances: HashMap<String, f32>,
+    pub venues: HashMap<String, VenueSpec>,
+    pub risk: RiskLimits,
+    pub rejections: Vec<(Order, RiskRejection)>,
+    books: HashMap<String, OrderBook<'a>>,
+    applied_fills: HashMap<u128, usize>, // fills of each order already applied
+    clock: SharedClock,
+}
+
+impl<'a> PaperAccount<'a> {
+    /// `name` tags the fills the account leaves on venue books
+    pub fn new(name: &'a str, clock: SharedClock) -> Self {
+        Self {
+            name,
+            positions: HashMap::new(),
+            balances: HashMap::new(),
+            venues: [VenueSpec::deribit(), VenueSpec::okex()]
+                .into_iter()
+                .map(|venue| (venue.name.clone(), venue))
+                .collect(),
+            risk: RiskLimits::default(),
+            rejections: Vec::new(),
+            books: HashMap::new(),
+            applied_fills: HashMap::new(),
+            clock,
+        }
+    }
+
+    pub fn deposit(&mut self, currency: &str, amount: f32) {
+        *self.balances.entry(currency.to_owned()).or_default() += amount;
+    }
+
+    pub fn balance(&self, currency: &str) -> f32 {
+        self.balances.get(currency).copied().unwrap_or_default()
+    }
+
+    /// Puts an order on the account's book for a venue, returns its id. The book checks it
+    /// against the account's limits, with positions and greeks from `venue`. Rejected orders are
+    /// kept in `rejections` and never reach the book
+    pub fn place(
+        &mut self,
+        exchange: &str,
+        instrument: &Instrument,
+        order: Order,
+        venue: &OrderBook,
+    ) -> Result<u128, RiskRejection> {
+        let quantity = self.to_underlying(exchange, order.quantity);
+        let state = self.risk_state(exchange, instrument, venue);
+        let name = self.name;
+        let clock = self.clock.clone();
+        let book = self
+            .books
+            .entry(exchange.to_owned())
+            .or_insert_with(|| OrderBook::with_clock(name, clock));
+        book.risk = self.risk.clone();
+        book.add_asset(instrument.clone());
+        let id = order.id;
+        if let Err(rejection) = book.add_order_checked(order.clone(), instrument, quantity, &state)
+        {
+            self.rejections.push((order, rejection.clone()));
+            return Err(rejection);
+        }
+        Ok(id)
+    }
+
+    fn to_underlying(&self, exchange: &str, contracts: i32) -> f32 {
+        self.venues
+            .get(exchange)
+            .map_or(contracts as f32, |venue| venue.to_underlying(contracts))
+    }
+
+    /// what the risk checks of an order on `instrument` see
+    pub fn risk_state(
+        &self,
+        exchange: &str,
+        instrument: &Instrument,
+        venue: &OrderBook,
+    ) -> RiskState {
+        let open: Vec<Order> = self
+            .books
+            .get(exchange)
+            .and_then(|book| book.table_for(instrument))
+            .map(|table| {
+                let orders = table.orders.lock().unwrap();
+                orders
+                    .iter()
+                    .filter(|order| order.is_open())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        let open_qty = |is_ask: bool| -> f32 {
+            open.iter()
+                .filter(|order| order.request.is_ask() == is_ask)
+                .map(|order| self.to_underlying(exchange, order.remaining_qty))
+                .sum()
+        };
+        RiskState {
+            midprice: self.mark(venue, instrument, MarkSource::Midprice),
+            open_orders: open.len(),
+            position: self.net_quantity(instrument),
+            open_bids: open_qty(false),
+            open_asks: open_qty(true),
+            portfolio_greeks: self.greeks(venue),
+            instrument_greeks: venue
+                .option_summary(instrument)
+                .map(|summary| summary.greeks),
+        }
+    }
+
+    /// Greeks of all positions on every venue, per this venue's latest option summaries. None
+    /// when a position is held in an instrument the venue has no summary for
+    pub fn greeks(&self, venue: &OrderBook) -> Option<Greeks> {
+        let mut total = Greeks::default();
+        for ((_, instrument), position) in self.positions.iter() {
+            if position.is_flat() {
+                continue;
+            }
+            let summary = venue.option_summary(instrument)?;
+            total += summary.greeks.scaled(position.quantity as f64);
+        }
+        Some(total)
+    }
+
+    pub fn cancel(
+        &mut self,
+        exchange: &str,
+        instrument: &Instrument,
+        order_id: u128,
+    ) -> Option<Order> {
+        self.books
+            .get_mut(exchange)?
+            .cancel_order(instrument, order_id)
+    }
+
+    /// cancels the open orders on instruments matching `filter`, on every venue
+    pub fn cancel_where(
+        &mut self,
+        filter: impl Fn(&Instrument) -> bool,
+    ) -> Vec<(Instrument, Order)> {
+        let mut cancelled = Vec::new();
+        for book in self.books.values_mut() {
+            let open: Vec<(Instrument, u128)> = book
+                .asset_order_table
+                .iter()
+                .filter(|(instrument, _)| filter(instrument))
+                .flat_map(|(instrument, table)| {
+                    let orders = table.orders.lock().unwrap();
+                    orders
+                        .iter()
+                        .filter(|order| order.is_open())
+                        .map(|order| (instrument.clone(), order.id))
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            for (instrument, order_id) in open {
+                if let Some(order) = book.cancel_order(&instrument, order_id) {
+                    cancelled.push((instrument, order));
+                }
+            }
+        }
+        cancelled
+    }
+
+    /// open and closed orders sent to a venue
+    pub fn orders(&self, exchange: &str) -> Vec<Order> {
+        let Some(book) = self.books.get(exchange) else {
+            return Vec::new();
+        };
+        book.asset_order_table
+            .values()
+            .flat_map(|table| {
+                let open: Vec<Order> = table.orders.lock().unwrap().iter().cloned().collect();
+                table.history.iter().cloned().chain(open)
+            })
+            .collect()
+    }
+
+    pub fn open_orders(&self, exchange: &str) -> Vec<Order> {
+        self.orders(exchange)
+            .into_iter()
+            .filter(|order| order.is_open())
+            .collect()
+    }
+
+    /// Matches the open orders sent to `exchange` against its book and applies the fills.
+    /// Fills are applied even when matching fails, the error is returned after
+    pub async fn match_against(
+        &mut self,
+        exchange: &str,
+        venue: Arc<Mutex<OrderBook<'a>>>,
+    ) -> anyhow::Result<Vec<AccountFill>> {
+        let Some(book) = self.books.get_mut(exchange) else {
+            return Ok(Vec::new());
+        };
+        let instruments: Vec<Instrument> = book
+            .asset_order_table
+            .iter()
+            .filter(|(_, table)| {
+                table
+                    .orders
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|order| order.is_open())
+            })
+            .map(|(instrument, _)| instrument.clone())
+            .collect();
+        let mut failure = None;
+        for instrument in instruments {
+            if let Err(err) = book.match_orders(&instrument, venue.clone()).await {
+                failure.get_or_insert(err);
+            }
+        }
+        let fills = self.apply_new_fills(exchange);
+        failure.map_or(Ok(fills), Err)
+    }
+
+    /// applies fills of orders on the venue's book that weren't applied yet, e.g. after
+    /// `match_orders` was called on it directly
+    fn apply_new_fills(&mut self, exchange: &str) -> Vec<AccountFill> {
+        let Some(book) = self.books.get(exchange) else {
+            return Vec::new();
+        };
+        let mut fills = Vec::new();
+        for (instrument, table) in book.asset_order_table.iter() {
+            let open: Vec<Order> = table.orders.lock().unwrap().iter().cloned().collect();
+            for order in table.history.iter().chain(open.iter()) {
+                let applied = self.applied_fills.entry(order.id).or_default();
+                for fill in order.filled_with.iter().skip(*applied) {
+                    fills.push(AccountFill {
+                        exchange: exchange.to_owned(),
+                        instrument: instrument.clone(),
+                        order: order.clone(),
+                        fill: fill.clone(),
+                        fee: 0.0,
+                    });
+                }
+                *applied = order.filled_with.len();
+            }
+        }
+        for fill in fills.iter_mut() {
+            fill.fee = self.apply_fill(fill);
+        }
+        fills
+    }
+
+    /// updates the position and the settlement balance, our orders take liquidity so pay the
+    /// taker fee. Returns the fee
+    fn apply_fill(&mut self, fill: &AccountFill) -> f32 {
+        let venue = self.venues.get(&fill.exchange);
+        let underlying = venue.map_or(fill.fill.quantity as f32, |venue| {
+            venue.to_underlying(fill.fill.quantity)
+        });
+        let fee = venue.map_or(0.0, |venue| venue.taker_fee_per_unit(fill.fill.price)) * underlying;
+        let signed = if fill.order.request.is_ask() {
+            -underlying
+        } else {
+            underlying
+        };
+        let instrument = fill.instrument.to_singular_asset();
+        self.positions
+            .entry((fill.exchange.clone(), instrument.clone()))
+            .or_default()
+            .apply_fill(signed, fill.fill.price, fee);
+        *self.balances.entry(instrument.asset).or_default() -= signed * fill.fill.price + fee;
+        fee
+    }
+
+    /// the position held on one venue
+    pub fn position(&self, exchange: &str, instrument: &Instrument) -> Position {
+        self.positions
+            .get(&(exchange.to_owned(), instrument.to_singular_asset()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// quantity held across all venues, in underlying units
+    pub fn net_quantity(&self, instrument: &Instrument) -> f32 {
+        let instrument = instrument.to_singular_asset();
+        self.positions
+            .iter()
+            .filter(|((_, held), _)| *held == instrument)
+            .map(|(_, position)| position.quantity)
+            .sum()
+    }
+
+    pub fn realized_pnl(&self) -> f32 {
+        self.positions
+            .values()
+            .map(|position| position.realized_pnl)
+            .sum()
+    }
+
+    /// the price a position is marked to on a venue's book
+    pub fn mark(
+        &self,
+        venue: &OrderBook,
+        instrument: &Instrument,
+        source: MarkSource,
+    ) -> Option<f32> {
+        let midprice = venue
+            .table_for(instrument)
+            .map(|table| table.midprice)
+            .filter(|midprice| *midprice > 0.0);
+        match source {
+            MarkSource::Midprice => midprice,
+            MarkSource::MarkPrice => venue.mark_price(instrument).or(midprice),
+        }
+    }
+
+    /// pnl of the position held on the venue, None while it has no price for the instrument
+    pub fn unrealized_pnl(
+        &self,
+        venue: &OrderBook,
+        instrument: &Instrument,
+        source: MarkSource,
+    ) -> Option<f32> {
+        let position = self.position(venue.exchange, instrument);
+        if position.is_flat() {
+            return Some(0.0);
+        }
+        self.mark(venue, instrument, source)
+            .map(|mark| position.unrealized_pnl(mark))
+    }
+
+    /// realized pnl net of fees plus the unrealized pnl, each position marked on its own venue
+    /// or, while that has no price, on the first of `venues` with one
+    pub fn pnl(&self, venues: &[&OrderBook], source: MarkSource) -> f32 {
+        self.positions
+            .iter()
+            .map(|((exchange, instrument), position)| {
+                let own = venues.iter().filter(|venue| venue.exchange == exchange);
+                let unrealized = own
+                    .chain(venues.iter())
+                    .find_map(|venue| self.mark(venue, instrument, source))
+                    .map_or(0.0, |mark| position.unrealized_pnl(mark));
+                position.realized_pnl - position.fees + unrealized
+            })
+            .sum()
+    }
+
+    /// unrealized pnl of every open position on `venue`, marked there
+    pub fn total_unrealized_pnl(&self, venue: &OrderBook, source: MarkSource) -> f32 {
+        self.positions
+            .keys()
+            .filter(|(exchange, _)| exchange == venue.exchange)
+            .filter_map(|(_, instrument)| self.unrealized_pnl(venue, instrument, source))
+            .sum()
+    }
+}
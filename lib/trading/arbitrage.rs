@@ -0,0 +1,148 @@
+use super::{BestBidOffer, ConsolidatedBook, Instrument};
+use crate::exchanges::VenueSpec;
+use crate::utils::{system_clock, SharedClock};
+use std::collections::HashMap;
+
+/// buying on one venue and selling on another is profitable after fees
+#[derive(PartialEq, Debug, Clone)]
+pub struct ArbitrageOpportunity {
+    pub instrument: Instrument, // canonical instrument
+    pub buy_exchange: String,
+    pub buy_price: f32,
+    pub sell_exchange: String,
+    pub sell_price: f32,
+    pub size: f32,          // underlying units tradable on both venues
+    pub edge_per_unit: f32, // in the premium currency (BTC), after fees
+    pub edge_btc: f32,
+    pub edge_usd: Option<f32>, // needs the underlying price
+    pub first_seen: u128,
+    pub last_seen: u128,
+}
+
+impl ArbitrageOpportunity {
+    pub fn lifetime_ms(&self) -> u128 {
+        self.last_seen - self.first_seen
+    }
+}
+
+type OpportunityKey = (Instrument, String, String);
+
+/// Compares the best bid of every venue with the best ask of every other venue,
+/// net of taker fees and contract sizes. Keeps track of when each opportunity was first seen
+pub struct ArbitrageDetector {
+    pub venues: HashMap<String, VenueSpec>,
+    pub min_edge: f32, // minimum edge per unit after fees
+    active: HashMap<OpportunityKey, u128>,
+    clock: SharedClock,
+}
+
+impl Default for ArbitrageDetector {
+    fn default() -> Self {
+        Self::new(vec![VenueSpec::deribit(), VenueSpec::okex()], 0.0)
+    }
+}
+
+impl ArbitrageDetector {
+    pub fn new(venues: Vec<VenueSpec>, min_edge: f32) -> Self {
+        Self {
+            venues: venues
+                .into_iter()
+                .map(|venue| (venue.name.clone(), venue))
+                .collect(),
+            min_edge,
+            active: HashMap::new(),
+            clock: system_clock(),
+        }
+    }
+
+    /// times opportunities with the given clock instead of the wall clock
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// opportunities on one instrument, given the current quote of each venue
+    pub fn detect(
+        &mut self,
+        instrument: &Instrument,
+        quotes: &[BestBidOffer],
+        underlying_price: Option<f32>,
+    ) -> Vec<ArbitrageOpportunity> {
+        let now = self.clock.now_ms();
+        let instrument = instrument.to_singular_asset();
+        let mut opportunities = Vec::new();
+
+        for buy in quotes.iter().filter_map(|quote| quote.ask.as_ref()) {
+            for sell in quotes.iter().filter_map(|quote| quote.bid.as_ref()) {
+                if buy.exchange == sell.exchange {
+                    continue;
+                }
+                let (Some(buy_venue), Some(sell_venue)) = (
+                    self.venues.get(&buy.exchange),
+                    self.venues.get(&sell.exchange),
+                ) else {
+                    continue;
+                };
+                let edge_per_unit = (sell.price - sell_venue.taker_fee_per_unit(sell.price))
+                    - (buy.price + buy_venue.taker_fee_per_unit(buy.price));
+                if edge_per_unit <= self.min_edge {
+                    continue;
+                }
+                // both legs must be whole contracts on their own venue
+                let available = buy_venue
+                    .to_underlying(buy.quantity)
+                    .min(sell_venue.to_underlying(sell.quantity));
+                let size = buy_venue
+                    .to_underlying(buy_venue.to_contracts(available))
+                    .min(sell_venue.to_underlying(sell_venue.to_contracts(available)));
+                if size <= 0.0 {
+                    continue;
+                }
+
+                let key = (
+                    instrument.clone(),
+                    buy.exchange.clone(),
+                    sell.exchange.clone(),
+                );
+                let first_seen = *self.active.entry(key).or_insert(now);
+                let edge_btc = edge_per_unit * size;
+                opportunities.push(ArbitrageOpportunity {
+                    instrument: instrument.clone(),
+                    buy_exchange: buy.exchange.clone(),
+                    buy_price: buy.price,
+                    sell_exchange: sell.exchange.clone(),
+                    sell_price: sell.price,
+                    size,
+                    edge_per_unit,
+                    edge_btc,
+                    edge_usd: underlying_price.map(|price| edge_btc * price),
+                    first_seen,
+                    last_seen: now,
+                });
+            }
+        }
+
+        // forget opportunities on this instrument that have gone away
+        self.active.retain(|(active, buy, sell), _| {
+            *active != instrument
+                || opportunities
+                    .iter()
+                    .any(|o| o.buy_exchange == *buy && o.sell_exchange == *sell)
+        });
+        opportunities
+    }
+
+    /// opportunities across every instrument listed on the consolidated book
+    pub async fn scan(
+        &mut self,
+        book: &ConsolidatedBook<'_>,
+        underlying_price: Option<f32>,
+    ) -> Vec<ArbitrageOpportunity> {
+        let mut opportunities = Vec::new();
+        for instrument in book.instruments().await {
+            let quotes = book.venue_quotes(&instrument).await;
+            opportunities.extend(self.detect(&instrument, &quotes, underlying_price));
+        }
+        opportunities
+    }
+}
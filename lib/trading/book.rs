@@ -1,10 +1,15 @@
 use ordered_float::OrderedFloat;
 use tokio_tungstenite::tungstenite::http::request;
 
+use crate::analytics::Greeks;
+use crate::exchanges::{OptionSummary, PriceKind, PriceUpdate, Trade, VenueSpec};
 use crate::trading::{CurrentHoldingPerPrice, MatchedOrders, OrderStatus};
 
-use super::{Instrument, Order, PriceColumns, TradeRequest};
-use crate::utils::{add_each, get_timestamp_ms, match_at_price_level};
+use super::{
+    BookMetrics, CandleBook, CandleInterval, CandleSeries, CandleSource, Instrument, Order,
+    PriceColumns, RiskLimits, RiskRejection, RiskState, TradeRequest, TradeTape,
+};
+use crate::utils::{add_each, match_at_price_level, system_clock, SharedClock};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
 use std::vec;
@@ -15,6 +20,19 @@ pub type OrderTable = HashMap<Instrument, PriceColumns>;
 pub struct OrderBook<'a> {
     pub exchange: &'a str,
     pub asset_order_table: OrderTable,
+    pub prices: PriceFeeds,
+    pub trades: HashMap<Instrument, TradeTape>,
+    pub option_summaries: HashMap<Instrument, OptionSummary>,
+    pub candles: CandleBook,
+    pub clock: SharedClock,
+    pub risk: RiskLimits, // checked by `add_order` on our own orders, no limits by default
+}
+
+/// latest index and mark prices published by the exchange of a book
+#[derive(Debug, Default)]
+pub struct PriceFeeds {
+    pub indices: HashMap<String, PriceUpdate>,
+    pub marks: HashMap<Instrument, PriceUpdate>,
 }
 
 impl<'a> OrderBook<'a> {
@@ -23,19 +41,66 @@ impl<'a> OrderBook<'a> {
     }
 
     pub fn new(exchange_name: &'a str) -> Self {
+        Self::with_clock(exchange_name, system_clock())
+    }
+
+    /// a book whose orders and fills are stamped by the given clock
+    pub fn with_clock(exchange_name: &'a str, clock: SharedClock) -> Self {
         Self {
             exchange: exchange_name,
             asset_order_table: HashMap::default(),
+            prices: PriceFeeds::default(),
+            trades: HashMap::default(),
+            option_summaries: HashMap::default(),
+            candles: CandleBook::default(),
+            clock,
+            risk: RiskLimits::default(),
         }
     }
 
+    pub fn now(&self) -> u128 {
+        self.clock.now_ms()
+    }
+
+    /// an order created at the book's current time
+    pub fn new_order(&self, price: f32, quantity: i32, request: TradeRequest) -> Order {
+        Order::new_at(price, quantity, request, self.clock.as_ref())
+    }
+
     pub fn add_asset(&mut self, name: Instrument) {
         if !self.asset_order_table.contains_key(&name) {
             self.asset_order_table.entry(name).or_default();
         }
     }
 
-    pub fn add_order(&mut self, mut order: Order, asset: &Instrument) {
+    /// Adds an order once it passes the book's risk limits, checked against what the book
+    /// knows: its own midprice and open orders and the venue's greeks for the instrument
+    pub fn add_order(&mut self, order: Order, asset: &Instrument) -> Result<(), RiskRejection> {
+        let quantity = VenueSpec::for_exchange(self.exchange)
+            .map_or(order.quantity as f32, |venue| {
+                venue.to_underlying(order.quantity)
+            });
+        let state = self.risk_state(asset);
+        self.add_order_checked(order, asset, quantity, &state)
+    }
+
+    /// Adds an order of `quantity` underlying units once it passes the book's risk limits
+    /// against `state`, for owners that know more than the book does (e.g. positions)
+    pub fn add_order_checked(
+        &mut self,
+        mut order: Order,
+        asset: &Instrument,
+        quantity: f32,
+        state: &RiskState,
+    ) -> Result<(), RiskRejection> {
+        self.risk.check(&order, quantity, state)?;
+        self.insert_order(order, asset);
+        Ok(())
+    }
+
+    /// Adds an order without any risk checks, for the venue's own depth from the feeds. Our
+    /// orders go through `add_order`
+    pub fn insert_order(&mut self, mut order: Order, asset: &Instrument) {
         if let Some(mut table) = self.asset_order_table.get_mut(asset) {
             add_each(table, &mut order);
             table.orders.lock().unwrap().push_back(order);
@@ -44,9 +109,222 @@ impl<'a> OrderBook<'a> {
         }
     }
 
+    /// what the risk checks of an order on `asset` see from this book alone, it holds no
+    /// positions
+    pub fn risk_state(&self, asset: &Instrument) -> RiskState {
+        let to_underlying = |contracts: i32| {
+            VenueSpec::for_exchange(self.exchange)
+                .map_or(contracts as f32, |venue| venue.to_underlying(contracts))
+        };
+        let mut state = RiskState {
+            portfolio_greeks: Some(Greeks::default()),
+            instrument_greeks: self.option_summary(asset).map(|summary| summary.greeks),
+            ..Default::default()
+        };
+        let Some(table) = self.table_for(asset) else {
+            return state;
+        };
+        state.midprice =
+            (!table.bids.is_empty() && !table.asks.is_empty()).then_some(table.midprice);
+        for order in table
+            .orders
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|order| order.is_open())
+        {
+            state.open_orders += 1;
+            let open = to_underlying(order.remaining_qty);
+            if order.request.is_ask() {
+                state.open_asks += open;
+            } else {
+                state.open_bids += open;
+            }
+        }
+        state
+    }
+
     pub fn get_name(&self) -> String {
         self.exchange.to_owned()
     }
+
+    pub fn apply_price_update(&mut self, update: PriceUpdate) {
+        match (&update.kind, &update.instrument) {
+            (PriceKind::Index, _) => {
+                self.prices.indices.insert(update.name.clone(), update);
+            }
+            (PriceKind::Mark, Some(instrument)) => {
+                self.prices.marks.insert(instrument.clone(), update);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn record_trade(&mut self, trade: Trade) {
+        let tape = self.trades.entry(trade.instrument.clone()).or_default();
+        if tape.push(trade.clone()) {
+            self.candles.record_trade(&trade);
+        }
+    }
+
+    /// adds the current midprice of an instrument to its candles, once both sides are quoted
+    pub fn record_midprice(&mut self, asset: &Instrument, timestamp: u128) {
+        let Some(table) = self.asset_order_table.get(asset) else {
+            return;
+        };
+        if table.bids.is_empty() || table.asks.is_empty() {
+            return;
+        }
+        let midprice = table.midprice;
+        self.candles
+            .record(asset, CandleSource::Mid, midprice, 0.0, timestamp);
+    }
+
+    /// microstructure metrics of an instrument's book, None while it is one sided
+    pub fn metrics(&self, asset: &Instrument) -> Option<BookMetrics> {
+        self.table_for(asset)?.metrics
+    }
+
+    /// candles of an instrument, looked up like `table_for`
+    pub fn candles_for(
+        &self,
+        asset: &Instrument,
+        source: CandleSource,
+        interval: CandleInterval,
+    ) -> Option<&CandleSeries> {
+        if let Some(series) = self.candles.series(asset, source, interval) {
+            return Some(series);
+        }
+        let canonical = asset.to_singular_asset();
+        self.candles
+            .series
+            .iter()
+            .find(|((instrument, kind), _)| {
+                *kind == source && instrument.to_singular_asset() == canonical
+            })?
+            .1
+            .get(&interval)
+    }
+
+    /// the trade tape of an instrument, looked up like `table_for`
+    pub fn trade_tape(&self, asset: &Instrument) -> Option<&TradeTape> {
+        if let Some(tape) = self.trades.get(asset) {
+            return Some(tape);
+        }
+        let canonical = asset.to_singular_asset();
+        self.trades
+            .iter()
+            .find(|(instrument, _)| instrument.to_singular_asset() == canonical)
+            .map(|(_, tape)| tape)
+    }
+
+    /// keeps the latest exchange computed iv and greeks of an instrument
+    pub fn apply_option_summary(&mut self, summary: OptionSummary) {
+        let newer = self
+            .option_summaries
+            .get(&summary.instrument)
+            .is_none_or(|current| current.timestamp <= summary.timestamp);
+        if newer {
+            self.option_summaries
+                .insert(summary.instrument.clone(), summary);
+        }
+    }
+
+    /// latest exchange iv and greeks of an instrument, looked up like `table_for`
+    pub fn option_summary(&self, asset: &Instrument) -> Option<&OptionSummary> {
+        if let Some(summary) = self.option_summaries.get(asset) {
+            return Some(summary);
+        }
+        let canonical = asset.to_singular_asset();
+        self.option_summaries
+            .iter()
+            .find(|(instrument, _)| instrument.to_singular_asset() == canonical)
+            .map(|(_, summary)| summary)
+    }
+
+    /// the most recent index price of the underlying
+    pub fn index_price(&self) -> Option<f32> {
+        self.prices
+            .indices
+            .values()
+            .max_by_key(|update| update.timestamp)
+            .map(|update| update.price)
+    }
+
+    /// latest mark price of an instrument, looked up like `table_for`
+    pub fn mark_price(&self, asset: &Instrument) -> Option<f32> {
+        if let Some(update) = self.prices.marks.get(asset) {
+            return Some(update.price);
+        }
+        let canonical = asset.to_singular_asset();
+        self.prices
+            .marks
+            .iter()
+            .find(|(instrument, _)| instrument.to_singular_asset() == canonical)
+            .map(|(_, update)| update.price)
+    }
+
+    /// the price columns for an instrument, falling back to the same canonical instrument
+    /// under this exchange's naming (e.g. BTC on deribit is BTC-USD on okex)
+    pub fn table_for(&self, asset: &Instrument) -> Option<&PriceColumns> {
+        if let Some(table) = self.asset_order_table.get(asset) {
+            return Some(table);
+        }
+        let canonical = asset.to_singular_asset();
+        self.asset_order_table
+            .iter()
+            .find(|(instrument, _)| instrument.to_singular_asset() == canonical)
+            .map(|(_, table)| table)
+    }
+
+    pub fn table_for_mut(&mut self, asset: &Instrument) -> Option<&mut PriceColumns> {
+        let key = if self.asset_order_table.contains_key(asset) {
+            asset.clone()
+        } else {
+            let canonical = asset.to_singular_asset();
+            self.asset_order_table
+                .keys()
+                .find(|instrument| instrument.to_singular_asset() == canonical)?
+                .clone()
+        };
+        self.asset_order_table.get_mut(&key)
+    }
+
+    /// look up an open order by its id
+    pub fn get_order(&self, asset: &Instrument, order_id: u128) -> Option<Order> {
+        let table = self.asset_order_table.get(asset)?;
+        let orders = table.orders.lock().unwrap();
+        orders.iter().find(|order| order.id == order_id).cloned()
+    }
+
+    /// look up an open order by the client order id supplied when it was placed
+    pub fn get_order_by_client_id(
+        &self,
+        asset: &Instrument,
+        client_order_id: &str,
+    ) -> Option<Order> {
+        let table = self.asset_order_table.get(asset)?;
+        let orders = table.orders.lock().unwrap();
+        orders
+            .iter()
+            .find(|order| order.client_order_id.as_deref() == Some(client_order_id))
+            .cloned()
+    }
+
+    /// cancels an open order, removing its resting quantity from the price columns and moving it into the history
+    pub fn cancel_order(&mut self, asset: &Instrument, order_id: u128) -> Option<Order> {
+        let table = self.asset_order_table.get_mut(asset)?;
+        let mut removed = {
+            let mut orders = table.orders.lock().unwrap();
+            let index = orders.iter().position(|order| order.id == order_id)?;
+            orders.remove(index)?
+        };
+        removed.cancel().ok()?;
+        table.remove_from_level(removed.id, removed.price, &removed.request);
+        table.update_spread_and_mid_price();
+        table.history.push_back(removed.clone());
+        Some(removed)
+    }
 }
 
 // -----------------ORDER_MATCHING LOGIC--------------------------------------------------------- //
@@ -56,128 +334,191 @@ impl<'a> OrderBook<'a> {
         &mut self,
         assets: &Instrument,
         external_collection: Arc<Mutex<OrderBook<'a>>>,
-    ) {
-        // extend our assert_table with the other another one from a different exchange
-
-        let mut external_collection = external_collection.lock().await;
-
-        let external_table = external_collection.asset_order_table.get_mut(assets);
-
-        let table = self.asset_order_table.get_mut(assets);
-
-        if let (Some(asset_table), Some(extern_asset_table)) = (table, external_table) {
-            use TradeRequest::*;
-            let mut orders_to_remove = Vec::new();
-            asset_table
-                .orders
-                .lock()
-                .unwrap()
-                .iter_mut()
-                .for_each(|mut order| {
-                    let mut remaining_qty = order.quantity;
-                    let price: OrderedFloat<f32> = order.price.into();
-                    if order.remaining_qty > 0 {
-                        remaining_qty = order.remaining_qty;
+    ) -> anyhow::Result<()> {
+        self.match_orders_across(assets, &[external_collection])
+            .await
+    }
+
+    /// matches our open orders for an instrument against any number of external books,
+    /// sweeping their liquidity in price order regardless of venue.
+    /// `external_collections` must not contain this book itself, and are locked in the order
+    /// given while the caller holds this one: callers sharing books must keep one lock order
+    /// (`ConsolidatedBook::match_venue` locks them all up front instead)
+    pub async fn match_orders_across(
+        &mut self,
+        assets: &Instrument,
+        external_collections: &[Arc<Mutex<OrderBook<'a>>>],
+    ) -> anyhow::Result<()> {
+        // lock every external book for the whole match, so fills are applied to all sides atomically
+        let mut external_books = Vec::with_capacity(external_collections.len());
+        for collection in external_collections {
+            external_books.push(collection.lock().await);
+        }
+        let mut external_books: Vec<&mut OrderBook<'a>> =
+            external_books.iter_mut().map(|book| &mut **book).collect();
+        self.match_orders_in(assets, &mut external_books)
+    }
+
+    /// Matches our open orders for an instrument against books the caller already locked.
+    /// Quantities are converted through the `VenueSpec` of both venues, fills are taken in
+    /// whole lots of the larger contract so both sides stay in whole contracts. Books of
+    /// unknown venues (accounts, the router) trade one for one in the other venue's contracts.
+    /// A fill an order can't take is returned as the error once the rest of the match is booked
+    pub fn match_orders_in(
+        &mut self,
+        assets: &Instrument,
+        external_books: &mut [&mut OrderBook<'a>],
+    ) -> anyhow::Result<()> {
+        self.match_in(assets, external_books, |_| true)
+    }
+
+    /// matches one of our open orders like `match_orders_in`, leaving the others as they are
+    pub fn match_order_in(
+        &mut self,
+        assets: &Instrument,
+        order_id: u128,
+        external_books: &mut [&mut OrderBook<'a>],
+    ) -> anyhow::Result<()> {
+        self.match_in(assets, external_books, |order| order.id == order_id)
+    }
+
+    fn match_in(
+        &mut self,
+        assets: &Instrument,
+        external_books: &mut [&mut OrderBook<'a>],
+        filter: impl Fn(&Order) -> bool,
+    ) -> anyhow::Result<()> {
+        let mut venues: Vec<(&str, &mut PriceColumns)> = external_books
+            .iter_mut()
+            .filter_map(|book| {
+                let name = book.exchange;
+                book.table_for_mut(assets).map(|table| (name, table))
+            })
+            .collect();
+        let our_spec = VenueSpec::for_exchange(self.exchange);
+        let specs: Vec<Option<VenueSpec>> = venues
+            .iter()
+            .map(|(name, _)| VenueSpec::for_exchange(name))
+            .collect();
+
+        let our_name = self.exchange;
+        let now = self.now();
+        let Some(asset_table) = self.table_for_mut(assets) else {
+            return Ok(());
+        };
+        if venues.is_empty() {
+            return Ok(());
+        }
+
+        let mut failure = None;
+        let mut own_fills = Vec::new();
+        let mut external_fills = vec![Vec::new(); venues.len()];
+        asset_table
+            .orders
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|order| order.is_open() && filter(order))
+            .for_each(|order| {
+                let mut remaining_qty = order.remaining_qty;
+                let price: OrderedFloat<f32> = order.price.into();
+                let is_ask = order.request.is_ask();
+                // asks hit the external bids from the highest price down, bids lift the asks from the lowest up
+                let mut levels: Vec<(OrderedFloat<f32>, usize)> = venues
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(venue, (_, cols))| {
+                        let row = if is_ask { &cols.bids } else { &cols.asks };
+                        row.keys().map(move |level| (*level, venue))
+                    })
+                    .collect();
+                if is_ask {
+                    levels.sort_by_key(|level| std::cmp::Reverse(level.0));
+                } else {
+                    levels.sort_by_key(|level| level.0);
+                }
+
+                let mut fills = Vec::new();
+                for (level, venue) in levels {
+                    let crosses = if is_ask {
+                        price <= level
+                    } else {
+                        price >= level
+                    };
+                    if !crosses || remaining_qty == 0 {
+                        break;
                     }
-                    match order.request {
-                        Ask => {
-                            let mut bids = extern_asset_table.bids.iter_mut();
-
-                            if let Some((mut x, holding)) = bids.next_back() {
-                                while price <= *x {
-                                    let (matched_qty, remove) =
-                                        match_at_price_level(holding, &mut remaining_qty);
-
-                                    orders_to_remove.extend(remove);
-
-                                    holding.update_qty_and_amount();
-                                    if matched_qty > 0 {
-                                        let matched_order =
-                                            MatchedOrders::new(**x, matched_qty, "external");
-                                        order.filled_with.push_back(matched_order);
-                                    }
-                                    if let Some((a, _)) = bids.next_back() {
-                                        x = a;
-                                    } else {
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        Bid => {
-                            let mut asks = extern_asset_table.asks.iter_mut();
-
-                            if let Some((mut x, holding)) = asks.next() {
-                                while price >= *x {
-                                    let (matched_qty, remove) =
-                                        match_at_price_level(holding, &mut remaining_qty);
-                                    holding.update_qty_and_amount();
-                                    if matched_qty > 0 {
-                                        let matched_order =
-                                            MatchedOrders::new(**x, matched_qty, "external");
-                                        order.filled_with.push_back(matched_order);
-                                    }
-                                    if let Some((a, _)) = asks.next() {
-                                        x = a;
-                                    } else {
-                                        break;
-                                    }
-                                }
-                            }
+                    let (name, cols) = &mut venues[venue];
+                    let row = if is_ask {
+                        &mut cols.bids
+                    } else {
+                        &mut cols.asks
+                    };
+                    let Some(holding) = row.get_mut(&level) else {
+                        continue;
+                    };
+                    // contracts of theirs we may take, and what they are worth in ours
+                    let (mut wanted, ours_per_theirs) = match (&our_spec, &specs[venue]) {
+                        (Some(ours), Some(theirs)) => {
+                            let lot = ours.contract_size.max(theirs.contract_size);
+                            let available = theirs
+                                .to_underlying(holding.total_quantity)
+                                .min(ours.to_underlying(remaining_qty));
+                            let lots = ((available / lot) + 1e-4).floor();
+                            let wanted = theirs.to_contracts(lots * lot);
+                            (wanted, Some((ours, theirs)))
                         }
+                        _ => (remaining_qty, None),
+                    };
+                    if wanted == 0 {
+                        continue;
                     }
+                    let (matched_theirs, consumed) = match_at_price_level(holding, &mut wanted);
+                    let matched_qty = match ours_per_theirs {
+                        Some((ours, theirs)) => {
+                            ours.to_contracts(theirs.to_underlying(matched_theirs))
+                        }
+                        None => matched_theirs,
+                    };
+                    remaining_qty -= matched_qty;
 
-                    let acc_qty = order.filled_with.iter().fold(0, |c, n| c + n.quantity);
-                    // to get an arbitrage, if our price column is extended with another column from a different exchange and some of our trades are filled with other
-
-                    if remaining_qty != 0 && remaining_qty < order.quantity {
-                        order.status = OrderStatus::Partial;
-                        order.remaining_qty = order.quantity - acc_qty;
-                        println!(
-                            "{:#?}  partially completed with the following trade matches {:#?}",
-                            order, order.filled_with
-                        );
+                    external_fills[venue]
+                        .extend(consumed.into_iter().map(|(id, qty)| (id, *level, qty)));
+                    if matched_qty > 0 {
+                        fills.push(MatchedOrders::new(*level, matched_qty, name).at(now));
                     }
-                    if remaining_qty == 0
-                        && !order.filled_with.is_empty()
-                        && acc_qty == order.quantity
-                    {
-                        // marked as completed
-                        order.status = OrderStatus::Completed;
-                        if order.is_arbitrage {
-                            println!(" arbitrage detectd")
-                        }
+                }
 
-                        println!(
-                            "{:#?}  Completed with the following trade matches {:#?}",
-                            order, order.filled_with
-                        );
-                        // remove the existing value in place
-                        let completed = std::mem::take(order);
-                        asset_table.history.push_back(completed);
+                if fills.is_empty() {
+                    return;
+                }
+                for fill in fills {
+                    let quantity = fill.quantity;
+                    match order.record_fill(fill) {
+                        Ok(()) => {
+                            own_fills.push((order.id, order.price, order.request.clone(), quantity))
+                        }
+                        Err(err) => {
+                            failure.get_or_insert(err);
+                        }
                     }
-                });
-            for order_to_remove in orders_to_remove {
-                let mut lock = asset_table.orders.lock().unwrap();
-                if let Some(index) = lock.iter().position(|o| o.id == order_to_remove) {
-                    lock.remove(index);
                 }
-            }
+            });
+        // filled quantity no longer rests on our side of the book
+        for (id, price, request, qty) in own_fills {
+            asset_table.reduce_at_level(id, price, &request, qty);
+        }
+        asset_table.archive_closed_orders();
+        asset_table.prune_empty_levels();
 
-            asset_table.update_spread_and_mid_price();
-            // remove all used items
-            asset_table
-                .bids
-                .retain(|_, holding| holding.total_quantity != 0);
-            asset_table
-                .asks
-                .retain(|_, holding| holding.total_quantity != 0);
-
-            asset_table
-                .orders
-                .lock()
-                .unwrap()
-                .retain(|ord| ord.quantity > 0)
+        // the external orders we consumed get the matching fills on their side
+        for ((_, cols), fills) in venues.iter_mut().zip(external_fills) {
+            if let Err(err) = cols.record_fills(&fills, our_name, now) {
+                failure.get_or_insert(err);
+            }
+            cols.archive_closed_orders();
+            cols.prune_empty_levels();
         }
+        failure.map_or(Ok(()), Err)
     }
 }
@@ -0,0 +1,240 @@
+use super::Instrument;
+use crate::exchanges::Trade;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+
+pub const DEFAULT_CANDLE_CAPACITY: usize = 500;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy, Serialize)]
+pub enum CandleInterval {
+    Second,
+    Minute,
+    FiveMinutes,
+    Hour,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 4] = [
+        CandleInterval::Second,
+        CandleInterval::Minute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::Hour,
+    ];
+
+    pub fn from_given_str(input_str: &str) -> Option<Self> {
+        match input_str {
+            "1s" => Some(CandleInterval::Second),
+            "1m" => Some(CandleInterval::Minute),
+            "5m" => Some(CandleInterval::FiveMinutes),
+            "1h" => Some(CandleInterval::Hour),
+            _ => None,
+        }
+    }
+
+    pub fn millis(&self) -> u128 {
+        match self {
+            CandleInterval::Second => 1_000,
+            CandleInterval::Minute => 60_000,
+            CandleInterval::FiveMinutes => 300_000,
+            CandleInterval::Hour => 3_600_000,
+        }
+    }
+
+    /// start of the candle a timestamp (ms) falls in
+    pub fn bucket_start(&self, timestamp: u128) -> u128 {
+        timestamp - timestamp % self.millis()
+    }
+}
+
+/// what a candle is built from
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Serialize)]
+pub enum CandleSource {
+    Mid,    // midprice of the book after each update, volume is always 0
+    Trades, // public trade prints
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct Candle {
+    pub start: u128, // ms
+    pub open: f32,
+    pub high: f32,
+    pub low: f32,
+    pub close: f32,
+    pub volume: f32,
+    pub count: u32, // updates or trades in the candle
+}
+
+impl Candle {
+    fn new(start: u128, price: f32, size: f32) -> Self {
+        Self {
+            start,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: size,
+            count: 1,
+        }
+    }
+
+    fn update(&mut self, price: f32, size: f32, is_latest: bool) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        if is_latest {
+            self.close = price;
+        }
+        self.volume += size;
+        self.count += 1;
+    }
+}
+
+/// candles of one interval, oldest first and bounded to `capacity` (at least one)
+#[derive(Debug, Clone)]
+pub struct CandleSeries {
+    pub interval: CandleInterval,
+    pub capacity: usize,
+    pub candles: VecDeque<Candle>,
+    last_timestamp: u128,
+}
+
+impl CandleSeries {
+    pub fn new(interval: CandleInterval, capacity: usize) -> Self {
+        Self {
+            interval,
+            capacity,
+            candles: VecDeque::with_capacity(capacity),
+            last_timestamp: 0,
+        }
+    }
+
+    /// Adds a price at a timestamp (ms). Late updates still count towards the high, low and
+    /// volume of their candle but never move its close; ones older than the series are dropped
+    pub fn update(&mut self, price: f32, size: f32, timestamp: u128) {
+        let start = self.interval.bucket_start(timestamp);
+        let is_latest = timestamp >= self.last_timestamp;
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+        if self.candles.back().is_some_and(|last| start <= last.start) {
+            if let Some(candle) = self.candles.iter_mut().rev().find(|c| c.start == start) {
+                candle.update(price, size, is_latest);
+            }
+            return;
+        }
+        while self.candles.len() >= self.capacity.max(1) {
+            self.candles.pop_front();
+        }
+        self.candles.push_back(Candle::new(start, price, size));
+    }
+
+    pub fn last(&self) -> Option<&Candle> {
+        self.candles.back()
+    }
+
+    /// newest first
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &Candle> {
+        self.candles.iter().rev().take(count)
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        let json = serde_json::to_string_pretty(&self.candles)?;
+        Ok(json)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("start,open,high,low,close,volume,count\n");
+        for candle in &self.candles {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{}",
+                candle.start,
+                candle.open,
+                candle.high,
+                candle.low,
+                candle.close,
+                candle.volume,
+                candle.count
+            );
+        }
+        csv
+    }
+}
+
+/// OHLCV candles per instrument and source, in every configured interval
+#[derive(Debug, Clone)]
+pub struct CandleBook {
+    pub intervals: Vec<CandleInterval>,
+    pub capacity: usize,
+    pub series: HashMap<(Instrument, CandleSource), BTreeMap<CandleInterval, CandleSeries>>,
+}
+
+impl Default for CandleBook {
+    fn default() -> Self {
+        Self::new(CandleInterval::ALL.to_vec(), DEFAULT_CANDLE_CAPACITY)
+    }
+}
+
+impl CandleBook {
+    pub fn new(intervals: Vec<CandleInterval>, capacity: usize) -> Self {
+        Self {
+            intervals,
+            capacity,
+            series: HashMap::default(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        instrument: &Instrument,
+        source: CandleSource,
+        price: f32,
+        size: f32,
+        timestamp: u128,
+    ) {
+        let series = self
+            .series
+            .entry((instrument.clone(), source))
+            .or_insert_with(|| {
+                self.intervals
+                    .iter()
+                    .map(|interval| (*interval, CandleSeries::new(*interval, self.capacity)))
+                    .collect()
+            });
+        for candles in series.values_mut() {
+            candles.update(price, size, timestamp);
+        }
+    }
+
+    pub fn record_trade(&mut self, trade: &Trade) {
+        self.record(
+            &trade.instrument,
+            CandleSource::Trades,
+            trade.price,
+            trade.size,
+            trade.timestamp,
+        );
+    }
+
+    pub fn series(
+        &self,
+        instrument: &Instrument,
+        source: CandleSource,
+        interval: CandleInterval,
+    ) -> Option<&CandleSeries> {
+        self.series
+            .get(&(instrument.clone(), source))?
+            .get(&interval)
+    }
+
+    /// newest first
+    pub fn recent(
+        &self,
+        instrument: &Instrument,
+        source: CandleSource,
+        interval: CandleInterval,
+        count: usize,
+    ) -> Vec<Candle> {
+        self.series(instrument, source, interval)
+            .map(|series| series.recent(count).cloned().collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,175 @@
+use super::{BookMetrics, CurrentHoldingPerPrice, Instrument, OrderBook, TradeRequest};
+use ordered_float::OrderedFloat;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// a price level (or top of book) on a given venue
+#[derive(PartialEq, PartialOrd, Debug, Default, Clone)]
+pub struct VenueQuote {
+    pub exchange: String,
+    pub price: f32,
+    pub quantity: i32,
+}
+
+impl VenueQuote {
+    pub fn new(exchange: &str, price: f32, quantity: i32) -> Self {
+        Self {
+            exchange: exchange.to_owned(),
+            price,
+            quantity,
+        }
+    }
+}
+
+/// best bid and best offer across all venues for one canonical instrument
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct BestBidOffer {
+    pub bid: Option<VenueQuote>,
+    pub ask: Option<VenueQuote>,
+}
+
+impl BestBidOffer {
+    pub fn spread(&self) -> Option<f32> {
+        match (&self.bid, &self.ask) {
+            (Some(bid), Some(ask)) => Some(ask.price - bid.price),
+            _ => None,
+        }
+    }
+
+    /// the best bid on one venue is at or above the best ask on another
+    pub fn is_crossed(&self) -> bool {
+        self.spread().is_some_and(|spread| spread <= 0.0)
+    }
+}
+
+/// A consolidated view over the order books of any number of venues.
+/// Instruments are compared in their canonical form (`Instrument::to_singular_asset`),
+/// so the same option listed as BTC on deribit and BTC-USD on okex is treated as one
+pub struct ConsolidatedBook<'a> {
+    pub books: Vec<Arc<Mutex<OrderBook<'a>>>>,
+}
+
+impl<'a> ConsolidatedBook<'a> {
+    pub fn new(books: Vec<Arc<Mutex<OrderBook<'a>>>>) -> Self {
+        Self { books }
+    }
+
+    pub fn add_book(&mut self, book: Arc<Mutex<OrderBook<'a>>>) {
+        self.books.push(book);
+    }
+
+    pub async fn book(&self, exchange: &str) -> Option<Arc<Mutex<OrderBook<'a>>>> {
+        for book in &self.books {
+            if book.lock().await.exchange == exchange {
+                return Some(book.clone());
+            }
+        }
+        None
+    }
+
+    /// all resting levels of one side (asks or bids) across venues, best price first
+    pub async fn levels(&self, instrument: &Instrument, side: &TradeRequest) -> Vec<VenueQuote> {
+        let mut levels = Vec::new();
+        for book in &self.books {
+            let book = book.lock().await;
+            if let Some(table) = book.table_for(instrument) {
+                let row = if side.is_ask() {
+                    &table.asks
+                } else {
+                    &table.bids
+                };
+                levels.extend(row.iter().map(|(price, holding)| {
+                    VenueQuote::new(book.exchange, price.into_inner(), holding.total_quantity)
+                }));
+            }
+        }
+        if side.is_ask() {
+            levels.sort_by(|a, b| a.price.total_cmp(&b.price));
+        } else {
+            levels.sort_by(|a, b| b.price.total_cmp(&a.price));
+        }
+        levels
+    }
+
+    /// every instrument listed on any venue, in canonical form
+    pub async fn instruments(&self) -> Vec<Instrument> {
+        let mut instruments: Vec<Instrument> = Vec::new();
+        for book in &self.books {
+            let book = book.lock().await;
+            for instrument in book.asset_order_table.keys() {
+                let canonical = instrument.to_singular_asset();
+                if !instruments.contains(&canonical) {
+                    instruments.push(canonical);
+                }
+            }
+        }
+        instruments
+    }
+
+    /// the best bid and offer of each venue on its own
+    pub async fn venue_quotes(&self, instrument: &Instrument) -> Vec<BestBidOffer> {
+        let mut quotes = Vec::new();
+        for book in &self.books {
+            let book = book.lock().await;
+            if let Some(table) = book.table_for(instrument) {
+                let quote = |(price, holding): (&OrderedFloat<f32>, &CurrentHoldingPerPrice)| {
+                    VenueQuote::new(book.exchange, price.into_inner(), holding.total_quantity)
+                };
+                quotes.push(BestBidOffer {
+                    bid: table.bids.last_key_value().map(quote),
+                    ask: table.asks.first_key_value().map(quote),
+                });
+            }
+        }
+        quotes
+    }
+
+    pub async fn best_bid_offer(&self, instrument: &Instrument) -> BestBidOffer {
+        let bid = self
+            .levels(instrument, &TradeRequest::Bid)
+            .await
+            .into_iter()
+            .next();
+        let ask = self
+            .levels(instrument, &TradeRequest::Ask)
+            .await
+            .into_iter()
+            .next();
+        BestBidOffer { bid, ask }
+    }
+
+    /// the book metrics of each venue quoting both sides of an instrument
+    pub async fn venue_metrics(&self, instrument: &Instrument) -> Vec<(String, BookMetrics)> {
+        let mut metrics = Vec::new();
+        for book in &self.books {
+            let book = book.lock().await;
+            if let Some(book_metrics) = book.metrics(instrument) {
+                metrics.push((book.get_name(), book_metrics));
+            }
+        }
+        metrics
+    }
+
+    /// Matches the open orders of one venue against the liquidity of every other venue.
+    /// Every book is locked in the order of `books`, so concurrent matches on different venues
+    /// can't deadlock
+    pub async fn match_venue(&self, exchange: &str, instrument: &Instrument) -> anyhow::Result<()> {
+        let mut guards = Vec::with_capacity(self.books.len());
+        for book in &self.books {
+            guards.push(book.lock().await);
+        }
+        let mut own = None;
+        let mut others = Vec::with_capacity(guards.len());
+        for guard in guards.iter_mut() {
+            if own.is_none() && guard.exchange == exchange {
+                own = Some(&mut **guard);
+            } else {
+                others.push(&mut **guard);
+            }
+        }
+        match own {
+            Some(own) => own.match_orders_in(instrument, &mut others),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use super::{
+    ConsolidatedBook, CurrentHoldingPerPrice, Instrument, OrderBook, PriceColumns, TradeRequest,
+    VenueQuote,
+};
+use crate::exchanges::VenueSpec;
+use ordered_float::OrderedFloat;
+
+/// underlying units per contract of a venue, venues without a spec count contracts
+fn contract_size(exchange: &str) -> f32 {
+    VenueSpec::for_exchange(exchange).map_or(1.0, |venue| venue.contract_size)
+}
+
+/// what sweeping the book for a given size would cost, without touching the book
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct ImpactEstimate {
+    pub request: TradeRequest, // a Bid lifts the asks, an Ask hits the bids
+    pub requested: f32,        // underlying units, see `from_levels`
+    pub filled: f32,
+    pub vwap: Option<f32>,
+    pub worst_price: Option<f32>,
+    pub mid: Option<f32>,
+    pub slippage: Option<f32>, // how much worse than the mid the vwap is
+    pub slippage_bps: Option<f32>,
+    pub levels: Vec<VenueQuote>, // the contracts taken from each level, best first
+}
+
+impl ImpactEstimate {
+    /// Walks levels sorted best first (as `ConsolidatedBook::levels` returns them)
+    /// until `quantity` is filled or the book runs out. Levels are in their venue's contracts
+    /// and `unit_size` gives what one of them is worth in the units of `quantity`, the
+    /// underlying for book estimates. Only whole contracts are taken
+    pub fn from_levels(
+        request: TradeRequest,
+        quantity: f32,
+        levels: &[VenueQuote],
+        mid: Option<f32>,
+        unit_size: impl Fn(&VenueQuote) -> f32,
+    ) -> Self {
+        let requested = quantity.max(0.0);
+        let mut filled = 0.0;
+        let mut taken = Vec::new();
+        let mut amount = 0.0;
+        for level in levels {
+            let size = unit_size(level);
+            // small epsilon so 0.3 / 0.01 isn't floored to 29, as in `VenueSpec::to_contracts`
+            let wanted = (((requested - filled) / size) + 1e-4).floor() as i32;
+            let contracts = level.quantity.min(wanted);
+            if contracts <= 0 {
+                continue;
+            }
+            let units = contracts as f32 * size;
+            filled += units;
+            amount += level.price * units;
+            taken.push(VenueQuote::new(&level.exchange, level.price, contracts));
+        }
+        let vwap = (filled > 0.0).then(|| amount / filled);
+        let slippage = vwap.zip(mid).map(|(vwap, mid)| {
+            if request.is_ask() {
+                mid - vwap
+            } else {
+                vwap - mid
+            }
+        });
+        let slippage_bps = slippage
+            .zip(mid)
+            .filter(|(_, mid)| *mid > 0.0)
+            .map(|(slippage, mid)| slippage / mid * 10_000.0);
+        Self {
+            request,
+            requested,
+            filled,
+            vwap,
+            worst_price: taken.last().map(|level| level.price),
+            mid,
+            slippage,
+            slippage_bps,
+            levels: taken,
+        }
+    }
+
+    pub fn fill_ratio(&self) -> f32 {
+        if self.requested > 0.0 {
+            self.filled / self.requested
+        } else {
+            0.0
+        }
+    }
+
+    /// filled up to the contract rounding of the smallest level taken
+    pub fn is_complete(&self) -> bool {
+        self.requested - self.filled < 1e-4
+    }
+}
+
+impl PriceColumns {
+    /// the read-only counterpart of matching an order of `quantity` underlying units against
+    /// this book, whose levels are contracts of `contract_size`
+    pub fn estimate_impact(
+        &self,
+        request: TradeRequest,
+        quantity: f32,
+        contract_size: f32,
+    ) -> ImpactEstimate {
+        let to_quote = |(price, holding): (&OrderedFloat<f32>, &CurrentHoldingPerPrice)| {
+            VenueQuote::new(
+                &self.exchange_name,
+                price.into_inner(),
+                holding.total_quantity,
+            )
+        };
+        let levels: Vec<VenueQuote> = if request.is_ask() {
+            self.bids.iter().rev().map(to_quote).collect()
+        } else {
+            self.asks.iter().map(to_quote).collect()
+        };
+        let mid = (!self.bids.is_empty() && !self.asks.is_empty()).then_some(self.midprice);
+        ImpactEstimate::from_levels(request, quantity, &levels, mid, |_| contract_size)
+    }
+}
+
+impl OrderBook<'_> {
+    /// impact of sweeping this venue's book for an instrument, looked up like `table_for`,
+    /// for `quantity` underlying units
+    pub fn estimate_impact(
+        &self,
+        asset: &Instrument,
+        request: TradeRequest,
+        quantity: f32,
+    ) -> Option<ImpactEstimate> {
+        let mut estimate =
+            self.table_for(asset)?
+                .estimate_impact(request, quantity, contract_size(self.exchange));
+        for level in estimate.levels.iter_mut() {
+            level.exchange = self.get_name();
+        }
+        Some(estimate)
+    }
+}
+
+impl ConsolidatedBook<'_> {
+    /// Estimates sweeping every venue in price order for `quantity` underlying units, each
+    /// level normalized through its venue's contract size like `SmartOrderRouter` does.
+    /// Slippage is measured against the consolidated mid
+    pub async fn estimate_impact(
+        &self,
+        instrument: &Instrument,
+        request: TradeRequest,
+        quantity: f32,
+    ) -> ImpactEstimate {
+        let side = if request.is_ask() {
+            TradeRequest::Bid
+        } else {
+            TradeRequest::Ask
+        };
+        let levels = self.levels(instrument, &side).await;
+        let best = self.best_bid_offer(instrument).await;
+        let mid = best
+            .bid
+            .zip(best.ask)
+            .map(|(bid, ask)| (bid.price + ask.price) / 2.0);
+        ImpactEstimate::from_levels(request, quantity, &levels, mid, |level| {
+            contract_size(&level.exchange)
+        })
+    }
+}
@@ -0,0 +1,324 @@
+use super::Instrument;
+use crate::exchanges::ExchangeType;
+use crate::utils::SharedClock;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// what a kill switch applies to, instruments are compared in their canonical form
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum KillScope {
+    Global,
+    Instrument(Instrument),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum KillReason {
+    Manual(String), // as given by the operator
+    Loss {
+        pnl: f32,
+        limit: f32,
+    },
+    StaleData {
+        exchange: String,
+        age_ms: u128,
+        limit_ms: u128,
+    },
+    OrderRate {
+        orders: usize,
+        window_ms: u128,
+    },
+}
+
+impl fmt::Display for KillReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KillReason::Manual(note) => write!(f, "manual: {note}"),
+            KillReason::Loss { pnl, limit } => write!(f, "pnl {pnl} is below -{limit}"),
+            KillReason::StaleData {
+                exchange,
+                age_ms,
+                limit_ms,
+            } => write!(
+                f,
+                "no data from {exchange} for {age_ms}ms, the limit is {limit_ms}ms"
+            ),
+            KillReason::OrderRate { orders, window_ms } => {
+                write!(f, "{orders} orders within {window_ms}ms")
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum AuditAction {
+    Triggered(KillReason),
+    Reset,
+    Cancelled { order_id: u128 },
+    Blocked { order_id: u128 },
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct AuditEvent {
+    pub timestamp: u128, // ms
+    pub scope: KillScope,
+    pub action: AuditAction,
+}
+
+/// automatic triggers, None disables one
+#[derive(PartialEq, Debug, Clone)]
+pub struct KillSwitchConfig {
+    pub max_loss: Option<f32>, // in the premium currency
+    pub max_data_age_ms: Option<u128>,
+    pub max_orders: Option<usize>, // within `order_window_ms`
+    pub order_window_ms: u128,
+}
+
+impl Default for KillSwitchConfig {
+    fn default() -> Self {
+        Self {
+            max_loss: None,
+            max_data_age_ms: None,
+            max_orders: None,
+            order_window_ms: 1_000,
+        }
+    }
+}
+
+/// Blocks new orders globally or per instrument once tripped, until it is reset. Whoever owns
+/// the orders (e.g. the `StrategyRuntime`) cancels the open ones in a tripped scope.
+/// Every trigger, reset, cancel and block is kept in `audit` for the owner to report
+#[derive(Debug)]
+pub struct KillSwitch {
+    pub config: KillSwitchConfig,
+    pub audit: Vec<AuditEvent>,
+    tripped: HashMap<KillScope, KillReason>,
+    last_data: HashMap<String, u128>, // ms, per exchange
+    recent_orders: VecDeque<u128>,
+    clock: SharedClock,
+}
+
+pub type SharedKillSwitch = Arc<Mutex<KillSwitch>>;
+
+impl KillSwitch {
+    pub fn new(config: KillSwitchConfig, clock: SharedClock) -> Self {
+        Self {
+            config,
+            audit: Vec::new(),
+            tripped: HashMap::new(),
+            last_data: HashMap::new(),
+            recent_orders: VecDeque::new(),
+            clock,
+        }
+    }
+
+    pub fn shared(self) -> SharedKillSwitch {
+        Arc::new(Mutex::new(self))
+    }
+
+    fn canonical(scope: KillScope) -> KillScope {
+        match scope {
+            KillScope::Instrument(instrument) => {
+                KillScope::Instrument(instrument.to_singular_asset())
+            }
+            global => global,
+        }
+    }
+
+    fn log(&mut self, scope: KillScope, action: AuditAction) {
+        let event = AuditEvent {
+            timestamp: self.clock.now_ms(),
+            scope,
+            action,
+        };
+        self.audit.push(event);
+    }
+
+    /// trips the switch, false when the scope was already tripped
+    pub fn trigger(&mut self, scope: KillScope, reason: KillReason) -> bool {
+        let scope = Self::canonical(scope);
+        if self.tripped.contains_key(&scope) {
+            return false;
+        }
+        self.tripped.insert(scope.clone(), reason.clone());
+        self.log(scope, AuditAction::Triggered(reason));
+        true
+    }
+
+    /// allows orders in the scope again, a global reset leaves instruments tripped
+    pub fn reset(&mut self, scope: KillScope) -> bool {
+        let scope = Self::canonical(scope);
+        if self.tripped.remove(&scope).is_none() {
+            return false;
+        }
+        if scope == KillScope::Global {
+            self.recent_orders.clear();
+        }
+        self.log(scope, AuditAction::Reset);
+        true
+    }
+
+    /// why orders on an instrument are blocked, None when they aren't
+    pub fn blocked(&self, instrument: &Instrument) -> Option<&KillReason> {
+        self.tripped.get(&KillScope::Global).or_else(|| {
+            self.tripped
+                .get(&KillScope::Instrument(instrument.to_singular_asset()))
+        })
+    }
+
+    pub fn tripped(&self) -> Vec<(KillScope, KillReason)> {
+        self.tripped
+            .iter()
+            .map(|(scope, reason)| (scope.clone(), reason.clone()))
+            .collect()
+    }
+
+    pub fn record_cancel(&mut self, instrument: &Instrument, order_id: u128) {
+        self.log(
+            KillScope::Instrument(instrument.to_singular_asset()),
+            AuditAction::Cancelled { order_id },
+        );
+    }
+
+    pub fn record_blocked(&mut self, instrument: &Instrument, order_id: u128) {
+        self.log(
+            KillScope::Instrument(instrument.to_singular_asset()),
+            AuditAction::Blocked { order_id },
+        );
+    }
+
+    /// counts an order towards the rate limit, tripping the global switch when it runs away
+    pub fn record_order(&mut self) {
+        let now = self.clock.now_ms();
+        let window_ms = self.config.order_window_ms;
+        self.recent_orders.push_back(now);
+        while self
+            .recent_orders
+            .front()
+            .is_some_and(|sent| *sent + window_ms < now)
+        {
+            self.recent_orders.pop_front();
+        }
+        if let Some(limit) = self.config.max_orders {
+            let orders = self.recent_orders.len();
+            if orders > limit {
+                self.trigger(
+                    KillScope::Global,
+                    KillReason::OrderRate { orders, window_ms },
+                );
+            }
+        }
+    }
+
+    /// notes that market data from an exchange arrived just now
+    pub fn observe_data(&mut self, exchange: &str) {
+        let now = self.clock.now_ms();
+        self.last_data.insert(exchange.to_owned(), now);
+    }
+
+    /// expects data from an exchange, so it goes stale if none arrives within the limit
+    pub fn watch(&mut self, exchange: &str) {
+        let now = self.clock.now_ms();
+        self.last_data.entry(exchange.to_owned()).or_insert(now);
+    }
+
+    /// trips the global switch when an exchange went quiet for longer than allowed, a watched
+    /// exchange that never sent anything counts from when it was first watched
+    pub fn check_stale(&mut self) {
+        let Some(limit_ms) = self.config.max_data_age_ms else {
+            return;
+        };
+        let now = self.clock.now_ms();
+        let stale = self
+            .last_data
+            .iter()
+            .map(|(exchange, last)| (exchange.clone(), now.saturating_sub(*last)))
+            .find(|(_, age_ms)| *age_ms > limit_ms);
+        if let Some((exchange, age_ms)) = stale {
+            self.trigger(
+                KillScope::Global,
+                KillReason::StaleData {
+                    exchange,
+                    age_ms,
+                    limit_ms,
+                },
+            );
+        }
+    }
+
+    /// trips the global switch once the pnl falls below the loss limit
+    pub fn check_loss(&mut self, pnl: f32) {
+        if let Some(limit) = self.config.max_loss {
+            if pnl < -limit {
+                self.trigger(KillScope::Global, KillReason::Loss { pnl, limit });
+            }
+        }
+    }
+
+    /// runs an operator command, returns the reply for the operator
+    pub fn apply_command(&mut self, command: ControlCommand) -> String {
+        let scope = |instrument: Option<Instrument>| match instrument {
+            Some(instrument) => KillScope::Instrument(instrument),
+            None => KillScope::Global,
+        };
+        match command {
+            ControlCommand::Kill(instrument) => {
+                let scope = scope(instrument);
+                if self.trigger(scope.clone(), KillReason::Manual("operator".to_owned())) {
+                    format!("killed {scope:?}")
+                } else {
+                    format!("{scope:?} is already killed")
+                }
+            }
+            ControlCommand::Resume(instrument) => {
+                let scope = scope(instrument);
+                if self.reset(scope.clone()) {
+                    format!("resumed {scope:?}")
+                } else {
+                    format!("{scope:?} was not killed")
+                }
+            }
+            ControlCommand::Status => {
+                if self.tripped.is_empty() {
+                    return "trading".to_owned();
+                }
+                self.tripped
+                    .iter()
+                    .map(|(scope, reason)| format!("{scope:?} killed, {reason}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+    }
+}
+
+/// operator commands, e.g. from the command line:
+/// `kill`, `kill BTC-10MAY24-66000-C`, `resume`, `resume <instrument>` and `status`
+#[derive(PartialEq, Debug, Clone)]
+pub enum ControlCommand {
+    Kill(Option<Instrument>), // None for all instruments
+    Resume(Option<Instrument>),
+    Status,
+}
+
+impl ControlCommand {
+    /// instruments may be named like deribit or okex does
+    pub fn from_given_str(input_str: &str) -> Option<Self> {
+        let mut words = input_str.split_whitespace();
+        let command = words.next()?;
+        let instrument = match words.next() {
+            Some(name) => Some(
+                Instrument::from_exchange_string(name, ExchangeType::Delibris)
+                    .or_else(|_| Instrument::from_exchange_string(name, ExchangeType::Okex))
+                    .ok()?,
+            ),
+            None => None,
+        };
+        match command {
+            "kill" => Some(Self::Kill(instrument)),
+            "resume" => Some(Self::Resume(instrument)),
+            "status" if instrument.is_none() => Some(Self::Status),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use super::{CurrentHoldingPerPrice, PriceColumns, PriceRow};
+use ordered_float::OrderedFloat;
+
+/// parameters of the book metrics
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct MetricsConfig {
+    pub tick_size: f32,
+    pub depth_ticks: u32, // depth is summed within this many ticks of the best price
+    pub levels: usize,    // levels per side used by the weighted mid and book pressure
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        // BTC option tick on both deribit and okex
+        Self {
+            tick_size: 0.0005,
+            depth_ticks: 5,
+            levels: 5,
+        }
+    }
+}
+
+/// microstructure metrics of a two sided book
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub struct BookMetrics {
+    pub best_bid: f32,
+    pub best_ask: f32,
+    pub imbalance: f32, // top of book, (bid qty - ask qty) / (bid qty + ask qty), in [-1, 1]
+    pub microprice: f32,
+    pub bid_depth: i32, // quantity within `depth_ticks` of the best price
+    pub ask_depth: i32,
+    pub weighted_mid: f32, // volume weighted price of the top `levels` of both sides
+    pub pressure: f32,     // like imbalance over the top `levels`, level i weighted by 1 / (i + 1)
+}
+
+impl BookMetrics {
+    /// None unless both sides are quoted
+    pub fn compute(columns: &PriceColumns, config: &MetricsConfig) -> Option<Self> {
+        let (best_bid, bid_top) = columns.bids.last_key_value()?;
+        let (best_ask, ask_top) = columns.asks.first_key_value()?;
+        let (best_bid, best_ask) = (best_bid.into_inner(), best_ask.into_inner());
+        let (bid_qty, ask_qty) = (bid_top.total_quantity as f32, ask_top.total_quantity as f32);
+        let top_qty = bid_qty + ask_qty;
+        let (imbalance, microprice) = if top_qty > 0.0 {
+            (
+                (bid_qty - ask_qty) / top_qty,
+                (best_bid * ask_qty + best_ask * bid_qty) / top_qty,
+            )
+        } else {
+            (0.0, (best_bid + best_ask) / 2.0)
+        };
+
+        let reach = config.tick_size * config.depth_ticks as f32;
+        // small epsilon so levels exactly N ticks away aren't lost to float error
+        let epsilon = config.tick_size * 1e-3;
+        let bid_depth = columns
+            .bids
+            .range(OrderedFloat(best_bid - reach - epsilon)..)
+            .map(|(_, holding)| holding.total_quantity)
+            .sum();
+        let ask_depth = columns
+            .asks
+            .range(..=OrderedFloat(best_ask + reach + epsilon))
+            .map(|(_, holding)| holding.total_quantity)
+            .sum();
+
+        let bids = top_levels(&columns.bids, config.levels, true);
+        let asks = top_levels(&columns.asks, config.levels, false);
+        let (amount, volume) = bids
+            .iter()
+            .chain(asks.iter())
+            .fold((0.0, 0.0), |(amount, volume), (price, qty)| {
+                (amount + price * qty, volume + qty)
+            });
+        let weighted_mid = if volume > 0.0 {
+            amount / volume
+        } else {
+            (best_bid + best_ask) / 2.0
+        };
+        let decayed = |levels: &[(f32, f32)]| -> f32 {
+            levels
+                .iter()
+                .enumerate()
+                .map(|(i, (_, qty))| qty / (i + 1) as f32)
+                .sum()
+        };
+        let (bid_weight, ask_weight) = (decayed(&bids), decayed(&asks));
+        let pressure = if bid_weight + ask_weight > 0.0 {
+            (bid_weight - ask_weight) / (bid_weight + ask_weight)
+        } else {
+            0.0
+        };
+
+        Some(Self {
+            best_bid,
+            best_ask,
+            imbalance,
+            microprice,
+            bid_depth,
+            ask_depth,
+            weighted_mid,
+            pressure,
+        })
+    }
+}
+
+/// (price, qty) of the best `count` levels of a side, best first
+fn top_levels(row: &PriceRow, count: usize, is_bid: bool) -> Vec<(f32, f32)> {
+    let level = |(price, holding): (&OrderedFloat<f32>, &CurrentHoldingPerPrice)| {
+        (price.into_inner(), holding.total_quantity as f32)
+    };
+    if is_bid {
+        row.iter().rev().take(count).map(level).collect()
+    } else {
+        row.iter().take(count).map(level).collect()
+    }
+}
@@ -2,4 +2,28 @@ mod tools;
 pub use tools::*;
 mod book;
 pub use book::*;
+mod consolidated;
+pub use consolidated::*;
+mod router;
+pub use router::*;
+mod arbitrage;
+pub use arbitrage::*;
+mod parity;
+pub use parity::*;
+mod tape;
+pub use tape::*;
+mod candles;
+pub use candles::*;
+mod metrics;
+pub use metrics::*;
+mod impact;
+pub use impact::*;
+mod position;
+pub use position::*;
+mod account;
+pub use account::*;
+mod risk;
+pub use risk::*;
+mod kill_switch;
+pub use kill_switch::*;
 mod tests;
\ No newline at end of file
@@ -0,0 +1,297 @@
+use super::{BestBidOffer, ConsolidatedBook, Instrument, InstrumentType, TradeRequest, VenueQuote};
+use crate::exchanges::VenueSpec;
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, HashMap};
+
+/// forward prices of the underlying per expiry, falling back to the index (spot) price
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct ForwardCurve {
+    pub index_price: f32,
+    pub forwards: BTreeMap<NaiveDate, f32>, // e.g. from the futures expiring on that date
+}
+
+impl ForwardCurve {
+    pub fn new(index_price: f32) -> Self {
+        Self {
+            index_price,
+            forwards: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_forward(mut self, expiry: NaiveDate, forward: f32) -> Self {
+        self.forwards.insert(expiry, forward);
+        self
+    }
+
+    pub fn forward(&self, expiry: &NaiveDate) -> f32 {
+        self.forwards
+            .get(expiry)
+            .copied()
+            .unwrap_or(self.index_price)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ParityStrategy {
+    Conversion, // buy call, sell put, synthetic long is too cheap
+    Reversal,   // sell call, buy put, synthetic long is too rich
+    LongBox,    // the box costs less than it pays
+    ShortBox,   // the box can be sold for more than it pays
+}
+
+/// one option traded as part of a parity strategy
+#[derive(PartialEq, Debug, Clone)]
+pub struct ParityLeg {
+    pub instrument: Instrument,
+    pub request: TradeRequest, // Bid buys the option, Ask sells it
+    pub exchange: String,
+    pub price: f32,
+    pub fee: f32,      // per unit of the underlying
+    pub quantity: f32, // underlying units available at the price
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ParityViolation {
+    pub strategy: ParityStrategy,
+    pub asset: String,
+    pub expiry: NaiveDate,
+    pub strikes: Vec<i64>,
+    pub legs: Vec<ParityLeg>,
+    pub forward: f32,
+    pub fair_value: f32,  // premium (in BTC) the combination is worth
+    pub net_premium: f32, // premium paid (negative when received), fees included
+    pub edge: f32,        // per unit of the underlying, after fees
+    pub size: f32,
+}
+
+type StrikeKey = (String, NaiveDate, i64);
+
+/// Flags put-call parity violations and box spreads on inverse (coin-margined) options,
+/// taking each leg from whichever venue quotes it best after fees.
+/// With premiums quoted in the underlying, parity reads `C - P = 1 - K / F`
+pub struct ParityScanner {
+    pub venues: HashMap<String, VenueSpec>,
+    pub min_edge: f32,
+}
+
+impl Default for ParityScanner {
+    fn default() -> Self {
+        Self::new(vec![VenueSpec::deribit(), VenueSpec::okex()], 0.0)
+    }
+}
+
+impl ParityScanner {
+    pub fn new(venues: Vec<VenueSpec>, min_edge: f32) -> Self {
+        Self {
+            venues: venues
+                .into_iter()
+                .map(|venue| (venue.name.clone(), venue))
+                .collect(),
+            min_edge,
+        }
+    }
+
+    /// best venue to buy (`Bid`) or sell (`Ask`) an option at, net of fees
+    fn best_leg(
+        &self,
+        instrument: &Instrument,
+        quotes: &[BestBidOffer],
+        request: TradeRequest,
+    ) -> Option<ParityLeg> {
+        let buying = !request.is_ask();
+        quotes
+            .iter()
+            .filter_map(|quote| {
+                if buying {
+                    quote.ask.as_ref()
+                } else {
+                    quote.bid.as_ref()
+                }
+            })
+            .filter_map(|quote: &VenueQuote| {
+                let venue = self.venues.get(&quote.exchange)?;
+                Some(ParityLeg {
+                    instrument: instrument.clone(),
+                    request: request.clone(),
+                    exchange: quote.exchange.clone(),
+                    price: quote.price,
+                    fee: venue.taker_fee_per_unit(quote.price),
+                    quantity: venue.to_underlying(quote.quantity),
+                })
+            })
+            .min_by(|a, b| {
+                // cheapest all-in price to buy, highest all-in price to sell
+                let (a, b) = if buying {
+                    (a.price + a.fee, b.price + b.fee)
+                } else {
+                    (b.price - b.fee, a.price - a.fee)
+                };
+                a.total_cmp(&b)
+            })
+    }
+
+    fn violation(
+        &self,
+        strategy: ParityStrategy,
+        (asset, expiry): (&str, NaiveDate),
+        strikes: Vec<i64>,
+        legs: Vec<ParityLeg>,
+        forward: f32,
+        fair_value: f32,
+    ) -> Option<ParityViolation> {
+        let net_premium: f32 = legs
+            .iter()
+            .map(|leg| {
+                if leg.request.is_ask() {
+                    -(leg.price - leg.fee)
+                } else {
+                    leg.price + leg.fee
+                }
+            })
+            .sum();
+        // buying a combination (conversion, long box) earns fair value minus what we paid
+        let edge = match strategy {
+            ParityStrategy::Conversion | ParityStrategy::LongBox => fair_value - net_premium,
+            ParityStrategy::Reversal | ParityStrategy::ShortBox => -net_premium - fair_value,
+        };
+        if edge <= self.min_edge {
+            return None;
+        }
+        let size = legs
+            .iter()
+            .map(|leg| leg.quantity)
+            .fold(f32::INFINITY, f32::min);
+        Some(ParityViolation {
+            strategy,
+            asset: asset.to_owned(),
+            expiry,
+            strikes,
+            legs,
+            forward,
+            fair_value,
+            net_premium,
+            edge,
+            size,
+        })
+    }
+
+    /// scans quotes (per instrument, one entry per venue) for parity and box violations
+    pub fn scan_quotes(
+        &self,
+        quotes: &HashMap<Instrument, Vec<BestBidOffer>>,
+        forwards: &ForwardCurve,
+    ) -> Vec<ParityViolation> {
+        use ParityStrategy::*;
+        use TradeRequest::*;
+        // (asset, expiry, strike) -> (call, put)
+        let mut strikes: BTreeMap<StrikeKey, (Option<&Instrument>, Option<&Instrument>)> =
+            BTreeMap::new();
+        for instrument in quotes.keys() {
+            let key = (
+                instrument.to_singular_asset().asset,
+                instrument.expiration_date,
+                instrument.strike_price,
+            );
+            let entry = strikes.entry(key).or_default();
+            match instrument.instrument_type {
+                InstrumentType::Call => entry.0 = Some(instrument),
+                InstrumentType::Pull => entry.1 = Some(instrument),
+            }
+        }
+        let pairs: Vec<(&StrikeKey, &Instrument, &Instrument)> = strikes
+            .iter()
+            .filter_map(|(key, pair)| match pair {
+                (Some(call), Some(put)) => Some((key, *call, *put)),
+                _ => None,
+            })
+            .collect();
+        let leg = |instrument: &Instrument, request: TradeRequest| {
+            self.best_leg(instrument, &quotes[instrument], request)
+        };
+
+        let mut violations = Vec::new();
+        for ((asset, expiry, strike), call, put) in &pairs {
+            let forward = forwards.forward(expiry);
+            let fair_value = 1.0 - *strike as f32 / forward;
+            let group = (asset.as_str(), *expiry);
+            if let (Some(call_leg), Some(put_leg)) = (leg(call, Bid), leg(put, Ask)) {
+                violations.extend(self.violation(
+                    Conversion,
+                    group,
+                    vec![*strike],
+                    vec![call_leg, put_leg],
+                    forward,
+                    fair_value,
+                ));
+            }
+            if let (Some(call_leg), Some(put_leg)) = (leg(call, Ask), leg(put, Bid)) {
+                violations.extend(self.violation(
+                    Reversal,
+                    group,
+                    vec![*strike],
+                    vec![call_leg, put_leg],
+                    forward,
+                    fair_value,
+                ));
+            }
+        }
+
+        // boxes: long call K1, short call K2, long put K2, short put K1 pays K2 - K1
+        for (i, ((asset, expiry, low), low_call, low_put)) in pairs.iter().enumerate() {
+            for ((other_asset, other_expiry, high), high_call, high_put) in &pairs[i + 1..] {
+                if asset != other_asset || expiry != other_expiry {
+                    continue;
+                }
+                let forward = forwards.forward(expiry);
+                let fair_value = (*high - *low) as f32 / forward;
+                let group = (asset.as_str(), *expiry);
+                let long_box = (
+                    leg(low_call, Bid),
+                    leg(high_call, Ask),
+                    leg(high_put, Bid),
+                    leg(low_put, Ask),
+                );
+                if let (Some(a), Some(b), Some(c), Some(d)) = long_box {
+                    violations.extend(self.violation(
+                        LongBox,
+                        group,
+                        vec![*low, *high],
+                        vec![a, b, c, d],
+                        forward,
+                        fair_value,
+                    ));
+                }
+                let short_box = (
+                    leg(low_call, Ask),
+                    leg(high_call, Bid),
+                    leg(high_put, Ask),
+                    leg(low_put, Bid),
+                );
+                if let (Some(a), Some(b), Some(c), Some(d)) = short_box {
+                    violations.extend(self.violation(
+                        ShortBox,
+                        group,
+                        vec![*low, *high],
+                        vec![a, b, c, d],
+                        forward,
+                        fair_value,
+                    ));
+                }
+            }
+        }
+        violations
+    }
+
+    pub async fn scan(
+        &self,
+        book: &ConsolidatedBook<'_>,
+        forwards: &ForwardCurve,
+    ) -> Vec<ParityViolation> {
+        let mut quotes = HashMap::new();
+        for instrument in book.instruments().await {
+            let venue_quotes = book.venue_quotes(&instrument).await;
+            quotes.insert(instrument, venue_quotes);
+        }
+        self.scan_quotes(&quotes, forwards)
+    }
+}
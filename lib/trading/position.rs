@@ -0,0 +1,64 @@
+use super::Instrument;
+
+/// (exchange, canonical instrument), positions are kept per venue
+pub type VenueKey = (String, Instrument);
+
+/// quantities smaller than this are flat, well below the smallest contract (0.01 on okex) but
+/// above the rounding left by adding up f32 fills
+const FLAT_QUANTITY: f32 = 1e-6;
+
+/// A net position in one instrument. Quantities are in underlying units (negative when short),
+/// prices, pnl and fees in the premium currency
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Position {
+    pub quantity: f32,
+    pub avg_price: f32, // average entry of the open quantity
+    pub realized_pnl: f32,
+    pub fees: f32,
+}
+
+impl Position {
+    /// applies a fill of `quantity` (negative for sells) at `price`, returning the pnl it realized
+    pub fn apply_fill(&mut self, quantity: f32, price: f32, fee: f32) -> f32 {
+        self.fees += fee;
+        if quantity == 0.0 {
+            return 0.0;
+        }
+        let same_direction = self.is_flat() || self.quantity.signum() == quantity.signum();
+        if same_direction {
+            let size = self.quantity.abs() + quantity.abs();
+            self.avg_price = (self.avg_price * self.quantity.abs() + price * quantity.abs()) / size;
+            self.quantity += quantity;
+            self.snap_flat();
+            return 0.0;
+        }
+
+        let closed = quantity.abs().min(self.quantity.abs());
+        let realized = (price - self.avg_price) * closed * self.quantity.signum();
+        self.realized_pnl += realized;
+        let remaining = self.quantity + quantity;
+        if remaining.signum() != self.quantity.signum() {
+            // flipped, what is left was opened at this price
+            self.avg_price = price;
+        }
+        self.quantity = remaining;
+        self.snap_flat();
+        realized
+    }
+
+    pub fn unrealized_pnl(&self, mark: f32) -> f32 {
+        (mark - self.avg_price) * self.quantity
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.quantity.abs() < FLAT_QUANTITY
+    }
+
+    /// clears what rounding leaves of a closed position
+    fn snap_flat(&mut self) {
+        if self.is_flat() {
+            self.quantity = 0.0;
+            self.avg_price = 0.0;
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use super::{KillReason, Order, TradeRequest};
+use crate::analytics::Greeks;
+use std::fmt;
+
+/// Pre-trade limits checked before an order reaches a book, None disables a check.
+/// Quantities are in underlying units, prices and notionals in the premium currency
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct RiskLimits {
+    pub max_order_size: Option<f32>,    // underlying units
+    pub max_notional: Option<f32>,      // price times quantity
+    pub price_band: Option<f32>,        // max distance from the midprice, as a fraction of it
+    pub max_open_orders: Option<usize>, // per instrument
+    pub max_position: Option<f32>, // per instrument, as if every open order on that side filled
+    pub max_delta: Option<f64>,    // of the whole account once the order fills
+    pub max_gamma: Option<f64>,
+    pub max_vega: Option<f64>,
+}
+
+/// why an order was rejected
+#[derive(PartialEq, Debug, Clone)]
+pub enum RiskRejection {
+    UnknownVenue(String),
+    Halted(KillReason), // by the kill switch
+    OrderSize {
+        quantity: f32,
+        limit: f32,
+    },
+    Notional {
+        notional: f32,
+        limit: f32,
+    },
+    PriceBand {
+        price: f32,
+        midprice: f32,
+        band: f32,
+    },
+    NoMidprice, // a price band is set but the book isn't two sided
+    OpenOrders {
+        open: usize,
+        limit: usize,
+    },
+    Position {
+        projected: f32,
+        limit: f32,
+    },
+    Greek {
+        greek: &'static str,
+        projected: f64,
+        limit: f64,
+    },
+    NoGreeks, // greeks limits are set but the instrument or a held position can't be priced
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RiskRejection::*;
+        match self {
+            UnknownVenue(venue) => write!(f, "no book for venue {venue}"),
+            Halted(reason) => write!(f, "trading is halted, {reason}"),
+            OrderSize { quantity, limit } => {
+                write!(f, "order size {quantity} exceeds the limit of {limit}")
+            }
+            Notional { notional, limit } => {
+                write!(f, "notional {notional} exceeds the limit of {limit}")
+            }
+            PriceBand {
+                price,
+                midprice,
+                band,
+            } => write!(
+                f,
+                "price {price} is more than {band} away from the mid {midprice}"
+            ),
+            NoMidprice => write!(f, "no midprice to check the price band against"),
+            OpenOrders { open, limit } => {
+                write!(f, "{open} open orders already, the limit is {limit}")
+            }
+            Position { projected, limit } => {
+                write!(f, "position would reach {projected}, the limit is {limit}")
+            }
+            Greek {
+                greek,
+                projected,
+                limit,
+            } => write!(f, "{greek} would reach {projected}, the limit is {limit}"),
+            NoGreeks => write!(f, "no greeks to check the limits against"),
+        }
+    }
+}
+
+impl std::error::Error for RiskRejection {}
+
+/// what the checks need to know about the account and the market
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct RiskState {
+    pub midprice: Option<f32>,
+    pub open_orders: usize, // on the instrument
+    pub position: f32,
+    pub open_bids: f32, // unfilled quantity of open orders on the instrument
+    pub open_asks: f32,
+    pub portfolio_greeks: Option<Greeks>, // None when a held position can't be priced
+    pub instrument_greeks: Option<Greeks>, // per unit of the underlying
+}
+
+impl RiskLimits {
+    /// checks an order of `quantity` underlying units, the first failing check is returned
+    pub fn check(
+        &self,
+        order: &Order,
+        quantity: f32,
+        state: &RiskState,
+    ) -> Result<(), RiskRejection> {
+        if let Some(limit) = self.max_order_size {
+            if quantity > limit {
+                return Err(RiskRejection::OrderSize { quantity, limit });
+            }
+        }
+        if let Some(limit) = self.max_notional {
+            let notional = order.price * quantity;
+            if notional > limit {
+                return Err(RiskRejection::Notional { notional, limit });
+            }
+        }
+        if let Some(band) = self.price_band {
+            let midprice = state.midprice.ok_or(RiskRejection::NoMidprice)?;
+            if (order.price - midprice).abs() > band * midprice {
+                return Err(RiskRejection::PriceBand {
+                    price: order.price,
+                    midprice,
+                    band,
+                });
+            }
+        }
+        if let Some(limit) = self.max_open_orders {
+            if state.open_orders >= limit {
+                return Err(RiskRejection::OpenOrders {
+                    open: state.open_orders,
+                    limit,
+                });
+            }
+        }
+        let signed = match order.request {
+            TradeRequest::Bid => quantity,
+            TradeRequest::Ask => -quantity,
+        };
+        if let Some(limit) = self.max_position {
+            let projected = match order.request {
+                TradeRequest::Bid => state.position + state.open_bids + quantity,
+                TradeRequest::Ask => state.position - state.open_asks - quantity,
+            };
+            if projected.abs() > limit {
+                return Err(RiskRejection::Position { projected, limit });
+            }
+        }
+        let greek_limits = [
+            ("delta", self.max_delta),
+            ("gamma", self.max_gamma),
+            ("vega", self.max_vega),
+        ];
+        if greek_limits.iter().any(|(_, limit)| limit.is_some()) {
+            let greeks = state.instrument_greeks.ok_or(RiskRejection::NoGreeks)?;
+            let portfolio = state.portfolio_greeks.ok_or(RiskRejection::NoGreeks)?;
+            let signed = signed as f64;
+            let projected = [
+                portfolio.delta + greeks.delta * signed,
+                portfolio.gamma + greeks.gamma * signed,
+                portfolio.vega + greeks.vega * signed,
+            ];
+            for ((greek, limit), projected) in greek_limits.into_iter().zip(projected) {
+                match limit {
+                    Some(limit) if projected.abs() > limit => {
+                        return Err(RiskRejection::Greek {
+                            greek,
+                            projected,
+                            limit,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
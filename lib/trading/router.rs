@@ -0,0 +1,207 @@
+use super::{ConsolidatedBook, Instrument, Order, OrderBook, TradeRequest, VenueQuote};
+use crate::exchanges::VenueSpec;
+use crate::utils::system_clock;
+use std::collections::HashMap;
+
+/// a slice of the parent order sent to one price level of a venue
+#[derive(PartialEq, Debug, Clone)]
+pub struct RouteLeg {
+    pub exchange: String,
+    pub price: f32,
+    pub contracts: i32,  // in the venue's contract size
+    pub underlying: f32, // same quantity in underlying units
+    pub fee: f32,        // expected taker fee for the leg
+}
+
+/// how a parent order would be split across venues and what it is expected to cost
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct RoutePlan {
+    pub request: TradeRequest,
+    pub legs: Vec<RouteLeg>,
+    pub requested: f32, // underlying units
+    pub filled: f32,
+    pub best_price: Option<f32>, // best consolidated price before routing
+    pub avg_price: f32,
+    pub avg_price_with_fees: f32,
+    pub total_fees: f32,
+    pub slippage: f32, // how much worse than the best price the average is
+    pub slippage_bps: f32,
+}
+
+impl RoutePlan {
+    pub fn unfilled(&self) -> f32 {
+        (self.requested - self.filled).max(0.0)
+    }
+
+    /// contracts to send to each venue, along with the worst price we cross on it
+    pub fn per_venue(&self) -> Vec<(String, i32, f32)> {
+        let mut venues: Vec<(String, i32, f32)> = Vec::new();
+        for leg in &self.legs {
+            match venues.iter_mut().find(|(name, _, _)| *name == leg.exchange) {
+                Some((_, contracts, worst)) => {
+                    *contracts += leg.contracts;
+                    *worst = if self.request.is_ask() {
+                        worst.min(leg.price)
+                    } else {
+                        worst.max(leg.price)
+                    };
+                }
+                None => venues.push((leg.exchange.clone(), leg.contracts, leg.price)),
+            }
+        }
+        venues
+    }
+}
+
+/// Splits parent orders across venues based on consolidated depth, taker fees and contract sizes
+pub struct SmartOrderRouter {
+    pub venues: HashMap<String, VenueSpec>,
+}
+
+impl Default for SmartOrderRouter {
+    fn default() -> Self {
+        Self::new(vec![VenueSpec::deribit(), VenueSpec::okex()])
+    }
+}
+
+impl SmartOrderRouter {
+    pub fn new(venues: Vec<VenueSpec>) -> Self {
+        Self {
+            venues: venues
+                .into_iter()
+                .map(|venue| (venue.name.clone(), venue))
+                .collect(),
+        }
+    }
+
+    /// Plans a parent order of `quantity` underlying units. `request` is the side of the parent order,
+    /// so a Bid lifts the consolidated asks. Levels beyond `limit_price` are never used
+    pub async fn plan(
+        &self,
+        book: &ConsolidatedBook<'_>,
+        instrument: &Instrument,
+        request: TradeRequest,
+        quantity: f32,
+        limit_price: Option<f32>,
+    ) -> RoutePlan {
+        let is_sell = request.is_ask();
+        let opposite = if is_sell {
+            TradeRequest::Bid
+        } else {
+            TradeRequest::Ask
+        };
+        let levels = book.levels(instrument, &opposite).await;
+        let best_price = levels.first().map(|level| level.price);
+
+        // rank levels by what they really cost (or pay) once taker fees are included
+        let mut ranked: Vec<(f32, &VenueQuote, &VenueSpec)> = levels
+            .iter()
+            .filter_map(|level| {
+                let venue = self.venues.get(&level.exchange)?;
+                let fee = venue.taker_fee_per_unit(level.price);
+                let effective = if is_sell {
+                    level.price - fee
+                } else {
+                    level.price + fee
+                };
+                Some((effective, level, venue))
+            })
+            .filter(|(_, level, _)| match limit_price {
+                Some(limit) if is_sell => level.price >= limit,
+                Some(limit) => level.price <= limit,
+                None => true,
+            })
+            .collect();
+        if is_sell {
+            ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+        } else {
+            ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+
+        let mut plan = RoutePlan {
+            request,
+            requested: quantity,
+            best_price,
+            ..Default::default()
+        };
+        let mut remaining = quantity;
+        let mut notional = 0.0;
+        for (_, level, venue) in ranked {
+            if remaining <= 0.0 {
+                break;
+            }
+            let contracts = venue.to_contracts(remaining).min(level.quantity);
+            if contracts <= 0 {
+                continue;
+            }
+            let underlying = venue.to_underlying(contracts);
+            let fee = venue.taker_fee_per_unit(level.price) * underlying;
+            remaining -= underlying;
+            notional += level.price * underlying;
+            plan.filled += underlying;
+            plan.total_fees += fee;
+            plan.legs.push(RouteLeg {
+                exchange: level.exchange.clone(),
+                price: level.price,
+                contracts,
+                underlying,
+                fee,
+            });
+        }
+
+        if plan.filled > 0.0 {
+            plan.avg_price = notional / plan.filled;
+            plan.avg_price_with_fees = if is_sell {
+                (notional - plan.total_fees) / plan.filled
+            } else {
+                (notional + plan.total_fees) / plan.filled
+            };
+            if let Some(best) = best_price {
+                plan.slippage = if is_sell {
+                    best - plan.avg_price
+                } else {
+                    plan.avg_price - best
+                };
+                plan.slippage_bps = plan.slippage / best * 10_000.0;
+            }
+        }
+        plan
+    }
+
+    /// Executes a plan against the in-memory books: one child order per venue is matched
+    /// with `match_orders` against that venue only. Returns the child orders, unfilled
+    /// remainders are cancelled
+    pub async fn execute(
+        &self,
+        book: &ConsolidatedBook<'_>,
+        instrument: &Instrument,
+        plan: &RoutePlan,
+    ) -> anyhow::Result<Vec<Order>> {
+        // children live on the same time as the venues they are matched against
+        let clock = match book.books.first() {
+            Some(venue) => venue.lock().await.clock.clone(),
+            None => system_clock(),
+        };
+        let mut router_book = OrderBook::with_clock("router", clock);
+        router_book.add_asset(instrument.clone());
+        let mut children = Vec::new();
+
+        for (exchange, contracts, worst_price) in plan.per_venue() {
+            let Some(venue_book) = book.book(&exchange).await else {
+                continue;
+            };
+            let child = router_book
+                .new_order(worst_price, contracts, plan.request.clone())
+                .with_tag(&exchange);
+            let child_id = child.id;
+            router_book.add_order(child, instrument)?;
+            router_book.match_orders(instrument, venue_book).await?;
+            router_book.cancel_order(instrument, child_id);
+
+            if let Some(table) = router_book.table_for(instrument) {
+                children.extend(table.history.iter().filter(|o| o.id == child_id).cloned());
+            }
+        }
+        Ok(children)
+    }
+}
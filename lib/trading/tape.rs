@@ -0,0 +1,73 @@
+use crate::exchanges::Trade;
+use std::collections::VecDeque;
+
+pub const DEFAULT_TAPE_CAPACITY: usize = 1000;
+
+/// the most recent public trades of an instrument, oldest trades are dropped once full.
+/// A capacity of 0 keeps the last trade only
+#[derive(Debug, Clone)]
+pub struct TradeTape {
+    pub capacity: usize,
+    pub trades: VecDeque<Trade>,
+}
+
+impl Default for TradeTape {
+    fn default() -> Self {
+        Self::new(DEFAULT_TAPE_CAPACITY)
+    }
+}
+
+impl TradeTape {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            trades: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Adds a trade, ignoring trade ids we have already seen (reconnects replay recent prints).
+    /// Returns whether the trade was new
+    pub fn push(&mut self, trade: Trade) -> bool {
+        if self
+            .trades
+            .iter()
+            .rev()
+            .any(|seen| seen.trade_id == trade.trade_id)
+        {
+            return false;
+        }
+        while self.trades.len() >= self.capacity.max(1) {
+            self.trades.pop_front();
+        }
+        self.trades.push_back(trade);
+        true
+    }
+
+    pub fn last(&self) -> Option<&Trade> {
+        self.trades.back()
+    }
+
+    /// newest first
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &Trade> {
+        self.trades.iter().rev().take(count)
+    }
+
+    /// volume weighted average price of the trades on the tape
+    pub fn vwap(&self) -> Option<f32> {
+        let volume: f32 = self.trades.iter().map(|trade| trade.size).sum();
+        let notional: f32 = self
+            .trades
+            .iter()
+            .map(|trade| trade.price * trade.size)
+            .sum();
+        (volume > 0.0).then(|| notional / volume)
+    }
+
+    pub fn len(&self) -> usize {
+        self.trades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+}
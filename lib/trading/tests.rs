@@ -29,7 +29,7 @@ mod order_book {
             .lock()
             .await
             .match_orders(&instrument, second_order_book.clone())
-            .await;
+            .await?;
         //order_book.match_orders(&instrument, None);
         // both asks are completed with  higher bid of 90,
 
@@ -40,13 +40,16 @@ mod order_book {
         ];
 
         for order in second_orders {
-            second_order_book.lock().await.add_order(order, &instrument)
+            second_order_book
+                .lock()
+                .await
+                .add_order(order, &instrument)?;
         }
         second_order_book
             .lock()
             .await
             .match_orders(&instrument, order_book.clone())
-            .await;
+            .await?;
 
         /*  dbg!(&second_order_book,&order_book);  uncomment to inspect the structures */
         let mut lock = second_order_book.lock().await;
@@ -67,3 +70,1301 @@ mod order_book {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod order_ids {
+    use crate::{
+        exchanges::ExchangeType,
+        trading::{Instrument, Order, OrderBook, TradeRequest},
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn orders_created_together_get_unique_ids() {
+        let orders: Vec<_> = (0..1000)
+            .map(|_| Order::new(0.5, 1, TradeRequest::Ask))
+            .collect();
+        let ids: HashSet<_> = orders.iter().map(|o| o.id).collect();
+        assert_eq!(ids.len(), orders.len());
+        assert!(orders.windows(2).all(|pair| pair[0].id < pair[1].id));
+    }
+
+    #[test]
+    fn cancel_and_lookup_use_the_right_order() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-USD-240427-56000-C", ExchangeType::Okex)?;
+        let mut order_book = OrderBook::new("test");
+        order_book.add_asset(instrument.clone());
+
+        let first = Order::new(0.72, 30, TradeRequest::Ask).with_client_order_id("first");
+        let second = Order::new(0.72, 20, TradeRequest::Ask)
+            .with_client_order_id("second")
+            .with_tag("hedge");
+        let (first_id, second_id) = (first.id, second.id);
+        order_book.add_order(first, &instrument);
+        order_book.add_order(second, &instrument);
+
+        let found = order_book
+            .get_order_by_client_id(&instrument, "second")
+            .unwrap();
+        assert_eq!(found.id, second_id);
+        assert_eq!(found.tag.as_deref(), Some("hedge"));
+
+        let cancelled = order_book.cancel_order(&instrument, first_id).unwrap();
+        assert_eq!(cancelled.client_order_id.as_deref(), Some("first"));
+        assert!(order_book.get_order(&instrument, first_id).is_none());
+        assert!(order_book.get_order(&instrument, second_id).is_some());
+
+        let cols = order_book.asset_order_table.get(&instrument).unwrap();
+        let level = cols.asks.get(&0.72.into()).unwrap();
+        assert_eq!(level.total_quantity, 20);
+        assert_eq!(level.orders.len(), 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod order_state {
+    use crate::{
+        exchanges::ExchangeType,
+        trading::{Instrument, MatchedOrders, Order, OrderBook, OrderStatus, TradeRequest},
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn assert_invariants(order: &Order) {
+        let filled: i32 = order.filled_with.iter().map(|fill| fill.quantity).sum();
+        assert_eq!(order.filled_qty, filled);
+        assert_eq!(order.remaining_qty, order.quantity - order.filled_qty);
+        assert!(order.remaining_qty >= 0);
+        match order.status {
+            OrderStatus::New => assert_eq!(order.filled_qty, 0),
+            OrderStatus::PartiallyFilled => {
+                assert!(order.filled_qty > 0 && order.remaining_qty > 0)
+            }
+            OrderStatus::Filled => assert_eq!(order.remaining_qty, 0),
+            OrderStatus::Cancelled => {}
+        }
+    }
+
+    #[test]
+    fn fills_move_order_through_its_states() -> anyhow::Result<()> {
+        let mut order = Order::new(0.70, 30, TradeRequest::Bid);
+        assert_eq!(order.status, OrderStatus::New);
+        assert_invariants(&order);
+
+        order.record_fill(MatchedOrders::new(0.60, 10, "okex"))?;
+        assert_eq!(order.status, OrderStatus::PartiallyFilled);
+        assert_invariants(&order);
+
+        order.record_fill(MatchedOrders::new(0.66, 20, "okex"))?;
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert!((order.avg_fill_price - 0.64).abs() < 1e-6);
+        assert_invariants(&order);
+
+        // no overfills and no leaving a terminal state
+        assert!(order
+            .record_fill(MatchedOrders::new(0.66, 1, "okex"))
+            .is_err());
+        assert!(order.cancel().is_err());
+
+        let mut partial = Order::new(0.70, 30, TradeRequest::Bid);
+        assert!(partial
+            .record_fill(MatchedOrders::new(0.60, 31, "okex"))
+            .is_err());
+        partial.record_fill(MatchedOrders::new(0.60, 5, "okex"))?;
+        partial.cancel()?;
+        assert!(partial.status.is_cancelled());
+        assert_invariants(&partial);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn matching_keeps_fill_accounting_consistent() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-USD-240427-56000-C", ExchangeType::Okex)?;
+        let ours = Arc::new(Mutex::new(OrderBook::new("ours")));
+        let theirs = Arc::new(Mutex::new(OrderBook::new("theirs")));
+        ours.lock().await.add_asset(instrument.clone());
+        theirs.lock().await.add_asset(instrument.clone());
+
+        for order in [
+            Order::new(0.72, 30, TradeRequest::Ask),
+            Order::new(0.73, 20, TradeRequest::Ask),
+        ] {
+            theirs.lock().await.add_order(order, &instrument);
+        }
+        let bid = Order::new(0.90, 40, TradeRequest::Bid);
+        let untouched = Order::new(0.10, 5, TradeRequest::Bid);
+        let (bid_id, untouched_id) = (bid.id, untouched.id);
+        ours.lock().await.add_order(bid, &instrument);
+        ours.lock().await.add_order(untouched, &instrument);
+
+        ours.lock()
+            .await
+            .match_orders(&instrument, theirs.clone())
+            .await?;
+
+        let book = ours.lock().await;
+        let cols = book.asset_order_table.get(&instrument).unwrap();
+        let filled = cols.history.iter().find(|o| o.id == bid_id).unwrap();
+        assert!(filled.is_completed());
+        assert_eq!(filled.filled_qty, 40);
+        assert!((filled.avg_fill_price - (0.72 * 30.0 + 0.73 * 10.0) / 40.0).abs() < 1e-6);
+        // the filled bid no longer rests in our book, the unmatched one does
+        assert!(!cols.bids.contains_key(&0.90.into()));
+        let pending = book.get_order(&instrument, untouched_id).unwrap();
+        assert_eq!(pending.status, OrderStatus::New);
+
+        let orders = cols.orders.lock().unwrap();
+        orders
+            .iter()
+            .chain(cols.history.iter())
+            .for_each(assert_invariants);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cross_book {
+    use crate::{
+        exchanges::{ExchangeType, VenueSpec},
+        trading::{Instrument, Order, OrderBook, OrderStatus, TradeRequest},
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn matching_updates_both_books() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-USD-240427-56000-C", ExchangeType::Okex)?;
+        let ours = Arc::new(Mutex::new(OrderBook::new("deribit")));
+        let theirs = Arc::new(Mutex::new(OrderBook::new("okex")));
+        ours.lock().await.add_asset(instrument.clone());
+        theirs.lock().await.add_asset(instrument.clone());
+
+        // 10 BTC at each price, in okex contracts
+        let best_bid = Order::new(0.80, 1000, TradeRequest::Bid);
+        let next_bid = Order::new(0.75, 1000, TradeRequest::Bid);
+        let (best_id, next_id) = (best_bid.id, next_bid.id);
+        theirs.lock().await.add_order(best_bid, &instrument);
+        theirs.lock().await.add_order(next_bid, &instrument);
+
+        ours.lock()
+            .await
+            .add_order(Order::new(0.70, 15, TradeRequest::Ask), &instrument);
+        ours.lock()
+            .await
+            .match_orders(&instrument, theirs.clone())
+            .await?;
+
+        let ours = ours.lock().await;
+        let ours_cols = ours.asset_order_table.get(&instrument).unwrap();
+        let our_fills: i32 = ours_cols.history.iter().map(|o| o.filled_qty).sum();
+        assert_eq!(our_fills, 15);
+        assert!(ours_cols
+            .history
+            .iter()
+            .flat_map(|o| o.filled_with.iter())
+            .all(|fill| fill.exchange == "okex"));
+
+        let theirs = theirs.lock().await;
+        let theirs_cols = theirs.asset_order_table.get(&instrument).unwrap();
+        // the best bid was consumed completely and moved to the external history
+        let consumed = theirs_cols
+            .history
+            .iter()
+            .find(|o| o.id == best_id)
+            .unwrap();
+        assert_eq!(consumed.status, OrderStatus::Filled);
+        assert_eq!(consumed.filled_with[0].exchange, "deribit");
+        assert!(!theirs_cols.bids.contains_key(&0.80.into()));
+
+        let partially = theirs.get_order(&instrument, next_id).unwrap();
+        assert_eq!(partially.status, OrderStatus::PartiallyFilled);
+        assert_eq!(partially.remaining_qty, 500);
+        assert_eq!(
+            theirs_cols.bids.get(&0.75.into()).unwrap().total_quantity,
+            500
+        );
+
+        let their_fills: i32 = theirs_cols
+            .history
+            .iter()
+            .map(|o| o.filled_qty)
+            .sum::<i32>()
+            + partially.filled_qty;
+        // both sides traded the same underlying, each in its own contracts
+        let underlying = VenueSpec::deribit().to_underlying(our_fills);
+        assert_eq!(their_fills, VenueSpec::okex().to_contracts(underlying));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod consolidated {
+    use crate::{
+        exchanges::ExchangeType,
+        trading::{ConsolidatedBook, Instrument, Order, OrderBook, TradeRequest},
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn orders_sweep_all_venues_in_price_order() -> anyhow::Result<()> {
+        let okex_instrument =
+            Instrument::from_exchange_string("BTC-USD-240427-56000-C", ExchangeType::Okex)?;
+        let deribit_instrument =
+            Instrument::from_exchange_string("BTC-27APR24-56000-C", ExchangeType::Delibris)?;
+
+        let ours = Arc::new(Mutex::new(OrderBook::new("ours")));
+        let okex = Arc::new(Mutex::new(OrderBook::new("okex")));
+        let deribit = Arc::new(Mutex::new(OrderBook::new("deribit")));
+        ours.lock().await.add_asset(okex_instrument.clone());
+        okex.lock().await.add_asset(okex_instrument.clone());
+        deribit.lock().await.add_asset(deribit_instrument.clone());
+
+        for (price, qty) in [(0.71, 5), (0.74, 10)] {
+            let order = Order::new(price, qty, TradeRequest::Ask);
+            okex.lock().await.add_order(order, &okex_instrument);
+        }
+        for (price, qty) in [(0.70, 5), (0.72, 10)] {
+            let order = Order::new(price, qty, TradeRequest::Ask);
+            deribit.lock().await.add_order(order, &deribit_instrument);
+        }
+        let bid = Order::new(0.60, 3, TradeRequest::Bid);
+        deribit.lock().await.add_order(bid, &deribit_instrument);
+
+        let consolidated = ConsolidatedBook::new(vec![ours.clone(), okex.clone(), deribit.clone()]);
+        let bbo = consolidated.best_bid_offer(&okex_instrument).await;
+        let (bid, ask) = (bbo.bid.clone().unwrap(), bbo.ask.clone().unwrap());
+        assert_eq!((bid.exchange.as_str(), bid.price), ("deribit", 0.60));
+        assert_eq!((ask.exchange.as_str(), ask.price), ("deribit", 0.70));
+        assert!(!bbo.is_crossed());
+
+        let sweep = Order::new(0.72, 18, TradeRequest::Bid);
+        let sweep_id = sweep.id;
+        ours.lock().await.add_order(sweep, &okex_instrument);
+        consolidated.match_venue("ours", &okex_instrument).await?;
+
+        let ours = ours.lock().await;
+        let cols = ours.table_for(&okex_instrument).unwrap();
+        let filled = cols.history.iter().find(|o| o.id == sweep_id).unwrap();
+        let fills: Vec<_> = filled
+            .filled_with
+            .iter()
+            .map(|fill| (fill.exchange.as_str(), fill.price, fill.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                ("deribit", 0.70, 5),
+                ("okex", 0.71, 5),
+                ("deribit", 0.72, 8)
+            ]
+        );
+        drop(ours);
+
+        // the 0.74 okex level is out of our price, only 2 remain at deribit's 0.72
+        let ask = consolidated
+            .best_bid_offer(&deribit_instrument)
+            .await
+            .ask
+            .unwrap();
+        assert_eq!(
+            (ask.exchange.as_str(), ask.price, ask.quantity),
+            ("deribit", 0.72, 2)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn venues_match_in_underlying_units() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-27APR24-56000-C", ExchangeType::Delibris)?;
+        let okex = Arc::new(Mutex::new(OrderBook::new("okex")));
+        let deribit = Arc::new(Mutex::new(OrderBook::new("deribit")));
+        okex.lock().await.add_asset(instrument.clone());
+        deribit.lock().await.add_asset(instrument.clone());
+
+        // 2.5 BTC offered on okex, we bid 3 BTC on deribit
+        let ask = Order::new(0.05, 250, TradeRequest::Ask);
+        okex.lock().await.add_order(ask, &instrument);
+        let bid = Order::new(0.05, 3, TradeRequest::Bid);
+        let bid_id = bid.id;
+        deribit.lock().await.add_order(bid, &instrument);
+
+        let consolidated = ConsolidatedBook::new(vec![okex.clone(), deribit.clone()]);
+        consolidated.match_venue("deribit", &instrument).await?;
+
+        // only whole deribit contracts trade, the half BTC left stays on okex
+        let bid = deribit.lock().await.get_order(&instrument, bid_id).unwrap();
+        assert_eq!((bid.filled_qty, bid.remaining_qty), (2, 1));
+        let okex = okex.lock().await;
+        let left: Vec<_> = okex
+            .table_for(&instrument)
+            .unwrap()
+            .asks
+            .iter()
+            .map(|(price, level)| (**price, level.total_quantity))
+            .collect();
+        assert_eq!(left, vec![(0.05, 50)]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod router {
+    use crate::{
+        exchanges::{ExchangeType, VenueSpec},
+        trading::{ConsolidatedBook, Instrument, Order, OrderBook, SmartOrderRouter, TradeRequest},
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[test]
+    fn option_fees_are_capped_by_premium() {
+        let deribit = VenueSpec::deribit();
+        assert_eq!(deribit.taker_fee_per_unit(0.05), 0.0003);
+        assert!((deribit.taker_fee_per_unit(0.001) - 0.000125).abs() < 1e-9);
+        assert_eq!(VenueSpec::okex().to_contracts(0.3), 30);
+    }
+
+    #[tokio::test]
+    async fn parent_order_is_split_by_depth_fees_and_contract_size() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-27APR24-56000-C", ExchangeType::Delibris)?;
+        let okex_instrument =
+            Instrument::from_exchange_string("BTC-USD-240427-56000-C", ExchangeType::Okex)?;
+        let deribit = Arc::new(Mutex::new(OrderBook::new("deribit")));
+        let okex = Arc::new(Mutex::new(OrderBook::new("okex")));
+        deribit.lock().await.add_asset(instrument.clone());
+        okex.lock().await.add_asset(okex_instrument.clone());
+        for (price, qty) in [(0.050, 1), (0.060, 2)] {
+            let order = Order::new(price, qty, TradeRequest::Ask);
+            deribit.lock().await.add_order(order, &instrument);
+        }
+        // 150 okex contracts of 0.01 BTC
+        let order = Order::new(0.052, 150, TradeRequest::Ask);
+        okex.lock().await.add_order(order, &okex_instrument);
+
+        let book = ConsolidatedBook::new(vec![deribit.clone(), okex.clone()]);
+        let router = SmartOrderRouter::default();
+        let plan = router
+            .plan(&book, &instrument, TradeRequest::Bid, 2.0, None)
+            .await;
+
+        let legs: Vec<_> = plan
+            .legs
+            .iter()
+            .map(|leg| (leg.exchange.as_str(), leg.price, leg.contracts))
+            .collect();
+        assert_eq!(legs, vec![("deribit", 0.050, 1), ("okex", 0.052, 100)]);
+        assert!((plan.filled - 2.0).abs() < 1e-4);
+        assert!((plan.avg_price - 0.051).abs() < 1e-5);
+        assert!((plan.total_fees - 0.0006).abs() < 1e-6);
+        assert!((plan.slippage - 0.001).abs() < 1e-5);
+        assert!(plan.unfilled() < 1e-4);
+
+        // a limit below the okex level leaves part of the parent unfilled
+        let limited = router
+            .plan(&book, &instrument, TradeRequest::Bid, 2.0, Some(0.051))
+            .await;
+        assert_eq!(limited.legs.len(), 1);
+        assert!((limited.unfilled() - 1.0).abs() < 1e-4);
+
+        let children = router.execute(&book, &instrument, &plan).await?;
+        assert_eq!(children.len(), 2);
+        assert!(children.iter().all(|child| child.is_completed()));
+
+        let okex = okex.lock().await;
+        let okex_cols = okex.table_for(&okex_instrument).unwrap();
+        assert_eq!(
+            okex_cols.asks.get(&0.052.into()).unwrap().total_quantity,
+            50
+        );
+        let deribit = deribit.lock().await;
+        let deribit_cols = deribit.table_for(&instrument).unwrap();
+        assert!(!deribit_cols.asks.contains_key(&0.050.into()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod arbitrage {
+    use crate::{
+        exchanges::ExchangeType,
+        trading::{ArbitrageDetector, BestBidOffer, Instrument, VenueQuote},
+    };
+
+    fn quotes(deribit_ask: (f32, i32), okex_bid: (f32, i32)) -> Vec<BestBidOffer> {
+        vec![
+            BestBidOffer {
+                bid: None,
+                ask: Some(VenueQuote::new("deribit", deribit_ask.0, deribit_ask.1)),
+            },
+            BestBidOffer {
+                bid: Some(VenueQuote::new("okex", okex_bid.0, okex_bid.1)),
+                ask: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn detects_cross_venue_edge_after_fees() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-USD-240427-56000-C", ExchangeType::Okex)?;
+        let mut detector = ArbitrageDetector::default();
+
+        // 2 deribit contracts against 150 okex contracts of 0.01 BTC
+        let found = detector.detect(
+            &instrument,
+            &quotes((0.050, 2), (0.051, 150)),
+            Some(60000.0),
+        );
+        assert_eq!(found.len(), 1);
+        let opportunity = &found[0];
+        assert_eq!(opportunity.instrument.asset, "BTC");
+        assert_eq!(
+            (
+                opportunity.buy_exchange.as_str(),
+                opportunity.sell_exchange.as_str()
+            ),
+            ("deribit", "okex")
+        );
+        assert!((opportunity.size - 1.0).abs() < 1e-4);
+        assert!((opportunity.edge_per_unit - 0.0004).abs() < 1e-6);
+        assert!((opportunity.edge_usd.unwrap() - 24.0).abs() < 1e-2);
+
+        // the same opportunity keeps its first sighting
+        let again = detector.detect(&instrument, &quotes((0.050, 2), (0.051, 150)), None);
+        assert_eq!(again[0].first_seen, opportunity.first_seen);
+        assert!(again[0].lifetime_ms() < 1000);
+
+        // a 1 tick gap is eaten by fees, unless the premium cap kicks in for cheap options
+        assert!(detector
+            .detect(&instrument, &quotes((0.0500, 2), (0.0505, 100)), None)
+            .is_empty());
+        let cheap = detector.detect(&instrument, &quotes((0.0010, 2), (0.0013, 100)), None);
+        assert_eq!(cheap.len(), 1);
+        assert!((cheap[0].edge_per_unit - 0.0000125).abs() < 1e-7);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod parity {
+    use crate::trading::{
+        BestBidOffer, ForwardCurve, Instrument, InstrumentType, ParityScanner, ParityStrategy,
+        TradeRequest, VenueQuote,
+    };
+    use chrono::NaiveDate;
+    use std::collections::HashMap;
+
+    fn option(strike: i64, instrument_type: InstrumentType) -> Instrument {
+        Instrument {
+            asset: "BTC".to_owned(),
+            strike_price: strike,
+            expiration_date: NaiveDate::from_ymd_opt(2024, 4, 27).unwrap(),
+            instrument_type,
+        }
+    }
+
+    fn quote(exchange: &str, bid: f32, ask: f32, qty: i32) -> BestBidOffer {
+        BestBidOffer {
+            bid: Some(VenueQuote::new(exchange, bid, qty)),
+            ask: Some(VenueQuote::new(exchange, ask, qty)),
+        }
+    }
+
+    #[test]
+    fn flags_conversion_across_venues_after_fees() {
+        use InstrumentType::*;
+        let mut quotes = HashMap::new();
+        quotes.insert(
+            option(55000, Call),
+            vec![
+                quote("deribit", 0.085, 0.090, 1),
+                quote("okex", 0.084, 0.095, 100),
+            ],
+        );
+        quotes.insert(
+            option(55000, Pull),
+            vec![
+                quote("deribit", 0.009, 0.012, 1),
+                quote("okex", 0.010, 0.013, 200),
+            ],
+        );
+        quotes.insert(option(65000, Call), vec![quote("deribit", 0.018, 0.020, 1)]);
+        quotes.insert(option(65000, Pull), vec![quote("deribit", 0.100, 0.105, 1)]);
+
+        let expiry = NaiveDate::from_ymd_opt(2024, 4, 27).unwrap();
+        let forwards = ForwardCurve::new(58000.0).with_forward(expiry, 60000.0);
+        let violations = ParityScanner::default().scan_quotes(&quotes, &forwards);
+
+        assert_eq!(violations.len(), 1);
+        let conversion = &violations[0];
+        assert_eq!(conversion.strategy, ParityStrategy::Conversion);
+        assert_eq!(conversion.strikes, vec![55000]);
+        assert_eq!(conversion.forward, 60000.0);
+        // buy the call on deribit, sell the put on okex
+        let legs: Vec<_> = conversion
+            .legs
+            .iter()
+            .map(|leg| (leg.exchange.as_str(), leg.request.clone(), leg.price))
+            .collect();
+        assert_eq!(
+            legs,
+            vec![
+                ("deribit", TradeRequest::Bid, 0.090),
+                ("okex", TradeRequest::Ask, 0.010)
+            ]
+        );
+        let fair = 1.0 - 55000.0 / 60000.0;
+        assert!((conversion.edge - (fair - 0.0806)).abs() < 1e-5);
+        assert!((conversion.size - 1.0).abs() < 1e-4);
+
+        // a wider box at a cheaper forward makes the box worth more than it costs
+        let forwards = ForwardCurve::new(50000.0);
+        let violations = ParityScanner::default().scan_quotes(&quotes, &forwards);
+        assert!(violations
+            .iter()
+            .any(|v| v.strategy == ParityStrategy::LongBox && v.strikes == vec![55000, 65000]));
+    }
+}
+
+#[cfg(test)]
+mod price_feeds {
+    use crate::{
+        exchanges::{ExchangeType, PriceKind, PriceUpdate},
+        trading::{Instrument, OrderBook},
+    };
+
+    #[test]
+    fn latest_index_and_mark_prices_are_kept_with_the_book() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-USD-240510-66000-C", ExchangeType::Okex)?;
+        let mut order_book = OrderBook::new("okex");
+        let update = |kind, price, timestamp| PriceUpdate {
+            exchange: "okex".to_owned(),
+            kind,
+            name: "BTC-USD".to_owned(),
+            instrument: None,
+            price,
+            timestamp,
+        };
+        assert!(order_book.index_price().is_none());
+        order_book.apply_price_update(update(PriceKind::Index, 62000.0, 1));
+        order_book.apply_price_update(update(PriceKind::Index, 62100.0, 2));
+        order_book.apply_price_update(PriceUpdate {
+            instrument: Some(instrument.clone()),
+            ..update(PriceKind::Mark, 0.0126, 2)
+        });
+
+        assert_eq!(order_book.index_price(), Some(62100.0));
+        assert_eq!(order_book.mark_price(&instrument), Some(0.0126));
+        // deribit naming of the same option finds the mark too
+        let deribit_instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        assert_eq!(order_book.mark_price(&deribit_instrument), Some(0.0126));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod trade_tape {
+    use crate::{
+        exchanges::{ExchangeType, Trade, TradeSide},
+        trading::{Instrument, OrderBook, TradeTape},
+    };
+
+    #[test]
+    fn tape_is_bounded_and_deduplicated() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let trade = |id: u32, price: f32| Trade {
+            exchange: "deribit".to_owned(),
+            instrument: instrument.clone(),
+            trade_id: id.to_string(),
+            price,
+            size: 1.0,
+            side: TradeSide::Buy,
+            timestamp: id as u128,
+        };
+
+        let mut tape = TradeTape::new(3);
+        for id in 0..5 {
+            tape.push(trade(id, 0.01 * (id + 1) as f32));
+        }
+        tape.push(trade(4, 1.0)); // replayed print
+        assert_eq!(tape.len(), 3);
+        let ids: Vec<_> = tape.recent(3).map(|t| t.trade_id.as_str()).collect();
+        assert_eq!(ids, vec!["4", "3", "2"]);
+        assert!((tape.vwap().unwrap() - 0.04).abs() < 1e-6);
+        let mut unbounded = TradeTape::new(0);
+        unbounded.push(trade(0, 0.01));
+        unbounded.push(trade(1, 0.02));
+        assert_eq!(unbounded.len(), 1);
+
+        let mut order_book = OrderBook::new("deribit");
+        order_book.record_trade(trade(7, 0.02));
+        let okex_instrument =
+            Instrument::from_exchange_string("BTC-USD-240510-66000-C", ExchangeType::Okex)?;
+        let tape = order_book.trade_tape(&okex_instrument).unwrap();
+        assert_eq!(tape.last().unwrap().trade_id, "7");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod candles {
+    use crate::{
+        exchanges::{ExchangeType, Trade, TradeSide},
+        trading::{
+            CandleInterval, CandleSeries, CandleSource, Instrument, Order, OrderBook, TradeRequest,
+        },
+    };
+
+    #[test]
+    fn candles_roll_over_on_interval_boundaries() {
+        let mut series = CandleSeries::new(CandleInterval::Minute, 2);
+        series.update(10.0, 1.0, 60_000);
+        series.update(12.0, 2.0, 90_000);
+        series.update(9.0, 1.0, 110_000);
+        series.update(15.0, 1.0, 100_000); // late print, no new close
+        let candle = series.last().unwrap();
+        assert_eq!(
+            (candle.start, candle.open, candle.close),
+            (60_000, 10.0, 9.0)
+        );
+        assert_eq!((candle.high, candle.low, candle.volume), (15.0, 9.0, 5.0));
+
+        series.update(11.0, 1.0, 125_000);
+        series.update(11.5, 1.0, 185_000);
+        assert_eq!(series.candles.len(), 2);
+        let mut empty = CandleSeries::new(CandleInterval::Minute, 0);
+        empty.update(10.0, 1.0, 60_000);
+        empty.update(11.0, 1.0, 120_000);
+        assert_eq!(empty.candles.len(), 1);
+        assert_eq!(series.candles[0].start, 120_000);
+        assert!(series
+            .to_csv()
+            .lines()
+            .nth(2)
+            .unwrap()
+            .starts_with("180000,11.5"));
+    }
+
+    #[test]
+    fn books_build_candles_from_trades_and_mids() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let mut order_book = OrderBook::new("deribit");
+        order_book.add_asset(instrument.clone());
+        order_book.add_order(Order::new(0.01, 5, TradeRequest::Bid), &instrument);
+        order_book.record_midprice(&instrument, 1_000); // one sided, no mid yet
+        order_book.add_order(Order::new(0.02, 5, TradeRequest::Ask), &instrument);
+        order_book.record_midprice(&instrument, 1_500);
+
+        let trade = Trade {
+            exchange: "deribit".to_owned(),
+            instrument: instrument.clone(),
+            trade_id: "1".to_owned(),
+            price: 0.015,
+            size: 2.0,
+            side: TradeSide::Buy,
+            timestamp: 1_200,
+        };
+        order_book.record_trade(trade.clone());
+        order_book.record_trade(trade); // duplicates don't add volume
+
+        let mids = order_book
+            .candles_for(&instrument, CandleSource::Mid, CandleInterval::Second)
+            .unwrap();
+        assert_eq!(mids.candles.len(), 1);
+        assert_eq!(mids.last().unwrap().count, 1);
+        let trades =
+            order_book
+                .candles
+                .recent(&instrument, CandleSource::Trades, CandleInterval::Hour, 10);
+        assert_eq!((trades[0].close, trades[0].volume), (0.015, 2.0));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod metrics {
+    use crate::{
+        exchanges::ExchangeType,
+        trading::{Instrument, Order, OrderBook, TradeRequest},
+    };
+
+    #[test]
+    fn metrics_follow_every_book_change() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let mut order_book = OrderBook::new("deribit");
+        order_book.add_asset(instrument.clone());
+        order_book.add_order(Order::new(0.0100, 30, TradeRequest::Bid), &instrument);
+        assert!(order_book.metrics(&instrument).is_none());
+        order_book.add_order(Order::new(0.0090, 10, TradeRequest::Bid), &instrument);
+        order_book.add_order(Order::new(0.0070, 50, TradeRequest::Bid), &instrument); // 6 ticks away
+        let ask = Order::new(0.0110, 10, TradeRequest::Ask);
+        let ask_id = ask.id;
+        order_book.add_order(ask, &instrument);
+        order_book.add_order(Order::new(0.0120, 20, TradeRequest::Ask), &instrument);
+
+        let metrics = order_book.metrics(&instrument).unwrap();
+        assert!((metrics.imbalance - 0.5).abs() < 1e-6);
+        // leans towards the ask when the bid is heavier
+        assert!((metrics.microprice - 0.01075).abs() < 1e-6);
+        assert_eq!((metrics.bid_depth, metrics.ask_depth), (40, 30));
+        assert!(metrics.pressure > 0.0);
+        let weighted =
+            (0.01 * 30.0 + 0.009 * 10.0 + 0.007 * 50.0 + 0.011 * 10.0 + 0.012 * 20.0) / 120.0;
+        assert!((metrics.weighted_mid - weighted).abs() < 1e-6);
+
+        order_book.cancel_order(&instrument, ask_id);
+        let metrics = order_book.metrics(&instrument).unwrap();
+        assert_eq!(metrics.best_ask, 0.0120);
+        assert!((metrics.imbalance - 0.2).abs() < 1e-6);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod impact {
+    use crate::{
+        exchanges::ExchangeType,
+        trading::{ConsolidatedBook, Instrument, Order, OrderBook, TradeRequest},
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn estimates_do_not_touch_the_books() -> anyhow::Result<()> {
+        let deribit_instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let okex_instrument =
+            Instrument::from_exchange_string("BTC-USD-240510-66000-C", ExchangeType::Okex)?;
+        let mut deribit = OrderBook::new("deribit");
+        deribit.add_asset(deribit_instrument.clone());
+        deribit.add_order(Order::new(0.010, 5, TradeRequest::Bid), &deribit_instrument);
+        deribit.add_order(Order::new(0.012, 5, TradeRequest::Ask), &deribit_instrument);
+        deribit.add_order(Order::new(0.014, 5, TradeRequest::Ask), &deribit_instrument);
+        let mut okex = OrderBook::new("okex");
+        okex.add_asset(okex_instrument.clone());
+        // 10 BTC in okex contracts
+        okex.add_order(Order::new(0.013, 1000, TradeRequest::Ask), &okex_instrument);
+
+        let single = deribit
+            .estimate_impact(&okex_instrument, TradeRequest::Bid, 8.0)
+            .unwrap();
+        assert_eq!((single.filled, single.worst_price), (8.0, Some(0.014)));
+        assert!((single.vwap.unwrap() - 0.01275).abs() < 1e-6);
+        assert!((single.slippage.unwrap() - 0.00175).abs() < 1e-6);
+        assert_eq!(single.levels[0].exchange, "deribit");
+
+        let consolidated = ConsolidatedBook::new(vec![
+            Arc::new(Mutex::new(deribit)),
+            Arc::new(Mutex::new(okex)),
+        ]);
+        let swept = consolidated
+            .estimate_impact(&deribit_instrument, TradeRequest::Bid, 30.0)
+            .await;
+        assert!((swept.filled - 20.0).abs() < 1e-4);
+        assert!((swept.fill_ratio() - 20.0 / 30.0).abs() < 1e-6);
+        let venues: Vec<_> = swept
+            .levels
+            .iter()
+            .map(|l| (l.exchange.as_str(), l.quantity))
+            .collect();
+        assert_eq!(venues, vec![("deribit", 5), ("okex", 1000), ("deribit", 5)]);
+        // a part of an okex level is taken in whole okex contracts
+        let partial = consolidated
+            .estimate_impact(&deribit_instrument, TradeRequest::Bid, 5.3)
+            .await;
+        assert_eq!(partial.levels[1].quantity, 30);
+        assert!(partial.is_complete());
+
+        let sell = consolidated
+            .estimate_impact(&deribit_instrument, TradeRequest::Ask, 2.0)
+            .await;
+        assert_eq!(sell.vwap, Some(0.010));
+        assert!(sell.is_complete());
+        // nothing was consumed
+        let book = consolidated.book("deribit").await.unwrap();
+        let book = book.lock().await;
+        assert_eq!(book.table_for(&deribit_instrument).unwrap().asks.len(), 2);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod clock {
+    use crate::{
+        exchanges::ExchangeType,
+        trading::{Instrument, OrderBook, TradeRequest},
+        utils::SimulatedClock,
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn orders_and_fills_follow_the_simulated_clock() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let clock = Arc::new(SimulatedClock::new(1_000));
+        let mut ours = OrderBook::with_clock("deribit", clock.clone());
+        let mut theirs = OrderBook::with_clock("okex", clock.clone());
+        ours.add_asset(instrument.clone());
+        theirs.add_asset(instrument.clone());
+
+        let bid = ours.new_order(0.02, 5, TradeRequest::Bid);
+        let bid_id = bid.id;
+        assert_eq!(bid.created_at, 1_000);
+        ours.add_order(bid, &instrument);
+        clock.advance(250);
+        let ask = theirs.new_order(0.01, 500, TradeRequest::Ask);
+        assert_eq!(ask.created_at, 1_250);
+        theirs.add_order(ask, &instrument);
+
+        clock.set(2_000);
+        let theirs = Arc::new(Mutex::new(theirs));
+        ours.match_orders(&instrument, theirs.clone()).await?;
+        let filled = ours
+            .table_for(&instrument)
+            .unwrap()
+            .history
+            .iter()
+            .find(|order| order.id == bid_id)
+            .cloned()
+            .unwrap();
+        assert_eq!(filled.filled_with[0].matched_at, 2_000);
+        let theirs = theirs.lock().await;
+        let counterparty = &theirs.table_for(&instrument).unwrap().history[0];
+        assert_eq!(counterparty.filled_with[0].matched_at, 2_000);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod paper_account {
+    use crate::{
+        exchanges::{ExchangeType, PriceKind, PriceUpdate},
+        trading::{Instrument, MarkSource, Order, OrderBook, PaperAccount, Position, TradeRequest},
+        utils::SimulatedClock,
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn fills_update_positions_balances_and_pnl() -> anyhow::Result<()> {
+        let deribit_instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let okex_instrument =
+            Instrument::from_exchange_string("BTC-USD-240510-66000-C", ExchangeType::Okex)?;
+        let clock = Arc::new(SimulatedClock::new(1_000));
+        let mut deribit = OrderBook::with_clock("deribit", clock.clone());
+        deribit.add_asset(deribit_instrument.clone());
+        let ask = deribit.new_order(0.015, 5, TradeRequest::Ask);
+        deribit.add_order(ask, &deribit_instrument);
+        let bid = deribit.new_order(0.013, 5, TradeRequest::Bid);
+        deribit.add_order(bid, &deribit_instrument);
+        let deribit = Arc::new(Mutex::new(deribit));
+
+        let mut account = PaperAccount::new("paper", clock.clone());
+        account.deposit("BTC", 1.0);
+        let buy = Order::new_at(0.015, 2, TradeRequest::Bid, clock.as_ref());
+        let buy_id = account.place("deribit", &deribit_instrument, buy, &*deribit.lock().await)?;
+        let fills = account.match_against("deribit", deribit.clone()).await?;
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].order.id, buy_id);
+        assert!((fills[0].fee - 0.0006).abs() < 1e-9);
+        assert!(account.open_orders("deribit").is_empty());
+
+        // positions are kept per venue and canonical instrument, whatever the venue calls it
+        let position = account.position("deribit", &okex_instrument);
+        assert!(account.position("okex", &okex_instrument).is_flat());
+        assert_eq!(account.net_quantity(&okex_instrument), 2.0);
+        assert_eq!(position.quantity, 2.0);
+        assert_eq!(position.avg_price, 0.015);
+        assert!((account.balance("BTC") - (1.0 - 0.03 - 0.0006)).abs() < 1e-6);
+
+        {
+            let mut venue = deribit.lock().await;
+            // 0.015 x3 left against 0.013
+            let unrealized =
+                account.unrealized_pnl(&venue, &deribit_instrument, MarkSource::Midprice);
+            assert!((unrealized.unwrap() + 0.002).abs() < 1e-6);
+            venue.apply_price_update(PriceUpdate {
+                exchange: "deribit".to_owned(),
+                kind: PriceKind::Mark,
+                name: "BTC-10MAY24-66000-C".to_owned(),
+                instrument: Some(deribit_instrument.clone()),
+                price: 0.016,
+                timestamp: 1_000,
+            });
+            let unrealized =
+                account.unrealized_pnl(&venue, &deribit_instrument, MarkSource::MarkPrice);
+            assert!((unrealized.unwrap() - 0.002).abs() < 1e-6);
+        }
+
+        // closing half realizes the loss against the bid
+        let sell = Order::new_at(0.013, 1, TradeRequest::Ask, clock.as_ref());
+        account.place("deribit", &deribit_instrument, sell, &*deribit.lock().await)?;
+        // resting orders that don't cross stay open
+        let resting = Order::new_at(0.001, 1, TradeRequest::Bid, clock.as_ref());
+        let resting_id = account.place(
+            "deribit",
+            &deribit_instrument,
+            resting,
+            &*deribit.lock().await,
+        )?;
+        let fills = account.match_against("deribit", deribit.clone()).await?;
+        assert_eq!(fills.len(), 1);
+        assert!((account.realized_pnl() + 0.002).abs() < 1e-6);
+        let position = account.position("deribit", &deribit_instrument);
+        assert_eq!(position.quantity, 1.0);
+        assert!((position.fees - 0.0009).abs() < 1e-9);
+        assert!((account.balance("BTC") - (1.0 - 0.03 - 0.0006 + 0.013 - 0.0003)).abs() < 1e-6);
+
+        assert_eq!(account.open_orders("deribit").len(), 1);
+        let cancelled = account.cancel("deribit", &deribit_instrument, resting_id);
+        assert!(cancelled.is_some());
+        assert!(account.open_orders("deribit").is_empty());
+        assert_eq!(account.orders("deribit").len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn positions_closed_in_small_lots_are_flat() {
+        let mut position = Position::default();
+        for _ in 0..10 {
+            position.apply_fill(0.01, 0.015, 0.0);
+        }
+        position.apply_fill(-0.1, 0.016, 0.0);
+        assert!(position.is_flat());
+        assert_eq!(position.quantity, 0.0);
+        assert_eq!(position.avg_price, 0.0);
+
+        // what rounding leaves doesn't count as a position of its own
+        position.apply_fill(0.3, 0.015, 0.0);
+        position.apply_fill(-0.1, 0.016, 0.0);
+        position.apply_fill(-0.2, 0.016, 0.0);
+        assert_eq!(
+            position,
+            Position {
+                realized_pnl: position.realized_pnl,
+                ..Default::default()
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod risk {
+    use crate::{
+        analytics::Greeks,
+        exchanges::{DeribitResponse, ExchangeType, OptionSummary},
+        trading::{
+            Instrument, Order, OrderBook, PaperAccount, RiskLimits, RiskRejection, RiskState,
+            TradeRequest,
+        },
+        utils::{apply_frame, SimulatedClock},
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[test]
+    fn limits_reject_with_a_reason() {
+        let limits = RiskLimits {
+            max_order_size: Some(10.0),
+            max_notional: Some(0.1),
+            max_position: Some(5.0),
+            ..Default::default()
+        };
+        let state = RiskState {
+            position: 2.0,
+            open_bids: 1.0,
+            ..Default::default()
+        };
+        let bid = |price: f32, quantity: i32| Order::new(price, quantity, TradeRequest::Bid);
+        assert_eq!(limits.check(&bid(0.01, 2), 2.0, &state), Ok(()));
+        assert_eq!(
+            limits.check(&bid(0.01, 11), 11.0, &state),
+            Err(RiskRejection::OrderSize {
+                quantity: 11.0,
+                limit: 10.0
+            })
+        );
+        assert!(matches!(
+            limits.check(&bid(0.05, 3), 3.0, &state),
+            Err(RiskRejection::Notional { .. })
+        ));
+        // the open bid counts towards the position
+        assert_eq!(
+            limits.check(&bid(0.01, 3), 3.0, &state),
+            Err(RiskRejection::Position {
+                projected: 6.0,
+                limit: 5.0
+            })
+        );
+        let ask = Order::new(0.01, 7, TradeRequest::Ask);
+        assert_eq!(limits.check(&ask, 7.0, &state), Ok(()));
+        assert_eq!(
+            RiskRejection::OpenOrders { open: 3, limit: 3 }.to_string(),
+            "3 open orders already, the limit is 3"
+        );
+    }
+
+    #[test]
+    fn rejected_orders_never_reach_the_book() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let clock = Arc::new(SimulatedClock::new(1_000));
+        let mut venue = OrderBook::with_clock("deribit", clock.clone());
+        venue.add_asset(instrument.clone());
+        let ask = venue.new_order(0.015, 5, TradeRequest::Ask);
+        venue.add_order(ask, &instrument);
+        let bid = venue.new_order(0.013, 5, TradeRequest::Bid);
+        venue.add_order(bid, &instrument);
+
+        let mut account = PaperAccount::new("paper", clock.clone());
+        account.risk = RiskLimits {
+            price_band: Some(0.2),
+            max_open_orders: Some(1),
+            ..Default::default()
+        };
+        let bid = |price: f32, quantity: i32| Order::new(price, quantity, TradeRequest::Bid);
+        let rejected = account.place("deribit", &instrument, bid(0.02, 1), &venue);
+        assert!(matches!(rejected, Err(RiskRejection::PriceBand { .. })));
+        assert!(account.orders("deribit").is_empty());
+
+        account.place("deribit", &instrument, bid(0.013, 1), &venue)?;
+        let rejected = account.place("deribit", &instrument, bid(0.013, 1), &venue);
+        assert_eq!(
+            rejected,
+            Err(RiskRejection::OpenOrders { open: 1, limit: 1 })
+        );
+        assert_eq!(account.orders("deribit").len(), 1);
+        assert_eq!(account.rejections.len(), 2);
+
+        account.risk = RiskLimits {
+            max_delta: Some(0.5),
+            ..Default::default()
+        };
+        let rejected = account.place("deribit", &instrument, bid(0.013, 1), &venue);
+        assert_eq!(rejected, Err(RiskRejection::NoGreeks));
+        venue.apply_option_summary(OptionSummary {
+            exchange: "deribit".to_owned(),
+            instrument: instrument.clone(),
+            mark_iv: 0.5,
+            bid_iv: None,
+            ask_iv: None,
+            mark_price: Some(0.014),
+            underlying_price: 63_000.0,
+            greeks: Greeks {
+                delta: 0.4,
+                ..Default::default()
+            },
+            timestamp: 1_000,
+        });
+        let rejected = account.place("deribit", &instrument, bid(0.013, 2), &venue);
+        assert!(matches!(
+            rejected,
+            Err(RiskRejection::Greek { greek: "delta", .. })
+        ));
+        account.place("deribit", &instrument, bid(0.013, 1), &venue)?;
+        assert_eq!(account.orders("deribit").len(), 2);
+
+        // a position the venue can't price hides the account's greeks
+        let put = Instrument::from_exchange_string("BTC-10MAY24-60000-P", ExchangeType::Delibris)?;
+        account
+            .positions
+            .entry(("deribit".to_owned(), put))
+            .or_default()
+            .apply_fill(1.0, 0.01, 0.0);
+        let rejected = account.place("deribit", &instrument, bid(0.013, 1), &venue);
+        assert_eq!(rejected, Err(RiskRejection::NoGreeks));
+        Ok(())
+    }
+
+    #[test]
+    fn books_check_their_own_limits() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-USD-240510-66000-C", ExchangeType::Okex)?;
+        let mut okex = OrderBook::new("okex");
+        okex.add_asset(instrument.clone());
+        okex.risk = RiskLimits {
+            max_order_size: Some(0.5),
+            max_open_orders: Some(1),
+            ..Default::default()
+        };
+        // 100 contracts of 0.01 BTC
+        let rejected = okex.add_order(Order::new(0.01, 100, TradeRequest::Bid), &instrument);
+        assert_eq!(
+            rejected,
+            Err(RiskRejection::OrderSize {
+                quantity: 1.0,
+                limit: 0.5
+            })
+        );
+        okex.add_order(Order::new(0.01, 50, TradeRequest::Bid), &instrument)?;
+        let rejected = okex.add_order(Order::new(0.01, 10, TradeRequest::Bid), &instrument);
+        assert_eq!(
+            rejected,
+            Err(RiskRejection::OpenOrders { open: 1, limit: 1 })
+        );
+        assert_eq!(okex.table_for(&instrument).unwrap().bids.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn venue_depth_skips_the_limits() -> anyhow::Result<()> {
+        let instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let mut deribit = OrderBook::new("deribit");
+        deribit.risk = RiskLimits {
+            max_order_size: Some(1.0),
+            ..Default::default()
+        };
+        let deribit = Arc::new(Mutex::new(deribit));
+        // levels of 5 contracts are the venue's, not ours
+        apply_frame::<DeribitResponse>(
+            r#"{"params":{"channel":"book.BTC-10MAY24-66000-C.none.20.100ms","data":{"instrument_name":"BTC-10MAY24-66000-C","bids":[[0.0130,5.0]],"asks":[[0.0150,5.0]]}}}"#,
+            1_000,
+            deribit.clone(),
+        )
+        .await?;
+        let deribit = deribit.lock().await;
+        let table = deribit.table_for(&instrument).unwrap();
+        assert_eq!((table.bids.len(), table.asks.len()), (1, 1));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod kill_switch {
+    use crate::{
+        exchanges::ExchangeType,
+        trading::{
+            AuditAction, ControlCommand, Instrument, KillReason, KillScope, KillSwitch,
+            KillSwitchConfig,
+        },
+        utils::SimulatedClock,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn operator_commands_kill_and_resume() -> anyhow::Result<()> {
+        let deribit_instrument =
+            Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+        let okex_instrument =
+            Instrument::from_exchange_string("BTC-USD-240510-66000-C", ExchangeType::Okex)?;
+        let put = Instrument::from_exchange_string("BTC-10MAY24-60000-P", ExchangeType::Delibris)?;
+        let clock = Arc::new(SimulatedClock::new(1_000));
+        let mut switch = KillSwitch::new(KillSwitchConfig::default(), clock);
+
+        let kill = ControlCommand::from_given_str("kill BTC-USD-240510-66000-C").unwrap();
+        assert_eq!(kill, ControlCommand::Kill(Some(okex_instrument.clone())));
+        assert!(switch.apply_command(kill).starts_with("killed"));
+        // instruments are blocked whatever the venue calls them
+        assert!(switch.blocked(&deribit_instrument).is_some());
+        assert!(switch.blocked(&put).is_none());
+        assert!(switch
+            .apply_command(ControlCommand::Status)
+            .contains("manual"));
+
+        switch.apply_command(ControlCommand::from_given_str("kill").unwrap());
+        assert!(switch.blocked(&put).is_some());
+        switch.apply_command(ControlCommand::from_given_str("resume").unwrap());
+        assert!(switch.blocked(&put).is_none());
+        assert!(switch.blocked(&okex_instrument).is_some());
+        switch.apply_command(ControlCommand::from_given_str("resume BTC-10MAY24-66000-C").unwrap());
+        assert_eq!(switch.apply_command(ControlCommand::Status), "trading");
+
+        assert_eq!(ControlCommand::from_given_str("kill nonsense"), None);
+        assert_eq!(ControlCommand::from_given_str("flatten"), None);
+        let actions: Vec<AuditAction> = switch
+            .audit
+            .iter()
+            .map(|event| event.action.clone())
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                AuditAction::Triggered(KillReason::Manual("operator".to_owned())),
+                AuditAction::Triggered(KillReason::Manual("operator".to_owned())),
+                AuditAction::Reset,
+                AuditAction::Reset,
+            ]
+        );
+        assert_eq!(switch.audit[0].timestamp, 1_000);
+        Ok(())
+    }
+
+    #[test]
+    fn automatic_triggers() -> anyhow::Result<()> {
+        let clock = Arc::new(SimulatedClock::new(1_000));
+        let config = KillSwitchConfig {
+            max_loss: Some(0.5),
+            max_data_age_ms: Some(5_000),
+            max_orders: Some(3),
+            order_window_ms: 1_000,
+        };
+        let mut switch = KillSwitch::new(config, clock.clone());
+
+        // three orders a second are fine, as long as they're spread out
+        for _ in 0..6 {
+            switch.record_order();
+            clock.advance(400);
+        }
+        assert!(switch.tripped().is_empty());
+        for _ in 0..4 {
+            switch.record_order();
+        }
+        assert_eq!(
+            switch.tripped(),
+            vec![(
+                KillScope::Global,
+                KillReason::OrderRate {
+                    orders: 4,
+                    window_ms: 1_000
+                }
+            )]
+        );
+        switch.reset(KillScope::Global);
+
+        switch.observe_data("deribit");
+        clock.advance(5_000);
+        switch.check_stale();
+        assert!(switch.tripped().is_empty());
+        clock.advance(1);
+        switch.check_stale();
+        assert!(matches!(
+            switch.tripped()[0].1,
+            KillReason::StaleData { age_ms: 5_001, .. }
+        ));
+        switch.reset(KillScope::Global);
+
+        // a venue that never sends anything goes stale as well
+        switch.observe_data("deribit");
+        switch.watch("okex");
+        switch.watch("okex");
+        clock.advance(5_001);
+        switch.observe_data("deribit");
+        switch.check_stale();
+        assert!(matches!(
+            &switch.tripped()[0].1,
+            KillReason::StaleData { exchange, age_ms: 5_001, .. } if exchange == "okex"
+        ));
+        switch.reset(KillScope::Global);
+
+        switch.check_loss(-0.4);
+        assert!(switch.tripped().is_empty());
+        switch.check_loss(-0.6);
+        assert!(matches!(switch.tripped()[0].1, KillReason::Loss { .. }));
+        Ok(())
+    }
+}
@@ -2,7 +2,9 @@ use crate::exchanges::{
     self, string_to_instrument_deribit, string_to_instrument_okex, ExchangeType,
 };
 
-use crate::utils::{add_each, get_timestamp_ms, round};
+use super::{BookMetrics, MetricsConfig};
+use crate::utils::{add_each, next_order_id, round, Clock, SystemClock};
+use anyhow::ensure;
 use ordered_float::OrderedFloat;
 use std::default;
 use std::fmt::format;
@@ -56,19 +58,43 @@ impl CurrentHoldingPerPrice {
     }
 }
 #[derive(PartialEq, PartialOrd, Debug, Default, Clone)]
+/// Lifecycle of an order: New -> PartiallyFilled -> Filled/Cancelled
 pub enum OrderStatus {
-    Completed,
-    Partial,
     #[default]
-    Pending,
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
 }
 impl OrderStatus {
     pub fn is_completed(&self) -> bool {
-        *self == OrderStatus::Completed
+        *self == OrderStatus::Filled
     }
 
     pub fn is_partial(&self) -> bool {
-        *self == OrderStatus::Partial
+        *self == OrderStatus::PartiallyFilled
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self == OrderStatus::Cancelled
+    }
+
+    /// orders that can still be matched or cancelled
+    pub fn is_open(&self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
+    }
+
+    pub fn can_transition_to(&self, next: &OrderStatus) -> bool {
+        use OrderStatus::*;
+        matches!(
+            (self, next),
+            (New, PartiallyFilled)
+                | (New, Filled)
+                | (New, Cancelled)
+                | (PartiallyFilled, PartiallyFilled)
+                | (PartiallyFilled, Filled)
+                | (PartiallyFilled, Cancelled)
+        )
     }
 }
 #[derive(PartialEq, PartialOrd, Debug, Clone)]
@@ -76,6 +102,7 @@ pub struct MatchedOrders {
     pub price: f32,
     pub quantity: i32,
     pub exchange: String,
+    pub matched_at: u128, // timestamp in ms, from the clock of the matching book
 }
 impl MatchedOrders {
     pub fn new(price: f32, quantity: i32, exchange: &str) -> Self {
@@ -83,46 +110,78 @@ impl MatchedOrders {
             price,
             quantity,
             exchange: exchange.to_owned(),
+            matched_at: 0,
         }
     }
+
+    pub fn at(mut self, matched_at: u128) -> Self {
+        self.matched_at = matched_at;
+        self
+    }
 }
 #[derive(PartialEq, PartialOrd, Debug, Clone)]
 pub struct Order {
-    pub id: u128, // timestamp in ms
-    pub is_arbitrage: bool,
+    pub id: u128,                        // unique and monotonic, see `Clock::next_order_id`
+    pub client_order_id: Option<String>, // optional id supplied by the user placing the order
+    pub tag: Option<String>,
+    pub created_at: u128, // timestamp in ms
     pub status: OrderStatus,
     pub price: f32,
     pub request: TradeRequest,
     pub quantity: i32,
     pub remaining_qty: i32, // the qty required to completed order after a partial trade,
+    pub filled_qty: i32,    // cumulative quantity filled so far
+    pub avg_fill_price: f32,
     pub filled_with: VecDeque<MatchedOrders>,
 }
-// custom because we want to add a custom  id (timestamp)
+// custom because we want to add a custom  id
 impl Default for Order {
     fn default() -> Self {
         Self {
-            id: get_timestamp_ms(),
-            is_arbitrage: false,
+            id: next_order_id(),
+            client_order_id: None,
+            tag: None,
+            created_at: SystemClock.now_ms(),
             price: 0.0,
             quantity: 0,
             status: OrderStatus::default(),
             request: TradeRequest::default(),
             remaining_qty: 0,
+            filled_qty: 0,
+            avg_fill_price: 0.0,
             filled_with: VecDeque::new(),
         }
     }
 }
 
 impl Order {
+    /// an order created now by the wall clock, see `new_at` for other clocks
     pub fn new(price: f32, quantity: i32, request: TradeRequest) -> Self {
+        Self::new_at(price, quantity, request, &SystemClock)
+    }
+
+    pub fn new_at(price: f32, quantity: i32, request: TradeRequest, clock: &dyn Clock) -> Self {
         Self {
             price,
             quantity,
             request,
-            id: get_timestamp_ms(),
+            remaining_qty: quantity,
+            created_at: clock.now_ms(),
+            id: clock.next_order_id(),
             ..Default::default()
         }
     }
+
+    pub fn with_client_order_id<T: AsRef<str>>(mut self, client_order_id: T) -> Self {
+        self.client_order_id = Some(client_order_id.as_ref().to_owned());
+        self
+    }
+
+    pub fn with_tag<T: AsRef<str>>(mut self, tag: T) -> Self {
+        self.tag = Some(tag.as_ref().to_owned());
+        self
+    }
+
     pub fn is_completed(&self) -> bool {
         self.status.is_completed()
     }
@@ -130,6 +189,53 @@ impl Order {
     pub fn is_partial_completed(&self) -> bool {
         self.status.is_partial()
     }
+
+    pub fn is_open(&self) -> bool {
+        self.status.is_open()
+    }
+
+    fn transition(&mut self, next: OrderStatus) -> anyhow::Result<()> {
+        ensure!(
+            self.status.can_transition_to(&next),
+            "order {} cannot move from {:?} to {:?}",
+            self.id,
+            self.status,
+            next
+        );
+        self.status = next;
+        Ok(())
+    }
+
+    /// records a fill, updating the cumulative filled qty, average fill price and status
+    pub fn record_fill(&mut self, fill: MatchedOrders) -> anyhow::Result<()> {
+        ensure!(fill.quantity > 0, "fills must have a positive quantity");
+        ensure!(
+            fill.quantity <= self.remaining_qty,
+            "fill of {} exceeds the remaining {} of order {}",
+            fill.quantity,
+            self.remaining_qty,
+            self.id
+        );
+        let filled_qty = self.filled_qty + fill.quantity;
+        let next = if filled_qty == self.quantity {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        self.transition(next)?;
+
+        let filled_amount = self.avg_fill_price * self.filled_qty as f32;
+        self.avg_fill_price =
+            (filled_amount + fill.price * fill.quantity as f32) / filled_qty as f32;
+        self.filled_qty = filled_qty;
+        self.remaining_qty = self.quantity - filled_qty;
+        self.filled_with.push_back(fill);
+        Ok(())
+    }
+
+    pub fn cancel(&mut self) -> anyhow::Result<()> {
+        self.transition(OrderStatus::Cancelled)
+    }
 }
 pub type PriceRow = BTreeMap<OrderedFloat<f32>, CurrentHoldingPerPrice>;
 
@@ -142,6 +248,8 @@ pub struct PriceColumns {
     pub orders: Arc<Mutex<VecDeque<Order>>>,
     pub history: VecDeque<Order>, // all completed trades
     pub exchange_name: String,
+    pub metrics: Option<BookMetrics>, // refreshed with the spread and midprice
+    pub metrics_config: MetricsConfig,
 }
 
 impl PriceColumns {
@@ -155,6 +263,89 @@ impl PriceColumns {
             self.spread = round(diff.into_inner(), 5);
             self.midprice = round(mid_point.into_inner(), 5);
         }
+        self.metrics = BookMetrics::compute(self, &self.metrics_config);
+    }
+
+    /// reduces the resting quantity of an order (by id) at its price level after it has been (partially) filled
+    pub fn reduce_at_level(
+        &mut self,
+        order_id: u128,
+        price: f32,
+        request: &TradeRequest,
+        qty: i32,
+    ) {
+        let key: OrderedFloat<f32> = price.into();
+        let row = if request.is_ask() {
+            &mut self.asks
+        } else {
+            &mut self.bids
+        };
+        if let Some(holding) = row.get_mut(&key) {
+            if let Some(order) = holding.orders.iter_mut().find(|order| order.id == order_id) {
+                order.qty -= qty.min(order.qty);
+            }
+            holding.orders.retain(|order| order.qty > 0);
+            holding.update_qty_and_amount();
+            if holding.orders.is_empty() {
+                row.remove(&key);
+            }
+        }
+    }
+
+    /// records fills of (order id, price, qty) against the open orders of this column.
+    /// Every valid fill is recorded, the first one that isn't is returned as the error
+    pub fn record_fills(
+        &mut self,
+        fills: &[(u128, f32, i32)],
+        counterparty: &str,
+        matched_at: u128,
+    ) -> anyhow::Result<()> {
+        let mut orders = self.orders.lock().unwrap();
+        let mut failure = None;
+        for (id, price, qty) in fills {
+            if let Some(order) = orders.iter_mut().find(|order| order.id == *id) {
+                let fill = MatchedOrders::new(*price, *qty, counterparty).at(matched_at);
+                if let Err(err) = order.record_fill(fill) {
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// moves filled and cancelled orders into the history
+    pub fn archive_closed_orders(&mut self) {
+        let closed: Vec<Order> = {
+            let mut orders = self.orders.lock().unwrap();
+            let closed = orders.iter().filter(|o| !o.is_open()).cloned().collect();
+            orders.retain(|o| o.is_open());
+            closed
+        };
+        self.history.extend(closed);
+    }
+
+    /// drops empty price levels and refreshes the spread and midprice
+    pub fn prune_empty_levels(&mut self) {
+        self.bids.retain(|_, holding| holding.total_quantity != 0);
+        self.asks.retain(|_, holding| holding.total_quantity != 0);
+        self.update_spread_and_mid_price();
+    }
+
+    /// removes the resting quantity of an order (by id) from its price level, dropping the level once empty
+    pub fn remove_from_level(&mut self, order_id: u128, price: f32, request: &TradeRequest) {
+        let key: OrderedFloat<f32> = price.into();
+        let row = if request.is_ask() {
+            &mut self.asks
+        } else {
+            &mut self.bids
+        };
+        if let Some(holding) = row.get_mut(&key) {
+            holding.orders.retain(|order| order.id != order_id);
+            holding.update_qty_and_amount();
+            if holding.orders.is_empty() {
+                row.remove(&key);
+            }
+        }
     }
 
     pub fn extend(&mut self, other: &mut PriceColumns) {
@@ -0,0 +1,90 @@
+use super::{get_timestamp_ms, next_order_id, OrderIds};
+use std::fmt::Debug;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// source of the current time in ms, so tests, replays and backtests control time
+pub trait Clock: Send + Sync + Debug {
+    fn now_ms(&self) -> u128;
+
+    /// id for the next order created on this clock, process-wide unless the clock owns its ids
+    fn next_order_id(&self) -> u128 {
+        next_order_id()
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// wall clock time
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u128 {
+        get_timestamp_ms()
+    }
+}
+
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// time that only moves when it is advanced explicitly
+#[derive(Debug, Default)]
+pub struct SimulatedClock {
+    now: AtomicU64,
+    order_ids: OrderIds,
+}
+
+impl SimulatedClock {
+    pub fn new(start_ms: u64) -> Self {
+        Self {
+            now: AtomicU64::new(start_ms),
+            order_ids: OrderIds::default(),
+        }
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now.fetch_add(ms, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, ms: u64) {
+        self.now.store(ms, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now_ms(&self) -> u128 {
+        self.now.load(Ordering::SeqCst) as u128
+    }
+
+    fn next_order_id(&self) -> u128 {
+        self.order_ids.next()
+    }
+}
+
+/// follows the receive timestamps of the frames being replayed, never moving backwards, and
+/// numbers the orders of the replay from 1
+#[derive(Debug, Default)]
+pub struct ReplayClock {
+    now: AtomicU64,
+    order_ids: OrderIds,
+}
+
+impl ReplayClock {
+    pub fn observe(&self, received_at: u128) {
+        self.now.fetch_max(received_at as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ReplayClock {
+    fn now_ms(&self) -> u128 {
+        self.now.load(Ordering::SeqCst) as u128
+    }
+
+    fn next_order_id(&self) -> u128 {
+        self.order_ids.next()
+    }
+}
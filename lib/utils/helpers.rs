@@ -19,13 +19,10 @@ pub type ReaderStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
 pub fn add_each(table: &mut PriceColumns, order: &mut Order) {
     let Order {
         id,
-        is_arbitrage: _,
-        status: _,
         price,
         request,
         quantity,
-        filled_with: _,
-        remaining_qty: _,
+        ..
     } = order;
     let key = *price;
 
@@ -91,14 +88,19 @@ fn create_new_holding(
     }
 }
 
+use super::{is_recording, record_frame};
 use crate::trading::MininalOrder;
+use std::future::Future;
 use std::{
     collections::VecDeque,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::Mutex;
-/// Generates a  unique timestamp  to use an id
+/// current timestamp in ms
 pub fn get_timestamp_ms() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -106,76 +108,157 @@ pub fn get_timestamp_ms() -> u128 {
         .as_millis()
 }
 
+static NEXT_ORDER_ID: AtomicU64 = AtomicU64::new(1);
+/// Generates a process-wide unique, monotonically increasing order id.
+/// Unlike timestamps, ids never collide for orders created within the same millisecond
+pub fn next_order_id() -> u128 {
+    NEXT_ORDER_ID.fetch_add(1, Ordering::Relaxed) as u128
+}
+
+/// Order ids handed out from 1, owned by the clocks of simulations and replays so every run
+/// over the same input numbers its orders the same
+#[derive(Debug)]
+pub struct OrderIds(AtomicU64);
+
+impl Default for OrderIds {
+    fn default() -> Self {
+        Self(AtomicU64::new(1))
+    }
+}
+
+impl OrderIds {
+    pub fn next(&self) -> u128 {
+        self.0.fetch_add(1, Ordering::Relaxed) as u128
+    }
+}
+
 use std::collections::HashMap;
-/// updates the quantity required to complete a trade at price level
+/// updates the quantity required to complete a trade at price level,
+/// returns the matched qty and the (id, qty) consumed from each resting order
 pub fn match_at_price_level(
     current_holding: &mut CurrentHoldingPerPrice,
     incoming_order_qty: &mut i32,
-) -> (i32, VecDeque<u128>) {
+) -> (i32, VecDeque<(u128, i32)>) {
     let mut done_qty = 0;
 
-    let mut orders_to_remove = VecDeque::new();
+    let mut consumed = VecDeque::new();
 
     current_holding.orders.iter_mut().for_each(|order| {
-        if order.qty <= *incoming_order_qty {
-            *incoming_order_qty -= order.qty;
-            done_qty += order.qty;
-            order.qty = 0;
-            orders_to_remove.push_back(order.id);
-        } else {
-            order.qty -= *incoming_order_qty;
-            done_qty += *incoming_order_qty;
-
-            *incoming_order_qty = 0
+        let qty = order.qty.min(*incoming_order_qty);
+        if qty > 0 {
+            *incoming_order_qty -= qty;
+            done_qty += qty;
+            order.qty -= qty;
+            consumed.push_back((order.id, qty));
         }
     });
 
     current_holding.orders.retain(|x| x.qty > 0);
     current_holding.update_qty_and_amount();
 
-    (done_qty, orders_to_remove)
+    (done_qty, consumed)
+}
+
+/// where raw frames come from, a live websocket or a recording
+pub trait FrameSource {
+    /// the next text frame, None once the source is exhausted or broken
+    fn next_frame(&mut self) -> impl Future<Output = Option<String>> + Send;
+}
+
+impl FrameSource for ReaderStream {
+    async fn next_frame(&mut self) -> Option<String> {
+        loop {
+            match self.next().await? {
+                Ok(Message::Text(message)) => return Some(message),
+                Ok(_) => continue, // pings and other control frames
+                Err(_) => return None,
+            }
+        }
+    }
 }
 
 pub async fn fetch_bids_and_asks<T: Returnable + DeserializeOwned + std::fmt::Debug>(
-    reader: Arc<Mutex<ReaderStream>>,
+    reader: Arc<Mutex<impl FrameSource>>,
     order_book: Arc<Mutex<OrderBook<'_>>>,
 ) -> anyhow::Result<()> {
-    if let Some(Ok(Message::Text(message))) = reader.lock().await.next().await {
-        let json: T = serde_json::from_str(&message)?;
-
-        if let (Some((asks, bids)), Some(instrument_name)) =
-            (json.asks_bids_pair(), json.instrument_name())
-        {
-            let instra = instrument_name.clone();
-            order_book.lock().await.add_asset(instra);
-            let ask_pairs = asks.iter();
-            let bids_pairs = bids.iter();
-            let mut bids_count = 0;
-            let mut ask_count = 0;
-            for (asking_price, quantity) in ask_pairs {
-                let ask_order = Order::new(*asking_price, *quantity as i32, TradeRequest::Ask);
-
-                order_book
-                    .lock()
-                    .await
-                    .add_order(ask_order, &instrument_name);
-                ask_count += 1;
-            }
+    if let Some(message) = reader.lock().await.next_frame().await {
+        // record before decoding, frames we fail to decode are the interesting ones
+        if is_recording() {
+            let exchange = order_book.lock().await.get_name();
+            record_frame(&exchange, &message)?;
+        }
+        let received_at = order_book.lock().await.now();
+        apply_frame::<T>(&message, received_at, order_book).await?;
+    }
 
-            for (biding_price, bid_quantity) in bids_pairs {
-                let bid_order = Order::new(*biding_price, *bid_quantity as i32, TradeRequest::Bid);
-                order_book
-                    .lock()
-                    .await
-                    .add_order(bid_order, &instrument_name);
-                bids_count += 1;
-            }
+    Ok(())
+}
 
-            println!(
-                "+{ask_count} asks and + {bids_count} from {}",
-                order_book.lock().await.exchange
-            );
+/// decodes a frame and applies it to the book, `received_at` (ms) stamps what the frame adds
+pub async fn apply_frame<T: Returnable + DeserializeOwned + std::fmt::Debug>(
+    message: &str,
+    received_at: u128,
+    order_book: Arc<Mutex<OrderBook<'_>>>,
+) -> anyhow::Result<()> {
+    let json: T = serde_json::from_str(message)?;
+
+    let price_updates = json.price_updates();
+    if !price_updates.is_empty() {
+        let mut order_book = order_book.lock().await;
+        price_updates
+            .into_iter()
+            .for_each(|update| order_book.apply_price_update(update));
+    }
+
+    let trades = json.trades();
+    if !trades.is_empty() {
+        let mut order_book = order_book.lock().await;
+        trades
+            .into_iter()
+            .for_each(|trade| order_book.record_trade(trade));
+    }
+
+    let summaries = json.option_summaries();
+    if !summaries.is_empty() {
+        let mut order_book = order_book.lock().await;
+        summaries
+            .into_iter()
+            .for_each(|summary| order_book.apply_option_summary(summary));
+    }
+
+    if let (Some((asks, bids)), Some(instrument_name)) =
+        (json.asks_bids_pair(), json.instrument_name())
+    {
+        let instra = instrument_name.clone();
+        order_book.lock().await.add_asset(instra);
+        let ask_pairs = asks.iter();
+        let bids_pairs = bids.iter();
+        let mut bids_count = 0;
+        let mut ask_count = 0;
+        for (asking_price, quantity) in ask_pairs {
+            let mut order_book = order_book.lock().await;
+            let ask_order =
+                order_book.new_order(*asking_price, *quantity as i32, TradeRequest::Ask);
+            order_book.insert_order(ask_order, &instrument_name);
+            ask_count += 1;
+        }
+
+        for (biding_price, bid_quantity) in bids_pairs {
+            let mut order_book = order_book.lock().await;
+            let bid_order =
+                order_book.new_order(*biding_price, *bid_quantity as i32, TradeRequest::Bid);
+            order_book.insert_order(bid_order, &instrument_name);
+            bids_count += 1;
         }
+        order_book
+            .lock()
+            .await
+            .record_midprice(&instrument_name, received_at);
+
+        println!(
+            "+{ask_count} asks and + {bids_count} from {}",
+            order_book.lock().await.exchange
+        );
     }
 
     Ok(())
@@ -1,3 +1,9 @@
 
 mod helpers;
 pub use helpers::*;
+mod recorder;
+pub use recorder::*;
+mod replay;
+pub use replay::*;
+mod clock;
+pub use clock::*;
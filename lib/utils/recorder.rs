@@ -0,0 +1,229 @@
+use super::get_timestamp_ms;
+use anyhow::Context;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Lines, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use zstd::stream::{read::Decoder, write::Encoder};
+
+const COMPRESSION_LEVEL: i32 = 3;
+const FLUSH_EVERY: u64 = 100; // frames, bounds what a crash can lose
+
+/// a raw websocket frame as it came off the wire
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RecordedFrame {
+    pub received_at: u128, // local receive time in ms
+    pub exchange: String,
+    pub frame: String,
+}
+
+/// Writes frames as zstd compressed JSON lines, one file per session. The compressed stream
+/// is only complete once `finish` ran, dropping the recorder finishes it as a last resort
+pub struct FrameRecorder {
+    writer: Option<Encoder<'static, BufWriter<File>>>,
+    recorded: u64,
+}
+
+impl FrameRecorder {
+    /// starts a session in a new file, an existing recording is never overwritten
+    pub fn create<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .with_context(|| format!("creating recording {}", path.display()))?;
+        let writer = Encoder::new(BufWriter::new(file), COMPRESSION_LEVEL)?;
+        Ok(Self {
+            writer: Some(writer),
+            recorded: 0,
+        })
+    }
+
+    /// ends the compressed stream and flushes it to disk
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.finish_writer()
+    }
+
+    fn finish_writer(&mut self) -> anyhow::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finish()?.into_inner()?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, exchange: &str, frame: &str) -> anyhow::Result<()> {
+        self.record_frame(&RecordedFrame {
+            received_at: get_timestamp_ms(),
+            exchange: exchange.to_owned(),
+            frame: frame.to_owned(),
+        })
+    }
+
+    pub fn record_frame(&mut self, frame: &RecordedFrame) -> anyhow::Result<()> {
+        let writer = self.writer.as_mut().context("recording already finished")?;
+        serde_json::to_writer(&mut *writer, frame)?;
+        writer.write_all(b"\n")?;
+        self.recorded += 1;
+        if self.recorded.is_multiple_of(FLUSH_EVERY) {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+}
+
+impl Drop for FrameRecorder {
+    fn drop(&mut self) {
+        let _ = self.finish_writer();
+    }
+}
+
+/// `frames.jsonl.zst` becomes `frames-<started_at>.jsonl.zst`, with a counter when a session
+/// started within the same millisecond
+fn session_path(path: &Path, started_at: u128) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, extension) = match name.find('.') {
+        Some(dot) => name.split_at(dot),
+        None => (name.as_str(), ""),
+    };
+    let mut attempt = 0;
+    loop {
+        let suffix = match attempt {
+            0 => format!("{started_at}"),
+            _ => format!("{started_at}-{attempt}"),
+        };
+        let candidate = path.with_file_name(format!("{stem}-{suffix}{extension}"));
+        if !candidate.exists() {
+            return candidate;
+        }
+        attempt += 1;
+    }
+}
+
+/// reads the frames of a recording back in the order they were written
+pub struct RecordingReader {
+    lines: Lines<BufReader<Decoder<'static, BufReader<File>>>>,
+}
+
+impl RecordingReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("opening recording {}", path.display()))?;
+        Ok(Self {
+            lines: BufReader::new(Decoder::new(file)?).lines(),
+        })
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = anyhow::Result<RecordedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.lines.next()?;
+        Some(
+            line.map_err(anyhow::Error::from)
+                .and_then(|line| Ok(serde_json::from_str(&line)?)),
+        )
+    }
+}
+
+/// For global usage, every connection records into the same file once recording is started
+lazy_static! {
+    pub static ref RECORDER: Mutex<Option<FrameRecorder>> = Mutex::new(None);
+}
+
+/// Starts a recording session in its own file next to `path`, returning the file used.
+/// A session already running is finished first
+pub fn start_recording<P: AsRef<Path>>(path: P) -> anyhow::Result<PathBuf> {
+    stop_recording()?;
+    let path = session_path(path.as_ref(), get_timestamp_ms());
+    let recorder = FrameRecorder::create(&path)?;
+    *RECORDER.lock().unwrap() = Some(recorder);
+    Ok(path)
+}
+
+/// stops recording, finishing the compressed stream
+pub fn stop_recording() -> anyhow::Result<()> {
+    let recorder = RECORDER.lock().unwrap().take();
+    match recorder {
+        Some(recorder) => recorder.finish(),
+        None => Ok(()),
+    }
+}
+
+pub fn is_recording() -> bool {
+    RECORDER.lock().unwrap().is_some()
+}
+
+/// records a frame if recording has been started
+pub fn record_frame(exchange: &str, frame: &str) -> anyhow::Result<()> {
+    match RECORDER.lock().unwrap().as_mut() {
+        Some(recorder) => recorder.record(exchange, frame),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn recordings_are_read_back_in_order() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(format!("recording-{}.jsonl.zst", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut recorder = FrameRecorder::create(&path)?;
+    recorder.record("deribit", r#"{"params":{}}"#)?;
+    recorder.record("okex", r#"{"arg":{}}"#)?;
+    recorder.record("deribit", "not json, still kept")?;
+    recorder.finish()?;
+    // sessions never write into an earlier recording
+    assert!(FrameRecorder::create(&path).is_err());
+
+    let frames = RecordingReader::open(&path)?.collect::<anyhow::Result<Vec<_>>>()?;
+    std::fs::remove_file(&path)?;
+    let venues: Vec<_> = frames.iter().map(|f| f.exchange.as_str()).collect();
+    assert_eq!(venues, vec!["deribit", "okex", "deribit"]);
+    assert_eq!(frames[2].frame, "not json, still kept");
+    assert!(frames[0].received_at <= frames[2].received_at);
+    Ok(())
+}
+
+#[test]
+fn stopped_sessions_are_complete_and_separate() -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("sessions-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("frames.jsonl.zst");
+
+    let first = start_recording(&path)?;
+    record_frame("deribit", "first")?;
+    let second = start_recording(&path)?;
+    record_frame("okex", "second")?;
+    stop_recording()?;
+    assert!(!is_recording());
+    assert_ne!(first, second);
+    assert!(first
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .starts_with("frames-"));
+
+    // both files decode to the end, so their streams were finished
+    let read = |path: &Path| -> anyhow::Result<Vec<String>> {
+        let frames = RecordingReader::open(path)?.collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(frames.into_iter().map(|frame| frame.frame).collect())
+    };
+    let (first, second) = (read(&first)?, read(&second)?);
+    std::fs::remove_dir_all(&dir)?;
+    assert_eq!(first, vec!["first"]);
+    assert_eq!(second, vec!["second"]);
+    Ok(())
+}
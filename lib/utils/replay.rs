@@ -0,0 +1,329 @@
+use super::{apply_frame, FrameSource, RecordedFrame, RecordingReader, ReplayClock};
+use crate::exchanges::{DeribitResponse, OkexResponse};
+use crate::trading::{ConsolidatedBook, Instrument, OrderBook};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// how fast recorded frames are played back
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ReplaySpeed {
+    Original,
+    Accelerated(f64), // times faster than recorded
+    AsFastAsPossible,
+}
+
+impl ReplaySpeed {
+    /// `1`, `10` (times faster) or `max`
+    pub fn from_given_str(input_str: &str) -> Option<Self> {
+        match input_str {
+            "max" => Some(ReplaySpeed::AsFastAsPossible),
+            _ => match input_str.parse::<f64>().ok()? {
+                1.0 => Some(ReplaySpeed::Original),
+                factor if factor > 0.0 => Some(ReplaySpeed::Accelerated(factor)),
+                _ => None,
+            },
+        }
+    }
+
+    fn factor(&self) -> Option<f64> {
+        match self {
+            ReplaySpeed::Original => Some(1.0),
+            ReplaySpeed::Accelerated(factor) => Some(*factor),
+            ReplaySpeed::AsFastAsPossible => None,
+        }
+    }
+}
+
+type Frames = Box<dyn Iterator<Item = anyhow::Result<RecordedFrame>> + Send>;
+
+/// Recorded frames paced by their receive timestamps. As a `FrameSource` it stands in for the
+/// websocket of one venue (frames of other venues are skipped)
+pub struct ReplaySource {
+    frames: Frames,
+    speed: ReplaySpeed,
+    exchange: Option<String>,
+    started: Option<(u128, Instant)>, // first receive timestamp and when we played it
+    clock: Option<Arc<ReplayClock>>,
+    error: Option<anyhow::Error>, // why the recording could not be read to the end
+}
+
+impl ReplaySource {
+    pub fn new(frames: Frames, speed: ReplaySpeed) -> Self {
+        Self {
+            frames,
+            speed,
+            exchange: None,
+            started: None,
+            clock: None,
+            error: None,
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, speed: ReplaySpeed) -> anyhow::Result<Self> {
+        Ok(Self::new(Box::new(RecordingReader::open(path)?), speed))
+    }
+
+    pub fn from_frames(frames: Vec<RecordedFrame>, speed: ReplaySpeed) -> Self {
+        Self::new(Box::new(frames.into_iter().map(Ok)), speed)
+    }
+
+    /// only plays the frames of one venue
+    pub fn for_exchange(mut self, exchange: &str) -> Self {
+        self.exchange = Some(exchange.to_owned());
+        self
+    }
+
+    /// moves the clock to the receive time of every frame played, books stamped by this
+    /// clock then see the recorded times instead of the time of the replay
+    pub fn with_clock(mut self, clock: Arc<ReplayClock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// The next frame once it is due. A broken recording (I/O, decompression or a line that
+    /// isn't a frame) ends the replay, see `error`
+    pub async fn next_recorded(&mut self) -> Option<RecordedFrame> {
+        if self.error.is_some() {
+            return None;
+        }
+        let frame = loop {
+            let frame = match self.frames.next()? {
+                Ok(frame) => frame,
+                Err(err) => {
+                    self.error = Some(err);
+                    return None;
+                }
+            };
+            match &self.exchange {
+                Some(exchange) if *exchange != frame.exchange => continue,
+                _ => break frame,
+            }
+        };
+        if let Some(factor) = self.speed.factor() {
+            let (first, started) = *self
+                .started
+                .get_or_insert((frame.received_at, Instant::now()));
+            let elapsed = frame.received_at.saturating_sub(first) as f64 / factor;
+            sleep_until(started + Duration::from_secs_f64(elapsed / 1000.0)).await;
+        }
+        if let Some(clock) = &self.clock {
+            clock.observe(frame.received_at);
+        }
+        Some(frame)
+    }
+
+    /// what ended the replay before the end of the recording, if anything
+    pub fn error(&self) -> Option<&anyhow::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl FrameSource for ReplaySource {
+    async fn next_frame(&mut self) -> Option<String> {
+        self.next_recorded().await.map(|frame| frame.frame)
+    }
+}
+
+/// decodes a recorded frame with the decoder of its venue and applies it to the book
+pub async fn apply_recorded_frame(
+    frame: &RecordedFrame,
+    order_book: Arc<Mutex<OrderBook<'_>>>,
+) -> anyhow::Result<()> {
+    match frame.exchange.as_str() {
+        "deribit" => {
+            apply_frame::<DeribitResponse>(&frame.frame, frame.received_at, order_book).await
+        }
+        "okex" => apply_frame::<OkexResponse>(&frame.frame, frame.received_at, order_book).await,
+        other => Err(anyhow::anyhow!("no decoder for exchange {other}")),
+    }
+}
+
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct ReplayStats {
+    pub frames: u64,
+    pub skipped: u64,               // venues without a book
+    pub decode_errors: u64,         // frames that failed to decode, as they would have live
+    pub match_errors: u64,          // frames after which matching failed
+    pub read_error: Option<String>, // why the recording could not be read to the end
+}
+
+impl ReplayStats {
+    /// notes why `source` stopped early, if it did
+    pub fn record_source(&mut self, source: &ReplaySource) {
+        self.read_error = source.error().map(|err| format!("{err:#}"));
+    }
+}
+
+/// Plays a whole session in recorded order through the books of `consolidated`, matching each
+/// venue against the others after every frame like the live loop does. Frames are handled one
+/// at a time, so every run over the same recording ends in the same books
+pub async fn replay_session(
+    source: &mut ReplaySource,
+    consolidated: &ConsolidatedBook<'_>,
+    instruments: &[Instrument],
+) -> ReplayStats {
+    let mut stats = ReplayStats::default();
+    while let Some(frame) = source.next_recorded().await {
+        stats.frames += 1;
+        let Some(book) = consolidated.book(&frame.exchange).await else {
+            stats.skipped += 1;
+            continue;
+        };
+        if apply_recorded_frame(&frame, book).await.is_err() {
+            stats.decode_errors += 1;
+            continue;
+        }
+        for instrument in instruments {
+            if consolidated
+                .match_venue(&frame.exchange, instrument)
+                .await
+                .is_err()
+            {
+                stats.match_errors += 1;
+                break;
+            }
+        }
+    }
+    stats.record_source(source);
+    stats
+}
+
+#[tokio::test]
+async fn replays_end_in_the_same_books() -> anyhow::Result<()> {
+    use crate::exchanges::ExchangeType;
+    let instrument =
+        Instrument::from_exchange_string("BTC-10MAY24-66000-C", ExchangeType::Delibris)?;
+    let frame = |received_at: u128, exchange: &str, frame: &str| RecordedFrame {
+        received_at,
+        exchange: exchange.to_owned(),
+        frame: frame.to_owned(),
+    };
+    let frames = vec![
+        frame(
+            1_000,
+            "deribit",
+            r#"{"params":{"channel":"book.BTC-10MAY24-66000-C.none.20.100ms","data":{"instrument_name":"BTC-10MAY24-66000-C","bids":[[0.0130,5.0]],"asks":[[0.0150,5.0]]}}}"#,
+        ),
+        frame(
+            1_050,
+            "okex",
+            r#"{"arg":{"channel":"books","instId":"BTC-USD-240510-66000-C"},"data":[{"asks":[["0.012","300","0","1"]],"bids":[["0.010","4","0","1"]],"ts":"1715328000000"}]}"#,
+        ),
+        frame(1_060, "okex", "{truncated"),
+        frame(1_100, "bitmex", "{}"),
+        frame(
+            1_200,
+            "deribit",
+            r#"{"params":{"channel":"deribit_price_index.btc_usd","data":{"timestamp":1715328000000,"price":62995.42,"index_name":"btc_usd"}}}"#,
+        ),
+    ];
+
+    let mut snapshots = Vec::new();
+    for _ in 0..2 {
+        let clock = Arc::new(ReplayClock::default());
+        let deribit = Arc::new(Mutex::new(OrderBook::with_clock("deribit", clock.clone())));
+        let okex = Arc::new(Mutex::new(OrderBook::with_clock("okex", clock.clone())));
+        let consolidated = ConsolidatedBook::new(vec![deribit.clone(), okex.clone()]);
+        let mut source = ReplaySource::from_frames(frames.clone(), ReplaySpeed::AsFastAsPossible)
+            .with_clock(clock);
+        let stats = replay_session(
+            &mut source,
+            &consolidated,
+            std::slice::from_ref(&instrument),
+        )
+        .await;
+        assert_eq!(
+            (stats.frames, stats.skipped, stats.decode_errors),
+            (5, 1, 1)
+        );
+        assert_eq!(stats.read_error, None);
+
+        let mut snapshot = Vec::new();
+        for book in [deribit, okex] {
+            let book = book.lock().await;
+            let table = book.table_for(&instrument).unwrap();
+            let levels = |row: &crate::trading::PriceRow| -> Vec<(f32, i32)> {
+                row.iter()
+                    .map(|(price, holding)| (price.into_inner(), holding.total_quantity))
+                    .collect()
+            };
+            let fills: Vec<_> = table
+                .history
+                .iter()
+                .chain(table.orders.lock().unwrap().iter())
+                .map(|order| {
+                    let matched_at: Vec<u128> = order
+                        .filled_with
+                        .iter()
+                        .map(|fill| fill.matched_at)
+                        .collect();
+                    (
+                        order.id,
+                        order.price,
+                        order.filled_qty,
+                        order.created_at,
+                        matched_at,
+                    )
+                })
+                .collect();
+            snapshot.push((
+                levels(&table.bids),
+                levels(&table.asks),
+                fills,
+                book.index_price(),
+            ));
+        }
+        snapshots.push(snapshot);
+    }
+    assert_eq!(snapshots[0], snapshots[1]);
+    // the okex ask, 3 BTC in okex contracts, was crossed by the deribit bid
+    assert_eq!(snapshots[0][0].0, vec![(0.0130, 2)]);
+    assert!(snapshots[0][1].1.is_empty());
+    // numbered by the replay and stamped with the recorded times, the okex frame is the one
+    // that crossed the second order of the first frame
+    let (_, _, deribit_orders, _) = &snapshots[0][0];
+    assert!(deribit_orders.contains(&(2, 0.0130, 3, 1_000, vec![1_050])));
+    Ok(())
+}
+
+#[tokio::test]
+async fn replay_keeps_the_recorded_pace() {
+    let frames = (0..3)
+        .map(|i| RecordedFrame {
+            received_at: 1_000 + i * 100,
+            exchange: "okex".to_owned(),
+            frame: String::new(),
+        })
+        .collect();
+    let mut source = ReplaySource::from_frames(frames, ReplaySpeed::Accelerated(4.0));
+    let started = Instant::now();
+    while source.next_frame().await.is_some() {}
+    // 200ms recorded, played 4x faster
+    assert!(started.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn truncated_recordings_report_why_they_ended() -> anyhow::Result<()> {
+    use super::FrameRecorder;
+    let path = std::env::temp_dir().join(format!("truncated-{}.jsonl.zst", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut recorder = FrameRecorder::create(&path)?;
+    for i in 0..2_000 {
+        // frames that don't compress well, so the file is long enough to cut in half
+        recorder.record("okex", &format!("{}", i * 7_919 % 104_729))?;
+    }
+    recorder.finish()?;
+    let bytes = std::fs::read(&path)?;
+    std::fs::write(&path, &bytes[..bytes.len() / 2])?;
+
+    let okex = Arc::new(Mutex::new(OrderBook::new("okex")));
+    let consolidated = ConsolidatedBook::new(vec![okex]);
+    let mut source = ReplaySource::open(&path, ReplaySpeed::AsFastAsPossible)?;
+    let stats = replay_session(&mut source, &consolidated, &[]).await;
+    std::fs::remove_file(&path)?;
+    assert!(stats.frames < 2_000);
+    assert!(stats.read_error.is_some());
+    Ok(())
+}
@@ -1,46 +1,212 @@
 use lib::{
-    exchanges::{DeribitResponse, OkexResponse},
-    trading::{Instrument, OrderBook},
-    utils::{create_connection, fetch_bids_and_asks},
+    analytics::{check_exchange_models, ModelTolerance},
+    strategy::{forward_frames, StrategyRuntime},
+    trading::{
+        ArbitrageDetector, ConsolidatedBook, ControlCommand, Instrument, KillSwitch,
+        KillSwitchConfig, OrderBook, SharedKillSwitch,
+    },
+    utils::{
+        create_connection, replay_session, start_recording, stop_recording, system_clock,
+        ReplayClock, ReplaySource, ReplaySpeed, SharedClock,
+    },
 };
-use std::sync::Arc;
-use tokio::{select, sync::Mutex};
+use std::{collections::HashSet, hash::Hash, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    select,
+    sync::{mpsc, Mutex},
+};
+
+/// frames waiting for the runtime before the feeds wait for it
+const FRAME_BUFFER: usize = 1024;
+/// how often the runtime fires timers and checks the feeds for the kill switch
+const KILL_SWITCH_TICK: Duration = Duration::from_millis(250);
+/// how often the venues are matched against each other and changes are reported
+const MONITOR_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the live loop reported last, so that only changes are printed instead of every
+/// opportunity and disagreement on every frame
+struct Reported<K> {
+    active: HashSet<K>,
+}
+
+impl<K: Eq + Hash + Clone> Reported<K> {
+    fn new() -> Self {
+        Self {
+            active: HashSet::new(),
+        }
+    }
+
+    /// the items that were not active before and the keys of those that went away
+    fn update<'a, T>(&mut self, items: &'a [T], key: impl Fn(&T) -> K) -> (Vec<&'a T>, Vec<K>) {
+        let current: HashSet<K> = items.iter().map(&key).collect();
+        let new = items
+            .iter()
+            .filter(|item| !self.active.contains(&key(item)))
+            .collect();
+        let gone = self.active.difference(&current).cloned().collect();
+        self.active = current;
+        (new, gone)
+    }
+}
+
+/// reads operator commands (see `ControlCommand`) from stdin
+async fn control_console(kill_switch: SharedKillSwitch) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match ControlCommand::from_given_str(&line) {
+            Some(command) => println!("{}", kill_switch.lock().unwrap().apply_command(command)),
+            None => println!(
+                "unknown command {line}, try kill [instrument], resume [instrument] or status"
+            ),
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     console_subscriber::init();
-    let deribit_order_book = Arc::new(Mutex::new(OrderBook::new("deribit")));
-    let okex_order_book = Arc::new(Mutex::new(OrderBook::new("okex"))); // default instrument here is same as okex_one ()
+    // `--record <path>` captures every raw frame into a new file per session next to <path>,
+    // see `RecordingReader` to read it back. Ctrl-C finishes the recording before exiting.
+    // `--replay <path> [--speed 1|10|max]` plays a recording through the books instead of going live.
+    // `--max-data-age <ms>` trips the kill switch when a venue goes quiet for longer
+    let mut args = std::env::args().skip(1);
+    let mut replay = None;
+    let mut speed = ReplaySpeed::Original;
+    let mut kill_switch_config = KillSwitchConfig::default();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("{arg} needs a value"))
+        };
+        match arg.as_str() {
+            "--record" => {
+                let path = start_recording(value()?)?;
+                println!("recording raw frames to {}", path.display());
+            }
+            "--replay" => replay = Some(value()?),
+            "--speed" => {
+                let given = value()?;
+                speed = ReplaySpeed::from_given_str(&given)
+                    .ok_or_else(|| anyhow::anyhow!("unsupported replay speed {given}"))?;
+            }
+            "--max-data-age" => kill_switch_config.max_data_age_ms = Some(value()?.parse()?),
+            _ => {}
+        }
+    }
+    // replays run on the recorded time
+    let replay_clock = Arc::new(ReplayClock::default());
+    let clock: SharedClock = if replay.is_some() {
+        replay_clock.clone()
+    } else {
+        system_clock()
+    };
+    let deribit_order_book = Arc::new(Mutex::new(OrderBook::with_clock("deribit", clock.clone())));
+    let okex_order_book = Arc::new(Mutex::new(OrderBook::with_clock("okex", clock.clone()))); // default instrument here is same as okex_one ()
+    let consolidated =
+        ConsolidatedBook::new(vec![deribit_order_book.clone(), okex_order_book.clone()]);
+    // both venues list the same option, matching is done on the canonical instrument
+    let instrument = Instrument::from_exchange_string(
+        "BTC-10MAY24-66000-C",
+        lib::exchanges::ExchangeType::Delibris,
+    )?;
+    let mut arbitrage_detector = ArbitrageDetector::default().with_clock(clock.clone());
+    let kill_switch = KillSwitch::new(kill_switch_config, clock.clone()).shared();
+    let model_tolerance = ModelTolerance::default();
+    let mut reported_opportunities = Reported::new();
+    let mut reported_disagreements = Reported::new();
+    if let Some(path) = replay {
+        let mut source = ReplaySource::open(&path, speed)?.with_clock(replay_clock);
+        let stats = replay_session(&mut source, &consolidated, &[instrument]).await;
+        println!("replayed {path}: {stats:#?}");
+        return stop_recording();
+    }
     let deribit_reader = create_connection("deribit", None).await?;
     let okex_reader = create_connection("okex", Some("BTC-USD-240510-66000-C")).await?;
+    tokio::spawn(control_console(kill_switch.clone()));
+    // both feeds go through the strategy runtime, which applies them to the books and keeps
+    // the kill switch informed, while this loop matches the venues and reports what changed.
+    // The runtime is a task of its own: it holds book locks across awaits, so polling it from
+    // the loop that locks the same books could leave it parked on a guard the loop waits for
+    let (sender, frames) = mpsc::channel(FRAME_BUFFER);
+    tokio::spawn(forward_frames(
+        "deribit",
+        deribit_reader,
+        clock.clone(),
+        sender.clone(),
+    ));
+    tokio::spawn(forward_frames("okex", okex_reader, clock.clone(), sender));
+    let mut runtime = StrategyRuntime::new(
+        ConsolidatedBook::new(vec![deribit_order_book.clone(), okex_order_book.clone()]),
+        clock,
+    )
+    .with_kill_switch(kill_switch.clone());
+    let mut run = tokio::spawn(async move { runtime.run(frames, KILL_SWITCH_TICK).await });
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    let mut monitor = tokio::time::interval(MONITOR_INTERVAL);
+    let mut printed_audit = 0;
     loop {
-        let (deribit_reader_clone, okex_reader_clone) =
-            (deribit_reader.clone(), okex_reader.clone());
-        let (deribit_order_book_clone, okex_order_book_clone) =
-            (deribit_order_book.clone(), okex_order_book.clone());
-        let task = fetch_bids_and_asks::<OkexResponse>(okex_reader_clone, okex_order_book_clone);
-
-        let task_o =
-            fetch_bids_and_asks::<DeribitResponse>(deribit_reader_clone, deribit_order_book_clone); // on we add
-
-        // fetch data simultaneously also order match accross whenever a fetch in completed
         select! {
-             _ = task_o => {
-                 let mut deribit_ob = deribit_order_book.lock().await;
-                 let mut instrument = Instrument::from_exchange_string("BTC-10MAY24-66000-C",lib::exchanges::ExchangeType::Delibris)?;
-                  instrument.asset.push_str("-USD");
-
-                 deribit_ob.match_orders(&instrument,okex_order_book.clone()).await
-
-             }
-
-              _ =  task => {
-
-                let mut okex_ob = okex_order_book.lock().await;
-                   let instrument = Instrument::from_exchange_string("BTC-USD-240510-66000-C",lib::exchanges::ExchangeType::Okex)?;
-                  okex_ob.match_orders(&instrument.to_singular_asset(),deribit_order_book.clone()).await
-             }
-
+            stats = &mut run => {
+                match stats {
+                    Ok(stats) => println!("feeds closed: {stats:#?}"),
+                    Err(err) => println!("the strategy runtime stopped: {err}"),
+                }
+                break;
+            }
+            _ = &mut shutdown => break,
+            _ = monitor.tick() => {}
+        }
+        // the kill switch only keeps its audit trail, the new entries are printed here
+        {
+            let switch = kill_switch.lock().unwrap();
+            for event in &switch.audit[printed_audit..] {
+                println!("audit {event:?}");
+            }
+            printed_audit = switch.audit.len();
+        }
+        // no matching while the kill switch is tripped
+        if kill_switch.lock().unwrap().blocked(&instrument).is_none() {
+            for exchange in ["deribit", "okex"] {
+                if let Err(err) = consolidated.match_venue(exchange, &instrument).await {
+                    println!("matching on {exchange} failed: {err:#}");
+                }
+            }
+        }
+        let index_price = deribit_order_book.lock().await.index_price();
+        let opportunities = arbitrage_detector.scan(&consolidated, index_price).await;
+        let (new, gone) = reported_opportunities.update(&opportunities, |o| {
+            (
+                o.instrument.clone(),
+                o.buy_exchange.clone(),
+                o.sell_exchange.clone(),
+            )
+        });
+        for opportunity in new {
+            println!("arbitrage detected {opportunity:?}");
+        }
+        for (instrument, buy, sell) in gone {
+            println!("arbitrage closed: buy {buy} sell {sell} {instrument:?}");
+        }
+        let mut disagreements = Vec::new();
+        for book in [&deribit_order_book, &okex_order_book] {
+            let book = book.lock().await;
+            disagreements.extend(check_exchange_models(&book, 0.0, &model_tolerance));
+        }
+        let (new, gone) = reported_disagreements.update(&disagreements, |d| {
+            (d.exchange.clone(), d.instrument.clone(), d.field)
+        });
+        for disagreement in new {
+            println!(
+                "model disagrees with {} {disagreement:?}",
+                disagreement.exchange
+            );
+        }
+        for (exchange, instrument, field) in gone {
+            println!("model agrees with {exchange} again on {field:?} of {instrument:?}");
         }
     }
+    run.abort();
+    stop_recording()
 }